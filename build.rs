@@ -0,0 +1,37 @@
+//! Computes an id for the VM build, which CDS archives record so that an archive is only mapped
+//! by the build that dumped it
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::{env, fs};
+
+fn collect_sources(dir: &Path, sources: &mut Vec<PathBuf>) {
+    for entry in fs::read_dir(dir).unwrap().flatten() {
+        let path = entry.path();
+        if entry.file_type().unwrap().is_dir() {
+            collect_sources(&path, sources);
+        } else if path.extension().is_some_and(|ext| ext == "rs") {
+            sources.push(path);
+        }
+    }
+}
+
+fn main() {
+    let mut sources = vec![PathBuf::from("build.rs"), PathBuf::from("Cargo.lock")];
+    collect_sources(Path::new("src"), &mut sources);
+    sources.sort();
+
+    let mut hasher = DefaultHasher::new();
+    for path in &sources {
+        path.hash(&mut hasher);
+        fs::read(path).unwrap().hash(&mut hasher);
+    }
+    // Layouts computed by the VM can differ between targets and compilers
+    env::var("TARGET").unwrap().hash(&mut hasher);
+    env::var("RUSTC").unwrap().hash(&mut hasher);
+    println!("cargo:rustc-env=LEPRD_BUILD_ID={:016x}", hasher.finish());
+    println!("cargo:rerun-if-changed=src");
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed=Cargo.lock");
+}
@@ -0,0 +1,742 @@
+//! Class Data Sharing
+//!
+//! `-Xshare:dump` loads and links a list of classes with the bootstrap loader and writes their
+//! metadata to an archive. Later runs map the archive into an empty method area at startup instead
+//! of finding and parsing those class files again. Only metadata is archived, not heap objects, so
+//! archived classes still run their static initializers.
+//!
+//! The archive is only used if it was dumped by the same build of the VM with the same class path.
+//! Builds are told apart by a hash of the VM's sources computed by the build script.
+
+use crate::class::{Class, Field, FieldBacking, InitState, Method};
+use crate::class_file::attributes::{AttributeInfo, CodeAttribute, ExceptionTableEntry};
+use crate::class_file::descriptors::{FieldDescriptor, MethodDescriptor};
+use crate::class_loader::{self, method_area, ClassId, ClassLoader, FieldId, MethodArea, MethodId};
+use crate::class_path::{class_path, ClassSource};
+use crate::heap::{Array, Object, ValueKind};
+use crate::jvm::intrinsics;
+use crate::runtime_constant_pool::RuntimeConstantPool;
+use crate::symbol::Symbol;
+use crate::value::Value;
+use crate::CONFIG;
+use id_arena::{ArenaBehavior, DefaultArenaBehavior, Id};
+use std::collections::HashMap;
+use std::mem;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::{fs, io};
+
+const MAGIC: &[u8; 8] = b"leprdCDS";
+/// Bumped whenever the archive format or the layout of archived metadata changes
+const FORMAT_VERSION: u32 = 1;
+
+static SHARING_ENABLED: AtomicBool = AtomicBool::new(false);
+static DUMPING: AtomicBool = AtomicBool::new(false);
+
+/// Whether classes were mapped from an archive at startup
+pub fn is_sharing_enabled() -> bool {
+    SHARING_ENABLED.load(Ordering::Relaxed)
+}
+
+pub fn is_dumping() -> bool {
+    DUMPING.load(Ordering::Relaxed)
+}
+
+/// Classes dumped when no class list is given. Everything they depend on is archived too.
+pub const DEFAULT_CLASS_LIST: &[&str] = &[
+    "java/lang/Object",
+    "java/lang/String",
+    "java/lang/System",
+    "java/lang/Class",
+    "java/lang/Thread",
+    "java/lang/ThreadGroup",
+    "java/lang/ref/Finalizer",
+    "java/lang/Integer",
+    "java/lang/Long",
+    "java/lang/Character",
+    "java/lang/StringBuilder",
+    "java/lang/Math",
+    "java/util/HashMap",
+    "java/util/ArrayList",
+    "java/util/Properties",
+    "jdk/internal/misc/Unsafe",
+    "jdk/internal/misc/VM",
+];
+
+/// Load and link the classes in `class_list` and everything they depend on, then write all
+/// bootstrap classes to an archive. Returns the number of classes archived.
+pub fn dump(path: &Path, class_list: &[String]) -> io::Result<usize> {
+    DUMPING.store(true, Ordering::Relaxed);
+    if let Err(err) = class_loader::preload_classes() {
+        eprintln!("Preload warning: {}", err);
+    }
+    for name in class_list {
+        if let Err(err) = class_loader::resolve_class(ClassLoader::Bootstrap, Symbol::intern(name))
+        {
+            eprintln!("Preload warning: cannot load {}: {}", name, err);
+        }
+    }
+    let mut ma = method_area();
+    let loaded: Vec<ClassId> = ma.live_classes().map(|(id, _)| id).collect();
+    for &id in &loaded {
+        // Classes that fail to link are archived as loaded, and fail again when they are used
+        if let Err(err) = ma.link_class(id) {
+            eprintln!(
+                "Preload warning: cannot link {}: {}",
+                ma.classes[id].name, err
+            );
+        }
+    }
+    let data = write_archive(&ma, &class_path().fingerprint());
+    fs::write(path, data)?;
+    Ok(loaded.len())
+}
+
+/// Map an archive at startup, before any classes are loaded
+pub fn map(path: &Path) -> io::Result<()> {
+    let data = fs::read(path)?;
+    read_archive(&mut method_area(), &data, || class_path().fingerprint())?;
+    SHARING_ENABLED.store(true, Ordering::Relaxed);
+    Ok(())
+}
+
+fn invalid(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+/// Identifies the VM build, since archived layouts depend on it, along with the sizes that
+/// instance layouts are computed from
+fn vm_identity() -> String {
+    format!(
+        "{} {} build={} compressed_refs={} object={} array={} reference={}",
+        env!("CARGO_PKG_NAME"),
+        env!("CARGO_PKG_VERSION"),
+        env!("LEPRD_BUILD_ID"),
+        CONFIG.compressed_refs,
+        mem::size_of::<Object>(),
+        mem::size_of::<Array>(),
+        ValueKind::Object.layout().size(),
+    )
+}
+
+#[derive(Default)]
+pub struct ArchiveWriter {
+    buf: Vec<u8>,
+}
+
+impl ArchiveWriter {
+    pub fn u8(&mut self, val: u8) {
+        self.buf.push(val);
+    }
+
+    pub fn u16(&mut self, val: u16) {
+        self.buf.extend(val.to_le_bytes());
+    }
+
+    pub fn u32(&mut self, val: u32) {
+        self.buf.extend(val.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, val: u64) {
+        self.buf.extend(val.to_le_bytes());
+    }
+
+    pub fn bytes(&mut self, bytes: &[u8]) {
+        self.u32(bytes.len() as u32);
+        self.buf.extend(bytes);
+    }
+
+    pub fn str(&mut self, str: &str) {
+        self.bytes(str.as_bytes());
+    }
+
+    pub fn symbol(&mut self, symbol: Symbol) {
+        self.str(&symbol.as_str());
+    }
+
+    fn option_u32(&mut self, val: Option<u32>) {
+        // Indices are never u32::MAX, since the arenas would run out of memory first
+        self.u32(val.unwrap_or(u32::MAX));
+    }
+}
+
+pub struct ArchiveReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> ArchiveReader<'a> {
+    fn take(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        let bytes = self
+            .data
+            .get(self.pos..self.pos + len)
+            .ok_or_else(|| invalid("truncated archive"))?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    pub fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn u16(&mut self) -> io::Result<u16> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    pub fn u32(&mut self) -> io::Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    pub fn u64(&mut self) -> io::Result<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    pub fn bytes(&mut self) -> io::Result<&'a [u8]> {
+        let len = self.u32()? as usize;
+        self.take(len)
+    }
+
+    pub fn str(&mut self) -> io::Result<&'a str> {
+        std::str::from_utf8(self.bytes()?).map_err(|_| invalid("invalid string in archive"))
+    }
+
+    pub fn symbol(&mut self) -> io::Result<Symbol> {
+        Ok(Symbol::intern(self.str()?))
+    }
+
+    fn option_u32(&mut self) -> io::Result<Option<u32>> {
+        let val = self.u32()?;
+        Ok((val != u32::MAX).then_some(val))
+    }
+
+    pub fn vec<T>(
+        &mut self,
+        mut read: impl FnMut(&mut Self) -> io::Result<T>,
+    ) -> io::Result<Vec<T>> {
+        let len = self.u32()?;
+        (0..len).map(|_| read(self)).collect()
+    }
+}
+
+/// Maps the ids of archived classes, methods, and fields to their indices in the archive
+#[derive(Default)]
+struct ArchiveIndices {
+    classes: HashMap<ClassId, u32>,
+    methods: HashMap<MethodId, u32>,
+    fields: HashMap<FieldId, u32>,
+}
+
+impl ArchiveIndices {
+    fn class(&self, id: ClassId) -> u32 {
+        self.classes[&id]
+    }
+
+    fn method(&self, id: MethodId) -> u32 {
+        self.methods[&id]
+    }
+}
+
+/// Serialize every class defined by the bootstrap loader
+pub fn write_archive(ma: &MethodArea, class_path_fingerprint: &str) -> Vec<u8> {
+    let classes: Vec<ClassId> = ma
+        .live_classes()
+        .filter(|(_, class)| class.defining_loader == ClassLoader::Bootstrap)
+        .map(|(id, _)| id)
+        .collect();
+    let mut indices = ArchiveIndices::default();
+    let mut methods = Vec::new();
+    let mut fields = Vec::new();
+    for (idx, &id) in classes.iter().enumerate() {
+        indices.classes.insert(id, idx as u32);
+        for &method in &ma.classes[id].methods {
+            indices.methods.insert(method, methods.len() as u32);
+            methods.push(method);
+        }
+        for &field in &ma.classes[id].fields {
+            indices.fields.insert(field, fields.len() as u32);
+            fields.push(field);
+        }
+    }
+
+    let mut w = ArchiveWriter::default();
+    w.buf.extend(MAGIC);
+    w.u32(FORMAT_VERSION);
+    w.str(&vm_identity());
+    w.str(class_path_fingerprint);
+
+    // The counts come first so that ids can be assigned before anything is read
+    w.u32(classes.len() as u32);
+    w.u32(methods.len() as u32);
+    w.u32(fields.len() as u32);
+    for &id in &classes {
+        write_class(&mut w, &indices, &ma.classes[id]);
+    }
+    for &id in &methods {
+        write_method(&mut w, &indices, &ma.methods[id]);
+    }
+    for &id in &fields {
+        write_field(&mut w, &indices, &ma.fields[id]);
+    }
+
+    let class_map: Vec<(Symbol, ClassId)> = ma
+        .class_map
+        .iter()
+        .filter(|((loader, _), _)| *loader == ClassLoader::Bootstrap)
+        .map(|(&(_, name), &id)| (name, id))
+        .collect();
+    w.u32(class_map.len() as u32);
+    for (name, id) in class_map {
+        w.symbol(name);
+        w.u32(indices.class(id));
+    }
+    let array_classes: Vec<(u32, u32)> = ma
+        .array_classes
+        .iter()
+        .filter_map(|(component, arr_class)| {
+            Some((
+                *indices.classes.get(component)?,
+                *indices.classes.get(arr_class)?,
+            ))
+        })
+        .collect();
+    w.u32(array_classes.len() as u32);
+    for (component, arr_class) in array_classes {
+        w.u32(component);
+        w.u32(arr_class);
+    }
+    w.u32(ma.primitive_classes.len() as u32);
+    for (&name, &id) in &ma.primitive_classes {
+        w.symbol(name);
+        w.u32(indices.class(id));
+    }
+    w.buf
+}
+
+fn write_class(w: &mut ArchiveWriter, indices: &ArchiveIndices, class: &Class) {
+    let class_ids = |w: &mut ArchiveWriter, ids: &[ClassId]| {
+        w.u32(ids.len() as u32);
+        for &id in ids {
+            w.u32(indices.class(id));
+        }
+    };
+    let method_ids = |w: &mut ArchiveWriter, ids: &[MethodId]| {
+        w.u32(ids.len() as u32);
+        for &id in ids {
+            w.u32(indices.method(id));
+        }
+    };
+
+    w.symbol(class.name);
+    // Archived classes are never initialized, since static values aren't archived
+    w.u8((class.init_state != InitState::Loaded) as u8);
+    w.option_u32(class.super_class.map(|id| indices.class(id)));
+    class_ids(w, &class.interfaces);
+    w.u16(class.access_flags);
+    method_ids(w, &class.methods);
+    w.u32(class.fields.len() as u32);
+    for field in &class.fields {
+        w.u32(indices.fields[field]);
+    }
+    w.str(
+        &class
+            .elem_ty
+            .as_ref()
+            .map_or(String::new(), |ty| ty.descriptor()),
+    );
+    w.option_u32(class.component_class.map(|id| indices.class(id)));
+    class_ids(w, &class.primary_supers);
+    class_ids(w, &class.secondary_supers);
+    method_ids(w, &class.vtable);
+    w.u32(class.itables.len() as u32);
+    for (interface, itable) in &class.itables {
+        w.u32(indices.class(*interface));
+        method_ids(w, itable);
+    }
+    w.u32(class.size);
+    w.u8(class.alignment);
+    w.u32(class.field_gaps.len() as u32);
+    for &(offset, len) in &class.field_gaps {
+        w.u32(offset);
+        w.u32(len);
+    }
+    class.constant_pool.write_archive(w);
+}
+
+fn write_method(w: &mut ArchiveWriter, indices: &ArchiveIndices, method: &Method) {
+    w.u32(indices.class(method.defining_class));
+    w.symbol(method.name);
+    w.symbol(method.descriptor);
+    w.u16(method.access_flags);
+    w.option_u32(method.vtable_index.map(|idx| idx as u32));
+    w.option_u32(method.itable_index.map(|idx| idx as u32));
+    w.u8(method.code.is_some() as u8);
+    if let Some(code) = &method.code {
+        w.u16(code.max_stack);
+        w.u16(code.max_locals);
+        w.bytes(&code.code);
+        w.u32(code.exception_table.len() as u32);
+        for entry in &code.exception_table {
+            w.u16(entry.start_pc);
+            w.u16(entry.end_pc);
+            w.u16(entry.handler_pc);
+            w.u16(entry.catch_type);
+        }
+        w.u32(code.attributes.len() as u32);
+        for attribute in &code.attributes {
+            w.u16(attribute.attribute_name_index);
+            w.bytes(&attribute.info);
+        }
+    }
+}
+
+fn write_field(w: &mut ArchiveWriter, indices: &ArchiveIndices, field: &Field) {
+    w.u32(indices.class(field.defining_class));
+    w.symbol(field.name);
+    w.symbol(field.descriptor);
+    w.u16(field.access_flags);
+    w.option_u32(match field.backing {
+        FieldBacking::Unprepared | FieldBacking::StaticValue(_) => None,
+        FieldBacking::Instance(offset) => Some(offset),
+    });
+    w.option_u32(field.constant_value.map(u32::from));
+}
+
+/// The ids that archived classes, methods, and fields get, given their indices in the archive.
+/// Everything is allocated in archive order into empty arenas.
+struct ArchivedIds {
+    class_arena: u32,
+    method_arena: u32,
+    field_arena: u32,
+    class_count: u32,
+    method_count: u32,
+    field_count: u32,
+}
+
+impl ArchivedIds {
+    fn class(&self, idx: u32) -> io::Result<ClassId> {
+        Self::id(self.class_arena, idx, self.class_count)
+    }
+
+    fn method(&self, idx: u32) -> io::Result<MethodId> {
+        Self::id(self.method_arena, idx, self.method_count)
+    }
+
+    fn field(&self, idx: u32) -> io::Result<FieldId> {
+        Self::id(self.field_arena, idx, self.field_count)
+    }
+
+    fn id<T>(arena: u32, idx: u32, count: u32) -> io::Result<Id<T>> {
+        if idx >= count {
+            return Err(invalid("archived index out of bounds"));
+        }
+        Ok(DefaultArenaBehavior::new_id(arena, idx as usize))
+    }
+
+    fn classes(&self, r: &mut ArchiveReader) -> io::Result<Vec<ClassId>> {
+        r.vec(|r| self.class(r.u32()?))
+    }
+
+    fn methods(&self, r: &mut ArchiveReader) -> io::Result<Vec<MethodId>> {
+        r.vec(|r| self.method(r.u32()?))
+    }
+}
+
+/// Map the classes in an archive into an empty method area. Fails without changing the method
+/// area if the archive is invalid or was dumped by a different VM or with a different class path.
+/// The class path is only fingerprinted once the rest of the header matches, since that can mean
+/// reading the metadata of every class file.
+pub fn read_archive(
+    ma: &mut MethodArea,
+    data: &[u8],
+    class_path_fingerprint: impl FnOnce() -> String,
+) -> io::Result<()> {
+    assert!(
+        ma.classes.len() == 0 && ma.methods.len() == 0 && ma.fields.len() == 0,
+        "archives can only be mapped into an empty method area"
+    );
+    let mut r = ArchiveReader { data, pos: 0 };
+    if r.take(MAGIC.len())? != MAGIC || r.u32()? != FORMAT_VERSION {
+        return Err(invalid("not a compatible archive"));
+    }
+    if r.str()? != vm_identity() {
+        return Err(invalid(
+            "the archive was dumped by a different build of the VM",
+        ));
+    }
+    if r.str()? != class_path_fingerprint() {
+        return Err(invalid(
+            "the class path has changed since the archive was dumped",
+        ));
+    }
+
+    let ids = ArchivedIds {
+        class_arena: DefaultArenaBehavior::arena_id(ma.classes.next_id()),
+        method_arena: DefaultArenaBehavior::arena_id(ma.methods.next_id()),
+        field_arena: DefaultArenaBehavior::arena_id(ma.fields.next_id()),
+        class_count: r.u32()?,
+        method_count: r.u32()?,
+        field_count: r.u32()?,
+    };
+    let classes: Vec<Class> = (0..ids.class_count)
+        .map(|_| read_class(&mut r, &ids))
+        .collect::<io::Result<_>>()?;
+    let methods: Vec<Method> = (0..ids.method_count)
+        .map(|_| read_method(&mut r, &ids, &classes))
+        .collect::<io::Result<_>>()?;
+    let fields: Vec<Field> = (0..ids.field_count)
+        .map(|_| read_field(&mut r, &ids, &classes))
+        .collect::<io::Result<_>>()?;
+
+    let class_map = r.vec(|r| Ok((r.symbol()?, ids.class(r.u32()?)?)))?;
+    let array_classes = r.vec(|r| Ok((ids.class(r.u32()?)?, ids.class(r.u32()?)?)))?;
+    let primitive_classes = r.vec(|r| Ok((r.symbol()?, ids.class(r.u32()?)?)))?;
+
+    for class in classes {
+        ma.classes.alloc(class);
+    }
+    for method in methods {
+        ma.methods.alloc(method);
+    }
+    for field in fields {
+        ma.fields.alloc(field);
+    }
+    for (name, id) in class_map {
+        ma.class_map.insert((ClassLoader::Bootstrap, name), id);
+    }
+    ma.array_classes.extend(array_classes);
+    ma.primitive_classes.extend(primitive_classes);
+    Ok(())
+}
+
+fn read_class(r: &mut ArchiveReader, ids: &ArchivedIds) -> io::Result<Class> {
+    let name = r.symbol()?;
+    let init_state = if r.u8()? != 0 {
+        InitState::Linked
+    } else {
+        InitState::Loaded
+    };
+    let super_class = r.option_u32()?.map(|idx| ids.class(idx)).transpose()?;
+    let interfaces = ids.classes(r)?;
+    let access_flags = r.u16()?;
+    let methods = ids.methods(r)?;
+    let fields = r.vec(|r| ids.field(r.u32()?))?;
+    let elem_ty = match r.str()? {
+        "" => None,
+        desc => Some(FieldDescriptor::read(desc).0),
+    };
+    let component_class = r.option_u32()?.map(|idx| ids.class(idx)).transpose()?;
+    let primary_supers = ids.classes(r)?;
+    let secondary_supers = ids.classes(r)?;
+    let vtable = ids.methods(r)?;
+    let itables = r.vec(|r| Ok((ids.class(r.u32()?)?, ids.methods(r)?)))?;
+    let size = r.u32()?;
+    let alignment = r.u8()?;
+    let field_gaps = r.vec(|r| Ok((r.u32()?, r.u32()?)))?;
+    let constant_pool = RuntimeConstantPool::read_archive(r, ClassLoader::Bootstrap)?;
+    Ok(Class {
+        constant_pool: Arc::new(constant_pool),
+        init_state,
+        class_obj: None,
+        defining_loader: ClassLoader::Bootstrap,
+        name,
+        package: name.package(),
+        super_class,
+        interfaces,
+        access_flags,
+        methods,
+        fields,
+        elem_ty,
+        component_class,
+        primary_supers,
+        secondary_supers,
+        vtable,
+        itables,
+        size,
+        alignment,
+        field_gaps,
+    })
+}
+
+fn read_method(r: &mut ArchiveReader, ids: &ArchivedIds, classes: &[Class]) -> io::Result<Method> {
+    let class_idx = r.u32()?;
+    let defining_class = ids.class(class_idx)?;
+    let name = r.symbol()?;
+    let descriptor = r.symbol()?;
+    let access_flags = r.u16()?;
+    let vtable_index = r.option_u32()?.map(|idx| idx as usize);
+    let itable_index = r.option_u32()?.map(|idx| idx as usize);
+    let code = if r.u8()? != 0 {
+        let max_stack = r.u16()?;
+        let max_locals = r.u16()?;
+        let code = r.bytes()?.to_vec();
+        let exception_table = r.vec(|r| {
+            Ok(ExceptionTableEntry {
+                start_pc: r.u16()?,
+                end_pc: r.u16()?,
+                handler_pc: r.u16()?,
+                catch_type: r.u16()?,
+            })
+        })?;
+        let attributes = r.vec(|r| {
+            let attribute_name_index = r.u16()?;
+            let info = r.bytes()?.to_vec();
+            Ok(AttributeInfo {
+                attribute_name_index,
+                attribute_length: info.len() as u32,
+                info,
+            })
+        })?;
+        Some(Arc::new(CodeAttribute {
+            max_stack,
+            max_locals,
+            code_length: code.len() as u32,
+            code,
+            exception_table_length: exception_table.len() as u16,
+            exception_table,
+            attributes_count: attributes.len() as u16,
+            attributes,
+        }))
+    } else {
+        None
+    };
+
+    let class_name = classes[class_idx as usize].name.as_str();
+    Ok(Method {
+        defining_class,
+        name,
+        descriptor,
+        parsed_descriptor: MethodDescriptor::read(&descriptor.as_str()),
+        code,
+        access_flags,
+        inline_caches: HashMap::new(),
+        vtable_index,
+        itable_index,
+        intrinsic: CONFIG
+            .use_intrinsics
+            .then(|| intrinsics::lookup(&class_name, &name.as_str(), &descriptor.as_str()))
+            .flatten(),
+    })
+}
+
+fn read_field(r: &mut ArchiveReader, ids: &ArchivedIds, classes: &[Class]) -> io::Result<Field> {
+    let class_idx = r.u32()?;
+    let defining_class = ids.class(class_idx)?;
+    let name = r.symbol()?;
+    let descriptor = r.symbol()?;
+    let parsed_descriptor = FieldDescriptor::read(&descriptor.as_str());
+    let access_flags = r.u16()?;
+    let backing = match r.option_u32()? {
+        Some(offset) => FieldBacking::Instance(offset),
+        None if classes[class_idx as usize].init_state == InitState::Loaded => {
+            FieldBacking::Unprepared
+        }
+        None => FieldBacking::StaticValue(Value::default_for_ty(&parsed_descriptor.0)),
+    };
+    let constant_value = r.option_u32()?.map(|idx| idx as u16);
+    Ok(Field {
+        name,
+        defining_class,
+        access_flags,
+        descriptor,
+        parsed_descriptor,
+        backing,
+        constant_value,
+    })
+}
+
+#[test]
+fn archive_round_trip_test() {
+    let mut ma = MethodArea::default();
+    ma.resolve_primitive_class(Symbol::intern("int"));
+    ma.resolve_primitive_class(Symbol::intern("void"));
+    let data = write_archive(&ma, "class path");
+
+    let mut mapped = MethodArea::default();
+    assert!(read_archive(&mut mapped, &data, || "other class path".into()).is_err());
+    assert_eq!(mapped.classes.len(), 0);
+    read_archive(&mut mapped, &data, || "class path".into()).unwrap();
+    let int = mapped.primitive_classes[&Symbol::intern("int")];
+    let void = mapped.primitive_classes[&Symbol::intern("void")];
+    assert_eq!(mapped.classes[int].name, "int");
+    assert_eq!(mapped.classes[void].primary_supers, [void]);
+    assert_eq!(mapped.classes[void].init_state, InitState::Linked);
+
+    // An archive from another build, even of the same version, is rejected
+    let identity = vm_identity();
+    let start = data
+        .windows(identity.len())
+        .position(|window| window == identity.as_bytes())
+        .unwrap();
+    let mut other_build = data.clone();
+    other_build[start + identity.find("build=").unwrap() + "build=".len()] ^= 1;
+    let mut mapped = MethodArea::default();
+    let err = read_archive(&mut mapped, &other_build, || {
+        panic!("the class path is only fingerprinted for a matching archive")
+    })
+    .unwrap_err();
+    assert!(err.to_string().contains("different build"));
+
+    let mut truncated = MethodArea::default();
+    assert!(
+        read_archive(&mut truncated, &data[..data.len() - 1], || "class path"
+            .into())
+        .is_err()
+    );
+}
+
+#[test]
+fn archive_class_test() {
+    use crate::class_file::{fields, methods};
+    use crate::class_loader::{create_class, parse_class_file};
+    use crate::runtime_constant_pool::Entry;
+    let data = crate::class_file::assemble(
+        "test/Archived",
+        None,
+        &[(fields::acc::STATIC, "count", "I"), (0, "value", "J")],
+        // lconst_1, lreturn
+        &[(methods::acc::PUBLIC, "get", "()J", Some(&[0x0a, 0xad]))],
+    );
+    let mut ma = MethodArea::default();
+    let class_file = parse_class_file(&data).unwrap();
+    let id = create_class(
+        &mut ma,
+        ClassLoader::Bootstrap,
+        class_file,
+        None,
+        Vec::new(),
+    )
+    .unwrap();
+    ma.link_class(id).unwrap();
+    let data = write_archive(&ma, "class path");
+
+    let mut mapped = MethodArea::default();
+    read_archive(&mut mapped, &data, || "class path".into()).unwrap();
+    let class = &mapped.classes
+        [mapped.class_map[&(ClassLoader::Bootstrap, Symbol::intern("test/Archived"))]];
+    assert_eq!(class.init_state, InitState::Linked);
+    assert!(matches!(
+        class.constant_pool.entry(2),
+        Entry::Class { name, .. } if *name == "test/Archived"
+    ));
+
+    let [count, value] = class.fields[..] else {
+        panic!("expected two fields");
+    };
+    assert_eq!(mapped.fields[count].name, "count");
+    assert!(matches!(
+        mapped.fields[count].backing,
+        FieldBacking::StaticValue(Value::Int(0))
+    ));
+    assert_eq!(mapped.fields[value].descriptor, "J");
+
+    let [get] = class.methods[..] else {
+        panic!("expected one method");
+    };
+    let method = &mapped.methods[get];
+    assert_eq!(method.name, "get");
+    assert_eq!(method.access_flags, methods::acc::PUBLIC);
+    let code = method.code.as_ref().unwrap();
+    assert_eq!(code.code, [0x0a, 0xad]);
+    assert_eq!(code.max_stack, 8);
+}
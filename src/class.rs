@@ -1,14 +1,26 @@
 use crate::class_file::attributes::CodeAttribute;
 use crate::class_file::descriptors::{FieldDescriptor, FieldType, MethodDescriptor};
-use crate::class_file::{fields, ConstantPool};
-use crate::class_loader::{method_area, ClassId, ClassLoader, FieldId, MethodArea, MethodId};
-use crate::heap::{heap, ObjectRef};
+use crate::class_file::{fields, methods, ACC_INTERFACE};
+use crate::class_loader::{
+    self, maximally_specific_methods, method_area, ClassId, ClassLoader, FieldId, MethodArea,
+    MethodId,
+};
+use crate::error::{JavaError, JavaResult};
+use crate::heap::{heap, Heap, ObjectRef};
+use crate::jvm::inline_cache::InlineCache;
+use crate::jvm::intrinsics::Intrinsic;
+use crate::runtime_constant_pool::RuntimeConstantPool;
+use crate::symbol::Symbol;
 use crate::value::Value;
+use std::alloc::Layout;
 use std::collections::HashMap;
 use std::sync::Arc;
+use std::thread::ThreadId;
 
 #[derive(Debug)]
 pub enum FieldBacking {
+    /// A static field of a class that hasn't been prepared yet
+    Unprepared,
     StaticValue(Value),
     /// Stored in the object at the given offset
     Instance(u32),
@@ -16,11 +28,14 @@ pub enum FieldBacking {
 
 #[derive(Debug)]
 pub struct Field {
-    pub name: String,
+    pub name: Symbol,
     pub defining_class: ClassId,
     pub access_flags: u16,
-    pub descriptor: FieldDescriptor,
+    pub descriptor: Symbol,
+    pub parsed_descriptor: FieldDescriptor,
     pub backing: FieldBacking,
+    /// Constant pool index of the value assigned to a static field on initialization
+    pub constant_value: Option<u16>,
 }
 
 impl Field {
@@ -34,7 +49,7 @@ impl Field {
     }
 
     pub fn store_static(&mut self, val: Value) {
-        let ty = &self.descriptor.0;
+        let ty = &self.parsed_descriptor.0;
         match &mut self.backing {
             FieldBacking::StaticValue(static_value) => {
                 *static_value = val.store_ty(ty);
@@ -53,56 +68,93 @@ impl Field {
 #[derive(Debug)]
 pub struct Method {
     pub defining_class: ClassId,
-    pub name: String,
-    pub descriptor: MethodDescriptor,
+    pub name: Symbol,
+    pub descriptor: Symbol,
+    pub parsed_descriptor: MethodDescriptor,
     pub code: Option<Arc<CodeAttribute>>,
     pub access_flags: u16,
+    /// Keyed using the bytecode offset of the invokevirtual or invokeinterface instruction
+    pub inline_caches: HashMap<usize, InlineCache>,
+    /// Index into the vtable of the defining class and all of its subclasses
+    pub vtable_index: Option<usize>,
+    /// Index into the itable for the defining interface. Only used for interface methods.
+    pub itable_index: Option<usize>,
+    /// Runs instead of the method's bytecode or native implementation
+    pub intrinsic: Option<Intrinsic>,
 }
 
-#[derive(Copy, Clone, Debug)]
-pub enum Reference {
-    Unresolved,
-    Field(FieldId),
-    Method(MethodId),
-    Class(ClassId),
-}
+impl Method {
+    pub fn is_public(&self) -> bool {
+        self.access_flags & methods::acc::PUBLIC != 0
+    }
 
-impl Reference {
-    fn is_unresolved(self) -> bool {
-        matches!(self, Reference::Unresolved)
+    pub fn is_static(&self) -> bool {
+        self.access_flags & methods::acc::STATIC != 0
     }
-    fn field(self) -> Option<FieldId> {
-        match self {
-            Reference::Field(id) => Some(id),
-            _ => None,
-        }
+
+    pub fn is_private(&self) -> bool {
+        self.access_flags & methods::acc::PRIVATE != 0
     }
 
-    fn method(self) -> Option<MethodId> {
-        match self {
-            Reference::Method(id) => Some(id),
-            _ => None,
-        }
+    pub fn is_abstract(&self) -> bool {
+        self.access_flags & methods::acc::ABSTRACT != 0
     }
 
-    fn class(self) -> Option<ClassId> {
-        match self {
-            Reference::Class(id) => Some(id),
-            _ => None,
-        }
+    pub fn is_final(&self) -> bool {
+        self.access_flags & methods::acc::FINAL != 0
+    }
+
+    /// Whether this method is dispatched based on the class of the receiver
+    pub fn is_virtual(&self) -> bool {
+        !self.is_static()
+            && !self.is_private()
+            && self.name != Symbol::INIT
+            && self.name != Symbol::CLINIT
+    }
+
+    /// Whether this is a method of `MethodHandle` or `VarHandle` that can be invoked with any
+    /// descriptor, given the name of its class (JVMS 2.9.3)
+    pub fn is_signature_polymorphic(&self, class_name: Symbol) -> bool {
+        let flags = methods::acc::VARARGS | methods::acc::NATIVE;
+        (class_name == Symbol::METHOD_HANDLE || class_name == Symbol::VAR_HANDLE)
+            && self.access_flags & flags == flags
+            && self
+                .descriptor
+                .as_str()
+                .starts_with("([Ljava/lang/Object;)")
+    }
+
+    /// Neither public, protected, nor private
+    pub fn is_package_private(&self) -> bool {
+        self.access_flags & (methods::acc::PUBLIC | methods::acc::PROTECTED | methods::acc::PRIVATE)
+            == 0
     }
 }
 
+/// Initialization state of a class (JVMS 5.5)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InitState {
+    /// Loaded, but not verified or prepared yet
+    Loaded,
+    /// Linked, but `<clinit>` has not been run
+    Linked,
+    /// Being initialized by the given thread
+    BeingInitialized(ThreadId),
+    Initialized,
+    /// A previous attempt at initialization failed
+    Erroneous,
+}
+
 #[derive(Debug)]
 pub struct Class {
-    pub constant_pool: ConstantPool,
-    /// Keyed using constant pool index to reference entry
-    pub references: HashMap<u16, Reference>,
-    pub initialized: bool,
+    pub constant_pool: Arc<RuntimeConstantPool>,
+    pub init_state: InitState,
     pub class_obj: Option<ObjectRef>,
 
     pub defining_loader: ClassLoader,
-    pub name: String,
+    pub name: Symbol,
+    /// Computed from `name` when the class is created, for access checks
+    pub package: Symbol,
     pub super_class: Option<ClassId>,
     pub interfaces: Vec<ClassId>,
     pub access_flags: u16,
@@ -111,137 +163,228 @@ pub struct Class {
 
     /// Array element type. Only used for array classes.
     pub elem_ty: Option<FieldType>,
+    /// Class of the array elements, which may be a primitive class. Only used for array classes.
+    pub component_class: Option<ClassId>,
+
+    /// The superclass chain from `Object` down to this class. An interface has no entry for
+    /// itself, since it is only a supertype through `secondary_supers`.
+    pub primary_supers: Vec<ClassId>,
+    /// All direct and indirect superinterfaces
+    pub secondary_supers: Vec<ClassId>,
+
+    /// Virtual methods selected for this class, indexed by `Method::vtable_index`
+    pub vtable: Vec<MethodId>,
+    /// Methods selected for each interface this class implements, indexed by
+    /// `Method::itable_index`
+    pub itables: Vec<(ClassId, Vec<MethodId>)>,
 
     pub size: u32,
     pub alignment: u8,
+    /// Padding between instance fields as `(offset, length)`, which subclasses can put their
+    /// fields in
+    pub field_gaps: Vec<(u32, u32)>,
 }
 
 impl Class {
-    pub fn class_reference(id: ClassId, cp_idx: u16) -> ClassId {
-        let ma = method_area();
-        let self_class = &ma.classes[id];
-        match self_class.references[&cp_idx] {
-            Reference::Unresolved => {
-                let name = self_class.constant_pool.class_name(cp_idx);
-                drop(ma);
-                let class = method_area().resolve_class(&name);
+    pub fn is_interface(&self) -> bool {
+        self.access_flags & ACC_INTERFACE != 0
+    }
 
-                let self_class = &mut method_area().classes[id];
-                *self_class.references.get_mut(&cp_idx).unwrap() = Reference::Class(class);
+    /// The layout of an instance of this class, which is known once it has been linked
+    pub fn instance_layout(&self) -> Layout {
+        Layout::from_size_align(self.size as usize, self.alignment as usize).unwrap()
+    }
 
-                class
-            }
-            Reference::Class(class) => class,
-            _ => unreachable!(),
-        }
+    /// The name of the package this class is in, e.g. `java/lang` for `java/lang/Object`
+    pub fn package_name(&self) -> Symbol {
+        self.package
     }
 
-    pub fn field_reference(id: ClassId, cp_idx: u16) -> FieldId {
-        let ma = method_area();
-        let self_class = &ma.classes[id];
-        match self_class.references[&cp_idx] {
-            Reference::Unresolved => {
-                let (class_idx, nat) = self_class.constant_pool.any_ref(cp_idx);
-                let (name, ..) = self_class.constant_pool.nat(nat);
-                drop(ma);
-                let class_id = Class::class_reference(id, class_idx);
-
-                let mut ma = method_area();
-                let field = ma.resolve_field(class_id, &name);
-                let self_class = &mut ma.classes[id];
-                *self_class.references.get_mut(&cp_idx).unwrap() = Reference::Field(field);
-
-                field
+    /// Selects the method to invoke for a receiver of this class, given the resolved method
+    /// (JVMS 5.4.6)
+    pub fn select_method(ma: &MethodArea, id: ClassId, resolved: MethodId) -> JavaResult<MethodId> {
+        let class = &ma.classes[id];
+        let method = &ma.methods[resolved];
+        let selected = if let Some(idx) = method.vtable_index {
+            class.vtable[idx]
+        } else if let Some(idx) = method.itable_index {
+            let (_, itable) = class
+                .itables
+                .iter()
+                .find(|(interface, _)| *interface == method.defining_class)
+                .ok_or_else(|| {
+                    JavaError::new(
+                        "java/lang/IncompatibleClassChangeError",
+                        format!(
+                            "Class {} does not implement the requested interface {}",
+                            class.name, ma.classes[method.defining_class].name
+                        ),
+                    )
+                })?;
+            itable[idx]
+        } else {
+            return Ok(resolved);
+        };
+        Self::check_selected(ma, id, selected)
+    }
+
+    /// Selects the method invoked by invokespecial, starting the search at `id` (JVMS 6.5)
+    pub fn select_special_method(
+        ma: &MethodArea,
+        id: ClassId,
+        resolved: MethodId,
+    ) -> JavaResult<MethodId> {
+        let method = &ma.methods[resolved];
+        if method.defining_class == id {
+            return Ok(resolved);
+        }
+        let (name, descriptor) = (method.name, method.descriptor);
+
+        let class = &ma.classes[id];
+        let mut selected = None;
+        if class.is_interface() {
+            let object = class.super_class;
+            selected = object
+                .and_then(|object| ma.declared_method(object, name, descriptor))
+                .filter(|&method| {
+                    let method = &ma.methods[method];
+                    method.is_public() && !method.is_static()
+                });
+        } else {
+            let mut cur = Some(id);
+            while let (None, Some(class)) = (selected, cur) {
+                selected = ma.declared_method(class, name, descriptor);
+                cur = ma.classes[class].super_class;
             }
-            Reference::Field(field) => field,
-            _ => unreachable!(),
         }
+        if selected.is_none() {
+            let interfaces = ma.all_superinterfaces(id);
+            let candidates = maximally_specific_methods(ma, &interfaces, name, descriptor);
+            selected = candidates
+                .into_iter()
+                .find(|&method| !ma.methods[method].is_abstract());
+        }
+        Self::check_selected(ma, id, selected.unwrap_or(resolved))
     }
 
-    pub fn method_reference(id: ClassId, cp_idx: u16) -> MethodId {
-        let ma = method_area();
-        let self_class = &ma.classes[id];
-        match self_class.references[&cp_idx] {
-            Reference::Unresolved => {
-                let (class_idx, nat) = self_class.constant_pool.any_ref(cp_idx);
-                let (name, descriptor) = self_class.constant_pool.nat(nat);
-                drop(ma);
-                let class_id = Class::class_reference(id, class_idx);
-
-                let mut ma = method_area();
-                let method =
-                    ma.resolve_method(class_id, &name, &MethodDescriptor::read(&descriptor));
-                let self_class = &mut ma.classes[id];
-                *self_class.references.get_mut(&cp_idx).unwrap() = Reference::Method(method);
-                method
+    /// A default method can only be selected if it is the only non-abstract
+    /// maximally-specific superinterface method, and abstract methods can't be invoked at all
+    fn check_selected(ma: &MethodArea, id: ClassId, selected: MethodId) -> JavaResult<MethodId> {
+        let method = &ma.methods[selected];
+        let describe = |method: &Method| {
+            format!(
+                "{}.{}{}",
+                ma.classes[method.defining_class].name, method.name, method.descriptor
+            )
+        };
+        if ma.classes[method.defining_class].is_interface() && method.is_virtual() {
+            let interfaces = ma.all_superinterfaces(id);
+            let defaults: Vec<MethodId> =
+                maximally_specific_methods(ma, &interfaces, method.name, method.descriptor)
+                    .into_iter()
+                    .filter(|&method| !ma.methods[method].is_abstract())
+                    .collect();
+            if defaults.len() > 1 {
+                let conflicting: Vec<String> = defaults
+                    .iter()
+                    .map(|&method| describe(&ma.methods[method]))
+                    .collect();
+                return Err(JavaError::new(
+                    "java/lang/IncompatibleClassChangeError",
+                    format!("Conflicting default methods: {}", conflicting.join(" ")),
+                ));
             }
-            Reference::Method(method) => method,
-            _ => unreachable!(),
         }
+        if method.is_abstract() {
+            return Err(JavaError::new(
+                "java/lang/AbstractMethodError",
+                format!(
+                    "Receiver class {} does not define or inherit an implementation of the resolved method {}",
+                    ma.classes[id].name,
+                    describe(method)
+                ),
+            ));
+        }
+        Ok(selected)
     }
 
     pub fn obj(id: ClassId) -> ObjectRef {
-        let mut ma = method_area();
-        if let Some(obj_id) = ma.classes[id].class_obj {
-            return obj_id;
+        let ma = method_area();
+        if let Some(obj) = ma.classes[id].class_obj {
+            return obj;
         }
+        let component_class = ma.classes[id].component_class;
+        drop(ma);
+        let component_obj = component_class.map(Class::obj);
+        let class_class =
+            class_loader::resolve_class(ClassLoader::Bootstrap, Symbol::intern("java/lang/Class"))
+                .unwrap();
 
-        let class_class = ma.resolve_class("java/lang/Class");
-        let obj = heap().new_object(&mut ma, class_class);
+        let mut ma = method_area();
+        // The component type's mirror may have been created for this class already
+        if let Some(obj) = ma.classes[id].class_obj {
+            return obj;
+        }
+        // Mirrors are created before Class is initialized
+        ma.link_class(class_class).unwrap_or_else(|err| err.throw());
+        let obj = Heap::new_object(class_class, ma.classes[class_class].instance_layout());
+        if let Some(loader) = ma.classes[id].defining_loader.obj() {
+            let loader_field = ma
+                .resolve_field(
+                    class_class,
+                    Symbol::intern("classLoader"),
+                    Symbol::intern("Ljava/lang/ClassLoader;"),
+                )
+                .unwrap();
+            heap().store_field(&ma, obj, loader_field, Value::Object(Some(loader)));
+        }
+        if let Some(component_obj) = component_obj {
+            let component_field = ma
+                .resolve_field(
+                    class_class,
+                    Symbol::intern("componentType"),
+                    Symbol::intern("Ljava/lang/Class;"),
+                )
+                .unwrap();
+            heap().store_field(
+                &ma,
+                obj,
+                component_field,
+                Value::Object(Some(component_obj)),
+            );
+        }
+        ma.classes[id].class_obj = Some(obj);
         ma.class_objs.insert(obj, id);
-        // TODO: Initialize fields such as classLoader
         obj
     }
 
-    fn of_field_ty(ma: &mut MethodArea, field_ty: FieldType) -> ClassId {
-        match field_ty {
-            FieldType::ArrayType(arr_ty) => {
-                let elem_ty = arr_ty.0 .0;
-                ma.resolve_arr_class(&elem_ty)
-            }
-            FieldType::ObjectType(obj_ty) => ma.resolve_class(&obj_ty.class_name),
-            _ => todo!(),
-        }
+    /// The class used for a primitive type or `void`, given its name
+    pub fn primitive(name: &str) -> ClassId {
+        method_area().resolve_primitive_class(Symbol::intern(name))
     }
 
-    pub fn instance_of(this: ClassId, of: ClassId) -> bool {
-        let mut ma = method_area();
-
-        if let Some(this_elem_ty) = &ma.classes[this].elem_ty {
-            if let Some(of_elem_ty) = &ma.classes[of].elem_ty {
-                return match (this_elem_ty, of_elem_ty) {
-                    (FieldType::BaseType(this_prim), FieldType::BaseType(of_prim)) => {
-                        this_prim == of_prim
-                    }
-                    _ => {
-                        // FIXME: how do I get rid of these clones?
-                        let this_elem_ty = this_elem_ty.clone();
-                        let of_elem_ty = this_elem_ty.clone();
-                        Self::instance_of(
-                            Self::of_field_ty(&mut ma, this_elem_ty),
-                            Self::of_field_ty(&mut ma, of_elem_ty),
-                        )
-                    }
-                };
-            } else {
-                // TODO: also check if `of` is one of the interfaces implemented by array (JLS
-                // 4.10.3)
-                return of == ma.resolve_class("java/lang/Object");
-            }
+    /// Whether `this` is the same as `of` or a subtype of it (JLS 4.10). Arrays are covariant in
+    /// their component type, and primitive classes have no supertypes.
+    pub fn is_subclass_of(ma: &MethodArea, this: ClassId, of: ClassId) -> bool {
+        if this == of {
+            return true;
         }
-
-        let mut cur_class = this;
-        loop {
-            let class = &ma.classes[cur_class];
-            if cur_class == of || class.interfaces.contains(&of) {
-                break true;
-            }
-
-            if let Some(parent) = class.super_class {
-                cur_class = parent;
-            } else {
-                break false;
-            }
+        let this_class = &ma.classes[this];
+        let of_class = &ma.classes[of];
+        if let (Some(this_component), Some(of_component)) =
+            (this_class.component_class, of_class.component_class)
+        {
+            return Self::is_subclass_of(ma, this_component, of_component);
+        }
+        if of_class.is_interface() {
+            this_class.secondary_supers.contains(&of)
+        } else {
+            let depth = of_class.primary_supers.len() - 1;
+            this_class.primary_supers.get(depth) == Some(&of)
         }
     }
+
+    pub fn instance_of(this: ClassId, of: ClassId) -> bool {
+        Self::is_subclass_of(&method_area(), this, of)
+    }
 }
@@ -1,3 +1,5 @@
+use crate::error::{JavaError, JavaResult};
+use crate::symbol::Symbol;
 use deku::bitvec::{BitSlice, Msb0};
 use deku::prelude::*;
 
@@ -7,6 +9,9 @@ pub struct ConstantPool {
     pub count: u16,
     #[deku(reader = "ConstantPool::read_table(deku::rest, *count, endian)")]
     pub table: Vec<CPInfo>,
+    /// Interned Utf8 entries, filled in by `intern_symbols`
+    #[deku(skip)]
+    pub symbols: Vec<Option<Symbol>>,
 }
 
 impl ConstantPool {
@@ -28,48 +33,89 @@ impl ConstantPool {
         Ok((rest, table))
     }
 
-    pub fn utf8(&self, idx: u16) -> String {
-        match &self.table[idx as usize - 1] {
-            CPInfo::Utf8 { bytes, .. } => String::from_utf8(bytes.clone()).unwrap(),
-            _ => panic!("ClassFormatError"),
-        }
+    /// Interns every Utf8 entry so they can be retrieved with `symbol`
+    pub fn intern_symbols(&mut self) -> JavaResult<()> {
+        self.symbols = self
+            .table
+            .iter()
+            .map(|entry| match entry {
+                CPInfo::Utf8 { bytes, .. } => decode_modified_utf8(bytes)
+                    .map(|str| Some(Symbol::intern(&str)))
+                    .ok_or_else(|| {
+                        JavaError::new("java/lang/ClassFormatError", "malformed Utf8 constant")
+                    }),
+                _ => Ok(None),
+            })
+            .collect::<JavaResult<_>>()?;
+        Ok(())
     }
 
-    pub fn class_name(&self, idx: u16) -> String {
-        match self.table[idx as usize - 1] {
-            CPInfo::Class { name_index } => self.utf8(name_index),
-            _ => panic!("ClassFormatError"),
-        }
+    pub fn symbol(&self, idx: u16) -> Symbol {
+        self.symbols[idx as usize - 1].expect("ClassFormatError")
     }
 
-    pub fn any_ref(&self, idx: u16) -> (u16, u16) {
+    pub fn class_symbol(&self, idx: u16) -> Symbol {
         match self.table[idx as usize - 1] {
-            CPInfo::Fieldref {
-                class_index,
-                name_and_type_index,
-            } => (class_index, name_and_type_index),
-            CPInfo::Methodref {
-                class_index,
-                name_and_type_index,
-            } => (class_index, name_and_type_index),
-            CPInfo::InterfaceMethodref {
-                class_index,
-                name_and_type_index,
-            } => (class_index, name_and_type_index),
-            _ => unreachable!(),
+            CPInfo::Class { name_index } => self.symbol(name_index),
+            _ => panic!("ClassFormatError"),
         }
     }
 
-    // Read NameAndType entry
-    pub fn nat(&self, idx: u16) -> (String, String) {
+    // Read NameAndType entry as symbols
+    pub fn nat_symbols(&self, idx: u16) -> (Symbol, Symbol) {
         match self.table[idx as usize - 1] {
             CPInfo::NameAndType {
                 name_index,
                 descriptor_index,
-            } => (self.utf8(name_index), self.utf8(descriptor_index)),
+            } => (self.symbol(name_index), self.symbol(descriptor_index)),
             _ => unreachable!(),
         }
     }
+
+    pub fn utf8(&self, idx: u16) -> String {
+        match &self.table[idx as usize - 1] {
+            CPInfo::Utf8 { bytes, .. } => decode_modified_utf8(bytes).expect("ClassFormatError"),
+            _ => panic!("ClassFormatError"),
+        }
+    }
+}
+
+/// Decode the modified UTF-8 of a Utf8 entry (JVMS 4.4.7), where NUL takes two bytes and
+/// supplementary characters are surrogate pairs of three-byte sequences. Unpaired surrogates
+/// can't be held in a `String` and become U+FFFD. Returns `None` if the bytes are malformed.
+pub fn decode_modified_utf8(bytes: &[u8]) -> Option<String> {
+    // Most names and descriptors are ASCII, which is the same in both encodings
+    if bytes.iter().all(|&byte| (1..0x80).contains(&byte)) {
+        return std::str::from_utf8(bytes).ok().map(str::to_string);
+    }
+
+    let continuation = |idx: usize| {
+        bytes
+            .get(idx)
+            .filter(|&&byte| byte & 0xc0 == 0x80)
+            .map(|&byte| (byte & 0x3f) as u16)
+    };
+    let mut units = Vec::with_capacity(bytes.len());
+    let mut idx = 0;
+    while idx < bytes.len() {
+        let byte = bytes[idx] as u16;
+        let (unit, len) = match byte {
+            0x01..=0x7f => (byte, 1),
+            0xc0..=0xdf => ((byte & 0x1f) << 6 | continuation(idx + 1)?, 2),
+            0xe0..=0xef => (
+                (byte & 0x0f) << 12 | continuation(idx + 1)? << 6 | continuation(idx + 2)?,
+                3,
+            ),
+            _ => return None,
+        };
+        units.push(unit);
+        idx += len;
+    }
+    Some(
+        char::decode_utf16(units)
+            .map(|char| char.unwrap_or(char::REPLACEMENT_CHARACTER))
+            .collect(),
+    )
 }
 
 #[derive(DekuRead, Debug, PartialEq)]
@@ -170,3 +216,22 @@ fn cp_test() {
         }
     );
 }
+
+#[test]
+fn modified_utf8_test() {
+    assert_eq!(
+        decode_modified_utf8(b"java/lang/Object").unwrap(),
+        "java/lang/Object"
+    );
+    assert_eq!(decode_modified_utf8(b"a\xc0\x80b").unwrap(), "a\0b");
+    assert_eq!(decode_modified_utf8("é€".as_bytes()).unwrap(), "é€");
+    // U+1F600 as the surrogate pair D83D DE00
+    assert_eq!(
+        decode_modified_utf8(b"\xed\xa0\xbd\xed\xb8\x80").unwrap(),
+        "\u{1F600}"
+    );
+    assert_eq!(decode_modified_utf8(b"\xed\xa0\xbd").unwrap(), "\u{FFFD}");
+    assert_eq!(decode_modified_utf8(b"\0"), None);
+    assert_eq!(decode_modified_utf8(b"\xf0\x9f\x98\x80"), None);
+    assert_eq!(decode_modified_utf8(b"\xc3"), None);
+}
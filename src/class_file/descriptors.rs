@@ -18,6 +18,13 @@ fn method_desc_test() {
     )
 }
 
+#[test]
+fn field_desc_test() {
+    for desc in ["I", "[[J", "Ljava/lang/String;", "[Ljava/lang/Object;"] {
+        assert_eq!(FieldDescriptor::read(desc).0.descriptor(), desc);
+    }
+}
+
 #[derive(Debug, PartialEq)]
 pub struct MethodDescriptor(pub Vec<ParameterDescriptor>, pub ReturnDescriptor);
 
@@ -44,7 +51,7 @@ impl MethodDescriptor {
 }
 
 #[derive(Debug, PartialEq)]
-pub struct ParameterDescriptor(FieldType);
+pub struct ParameterDescriptor(pub FieldType);
 
 #[derive(Debug, PartialEq)]
 pub enum ReturnDescriptor {
@@ -70,6 +77,15 @@ pub enum FieldType {
 }
 
 impl FieldType {
+    /// The descriptor for this type, which is also the binary name of array classes
+    pub fn descriptor(&self) -> String {
+        match self {
+            FieldType::BaseType(base_type) => base_type.descriptor_char().to_string(),
+            FieldType::ObjectType(obj_type) => format!("L{};", obj_type.class_name),
+            FieldType::ArrayType(arr_type) => format!("[{}", arr_type.0 .0.descriptor()),
+        }
+    }
+
     fn read(cs: &mut impl Iterator<Item = char>) -> FieldType {
         match cs.next().unwrap() {
             'B' => FieldType::BaseType(BaseType::B),
@@ -126,6 +142,21 @@ pub enum BaseType {
     Z,
 }
 
+impl BaseType {
+    pub fn descriptor_char(self) -> char {
+        match self {
+            BaseType::B => 'B',
+            BaseType::C => 'C',
+            BaseType::D => 'D',
+            BaseType::F => 'F',
+            BaseType::I => 'I',
+            BaseType::J => 'J',
+            BaseType::S => 'S',
+            BaseType::Z => 'Z',
+        }
+    }
+}
+
 impl std::fmt::Display for BaseType {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
@@ -52,3 +52,87 @@ pub struct ClassFile {
     #[deku(count = "attributes_count")]
     pub attributes: Vec<AttributeInfo>,
 }
+
+/// Assemble a class file for tests. Fields are `(access_flags, name, descriptor)` and methods
+/// are `(access_flags, name, descriptor, code)`, where methods with code get a `max_stack` and
+/// `max_locals` of 8.
+#[cfg(test)]
+pub fn assemble(
+    name: &str,
+    super_class: Option<&str>,
+    fields: &[(u16, &str, &str)],
+    methods: &[(u16, &str, &str, Option<&[u8]>)],
+) -> Vec<u8> {
+    #[derive(Default)]
+    struct Pool {
+        bytes: Vec<u8>,
+        count: u16,
+        utf8s: std::collections::HashMap<String, u16>,
+    }
+    impl Pool {
+        fn utf8(&mut self, str: &str) -> u16 {
+            if let Some(&idx) = self.utf8s.get(str) {
+                return idx;
+            }
+            self.bytes.push(1);
+            self.bytes.extend((str.len() as u16).to_be_bytes());
+            self.bytes.extend(str.as_bytes());
+            self.count += 1;
+            self.utf8s.insert(str.to_string(), self.count);
+            self.count
+        }
+
+        fn class(&mut self, name: &str) -> u16 {
+            let name_idx = self.utf8(name);
+            self.bytes.push(7);
+            self.bytes.extend(name_idx.to_be_bytes());
+            self.count += 1;
+            self.count
+        }
+    }
+
+    let mut pool = Pool::default();
+    let mut body = Vec::new();
+    body.extend(ACC_PUBLIC.to_be_bytes());
+    body.extend(pool.class(name).to_be_bytes());
+    body.extend(super_class.map_or(0, |name| pool.class(name)).to_be_bytes());
+    body.extend(0u16.to_be_bytes());
+
+    body.extend((fields.len() as u16).to_be_bytes());
+    for &(access_flags, name, descriptor) in fields {
+        body.extend(access_flags.to_be_bytes());
+        body.extend(pool.utf8(name).to_be_bytes());
+        body.extend(pool.utf8(descriptor).to_be_bytes());
+        body.extend(0u16.to_be_bytes());
+    }
+
+    body.extend((methods.len() as u16).to_be_bytes());
+    for &(access_flags, name, descriptor, code) in methods {
+        body.extend(access_flags.to_be_bytes());
+        body.extend(pool.utf8(name).to_be_bytes());
+        body.extend(pool.utf8(descriptor).to_be_bytes());
+        let Some(code) = code else {
+            body.extend(0u16.to_be_bytes());
+            continue;
+        };
+        body.extend(1u16.to_be_bytes());
+        body.extend(pool.utf8("Code").to_be_bytes());
+        body.extend((12 + code.len() as u32).to_be_bytes());
+        body.extend(8u16.to_be_bytes());
+        body.extend(8u16.to_be_bytes());
+        body.extend((code.len() as u32).to_be_bytes());
+        body.extend(code);
+        body.extend(0u16.to_be_bytes());
+        body.extend(0u16.to_be_bytes());
+    }
+    body.extend(0u16.to_be_bytes());
+
+    let mut class_file = Vec::new();
+    class_file.extend(0xCAFEBABEu32.to_be_bytes());
+    class_file.extend(0u16.to_be_bytes());
+    class_file.extend(61u16.to_be_bytes());
+    class_file.extend((pool.count + 1).to_be_bytes());
+    class_file.extend(pool.bytes);
+    class_file.extend(body);
+    class_file
+}
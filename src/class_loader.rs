@@ -1,161 +1,1311 @@
-use crate::class::{Class, Field, FieldBacking, Method, Reference};
+use crate::class::{Class, Field, FieldBacking, InitState, Method};
 use crate::class_file::constant_pool::CPInfo;
-use crate::class_file::descriptors::{BaseType, FieldDescriptor, FieldType, MethodDescriptor};
-use crate::class_file::{fields, ClassFile};
-use crate::heap::{heap, Object, ObjectRef};
+use crate::class_file::descriptors::{
+    ArrayType, BaseType, ComponentType, FieldDescriptor, FieldType, MethodDescriptor, ObjectType,
+};
+use crate::class_file::{fields, ClassFile, ACC_ABSTRACT, ACC_FINAL, ACC_INTERFACE, ACC_PUBLIC};
+use crate::class_path::{class_path, ClassSource};
+use crate::error::{JavaError, JavaResult};
+use crate::heap::{heap, layout_for_field, Object, ObjectRef, MIN_OBJECT_ALIGN};
+use crate::jvm::inline_cache::InlineCache;
+use crate::jvm::profile::{self, profile};
+use crate::jvm::{intrinsics, Thread};
+use crate::runtime_constant_pool::RuntimeConstantPool;
+use crate::symbol::Symbol;
 use crate::value::Value;
 use crate::CONFIG;
 use deku::DekuContainerRead;
 use id_arena::{Arena, Id};
-use std::collections::HashMap;
-use std::path::PathBuf;
-use std::sync::{Arc, LazyLock, Mutex, MutexGuard};
-use std::{fs, str};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Condvar, LazyLock, Mutex, MutexGuard, PoisonError};
+use std::thread::{self, ThreadId};
+use std::{iter, mem};
 
 static METHOD_AREA: LazyLock<Mutex<MethodArea>> = LazyLock::new(Default::default);
 
 /// Retrieve the method area by locking the mutex
 pub fn method_area() -> MutexGuard<'static, MethodArea> {
-    METHOD_AREA.lock().unwrap()
+    // Errors are thrown by unwinding, which may happen while the lock is held
+    METHOD_AREA.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+#[derive(Default)]
+struct Placeholders {
+    /// The threads loading each class, keyed by initiating loader and name
+    owners: HashMap<(ClassLoader, Symbol), Vec<ThreadId>>,
+    /// The class each blocked thread is waiting for another thread to load
+    waiting: HashMap<ThreadId, (ClassLoader, Symbol)>,
+}
+
+impl Placeholders {
+    /// Whether waiting for `key` would make `current` wait on itself, through the classes the
+    /// threads loading `key` are themselves waiting for
+    fn would_deadlock(&self, current: ThreadId, key: (ClassLoader, Symbol)) -> bool {
+        let mut visited = HashSet::new();
+        let mut keys = vec![key];
+        while let Some(key) = keys.pop() {
+            for &owner in self.owners.get(&key).into_iter().flatten() {
+                if owner == current {
+                    return true;
+                }
+                if visited.insert(owner) {
+                    keys.extend(self.waiting.get(&owner));
+                }
+            }
+        }
+        false
+    }
+}
+
+static PLACEHOLDERS: LazyLock<Mutex<Placeholders>> = LazyLock::new(Default::default);
+static PLACEHOLDER_RELEASED: Condvar = Condvar::new();
+
+fn placeholders() -> MutexGuard<'static, Placeholders> {
+    PLACEHOLDERS.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// The current thread's claim on loading a class. It's released when dropped, including when an
+/// error unwinds, so waiting threads can't be left blocked.
+struct Placeholder {
+    key: (ClassLoader, Symbol),
+}
+
+impl Placeholder {
+    /// Claim a class for the current thread. A thread that reaches its own placeholder again is
+    /// loading a class that is its own superclass or superinterface. With `wait`, a class claimed
+    /// by another thread is waited for and `None` is returned once it's released, unless the
+    /// other thread is itself waiting for the current one, which is also circularity.
+    fn claim(key: (ClassLoader, Symbol), wait: bool) -> JavaResult<Option<Placeholder>> {
+        let current = thread::current().id();
+        let mut placeholders = placeholders();
+        let mut waited = false;
+        loop {
+            let threads = placeholders.owners.get(&key).map_or(&[][..], Vec::as_slice);
+            if threads.contains(&current) || wait && placeholders.would_deadlock(current, key) {
+                placeholders.waiting.remove(&current);
+                return Err(JavaError::new(
+                    "java/lang/ClassCircularityError",
+                    key.1.to_string(),
+                ));
+            }
+            if threads.is_empty() || !wait {
+                break;
+            }
+            waited = true;
+            placeholders.waiting.insert(current, key);
+            placeholders = PLACEHOLDER_RELEASED
+                .wait(placeholders)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+        if waited {
+            placeholders.waiting.remove(&current);
+            return Ok(None);
+        }
+        placeholders.owners.entry(key).or_default().push(current);
+        Ok(Some(Placeholder { key }))
+    }
+}
+
+impl Drop for Placeholder {
+    fn drop(&mut self) {
+        let current = thread::current().id();
+        let mut placeholders = placeholders();
+        if let Some(threads) = placeholders.owners.get_mut(&self.key) {
+            threads.retain(|&thread| thread != current);
+            if threads.is_empty() {
+                placeholders.owners.remove(&self.key);
+            }
+        }
+        PLACEHOLDER_RELEASED.notify_all();
+    }
 }
 
 pub type ClassId = Id<Class>;
 pub type MethodId = Id<Method>;
 pub type FieldId = Id<Field>;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
 pub enum ClassLoader {
+    #[default]
     Bootstrap,
-    UserDefined(/* TODO */),
+    /// A `java.lang.ClassLoader` instance
+    UserDefined(ObjectRef),
+}
+
+impl ClassLoader {
+    /// The loader for a `java.lang.ClassLoader` reference, where null means the bootstrap loader
+    pub fn from_obj(obj: Option<ObjectRef>) -> ClassLoader {
+        obj.map_or(ClassLoader::Bootstrap, ClassLoader::UserDefined)
+    }
+
+    pub fn obj(self) -> Option<ObjectRef> {
+        match self {
+            ClassLoader::Bootstrap => None,
+            ClassLoader::UserDefined(obj) => Some(obj),
+        }
+    }
+}
+
+/// Requires that all of `loaders` load the same class for `name` (JVMS 5.3.4)
+#[derive(Debug)]
+pub struct LoaderConstraint {
+    name: Symbol,
+    loaders: Vec<ClassLoader>,
+    /// The class one of the loaders has loaded, if any have
+    class: Option<ClassId>,
+}
+
+impl LoaderConstraint {
+    fn applies_to(&self, name: Symbol, loader: ClassLoader) -> bool {
+        self.name == name && self.loaders.contains(&loader)
+    }
+}
+
+fn constraint_violation(name: Symbol, loader: ClassLoader) -> JavaError {
+    JavaError::new(
+        "java/lang/LinkageError",
+        format!(
+            "loader constraint violation: loader {:?} has a different class with name {} than \
+             another loader that it shares types with",
+            loader, name
+        ),
+    )
+}
+
+/// Names of the classes in a field or method descriptor. Array types contribute their element
+/// class.
+fn descriptor_class_names(descriptor: &str) -> Vec<Symbol> {
+    let mut names = Vec::new();
+    let mut rest = descriptor;
+    while let Some(start) = rest.find('L') {
+        let end = start + rest[start..].find(';').unwrap();
+        names.push(Symbol::intern(&rest[start + 1..end]));
+        rest = &rest[end + 1..];
+    }
+    names
+}
+
+#[test]
+fn descriptor_class_names_test() {
+    let names = descriptor_class_names("(I[[Ljava/lang/String;JLLoader;)[LFoo;");
+    let names: Vec<String> = names.iter().map(|name| name.to_string()).collect();
+    assert_eq!(names, ["java/lang/String", "Loader", "Foo"]);
+    assert!(descriptor_class_names("([IJ)V").is_empty());
 }
 
 #[derive(Default, Debug)]
 pub struct MethodArea {
     pub classes: Arena<Class>,
-    pub class_map: HashMap<String, ClassId>,
+    /// Classes by initiating loader and name. A class is recorded under its defining loader and
+    /// every loader that delegated to it.
+    pub class_map: HashMap<(ClassLoader, Symbol), ClassId>,
     pub class_objs: HashMap<ObjectRef, ClassId>,
-    /// Mapping of the element type to class id
-    pub array_classes: HashMap<FieldType, ClassId>,
+    /// Mapping of the component class to array class
+    pub array_classes: HashMap<ClassId, ClassId>,
+    pub loader_constraints: Vec<LoaderConstraint>,
+    /// Classes of the primitive types and `void`, by name. These aren't in `class_map` since they
+    /// can't be resolved by name.
+    pub primitive_classes: HashMap<Symbol, ClassId>,
     pub methods: Arena<Method>,
     pub fields: Arena<Field>,
+    /// Slots of unloaded classes, methods, and fields, which are reused by later allocations
+    free_classes: HashSet<ClassId>,
+    free_methods: Vec<MethodId>,
+    free_fields: Vec<FieldId>,
 }
 
 impl MethodArea {
+    /// Classes that haven't been unloaded
+    pub fn live_classes(&self) -> impl Iterator<Item = (ClassId, &Class)> {
+        self.classes
+            .iter()
+            .filter(|(id, _)| !self.free_classes.contains(id))
+    }
+
+    /// The id the next class allocated will get
+    fn next_class_id(&self) -> ClassId {
+        self.free_classes
+            .iter()
+            .next()
+            .copied()
+            .unwrap_or_else(|| self.classes.next_id())
+    }
+
+    fn alloc_class(&mut self, class: Class) -> ClassId {
+        match self.next_class_id() {
+            id if self.free_classes.remove(&id) => {
+                self.classes[id] = class;
+                id
+            }
+            _ => self.classes.alloc(class),
+        }
+    }
+
+    fn alloc_method(&mut self, method: Method) -> MethodId {
+        match self.free_methods.pop() {
+            Some(id) => {
+                self.methods[id] = method;
+                id
+            }
+            None => self.methods.alloc(method),
+        }
+    }
+
+    fn alloc_field(&mut self, field: Field) -> FieldId {
+        match self.free_fields.pop() {
+            Some(id) => {
+                self.fields[id] = field;
+                id
+            }
+            None => self.fields.alloc(field),
+        }
+    }
+
+    /// Unload the classes of every user-defined loader that can't be reached from `roots` or from
+    /// the classes of the bootstrap loader, which are never unloaded. A loader is reachable if its
+    /// object, the mirror of one of its classes, or an instance of one of its classes is.
+    pub fn unload_unreachable_loaders(&mut self, roots: Vec<ObjectRef>) {
+        let mut unreachable: HashSet<ClassLoader> = self
+            .live_classes()
+            .map(|(_, class)| class.defining_loader)
+            .chain(self.class_map.keys().map(|&(loader, _)| loader))
+            .filter(|&loader| loader != ClassLoader::Bootstrap)
+            .collect();
+
+        let heap = heap();
+        let mut marked = HashSet::new();
+        let mut worklist = roots;
+        self.push_loader_roots(ClassLoader::Bootstrap, &mut worklist);
+        while let Some(obj) = worklist.pop() {
+            if !marked.insert(obj) {
+                continue;
+            }
+            // An object keeps its class alive, and a mirror keeps the class it represents alive
+            let class = heap.get_obj_class(obj);
+            let mirrored = self.class_objs.get(&obj).copied();
+            for class in iter::once(class).chain(mirrored) {
+                worklist.extend(self.classes[class].defining_loader.obj());
+            }
+            if unreachable.remove(&ClassLoader::UserDefined(obj)) {
+                self.push_loader_roots(ClassLoader::UserDefined(obj), &mut worklist);
+            }
+            worklist.extend(heap.references(self, obj));
+        }
+
+        let mut unused = HashSet::new();
+        for loader in unreachable {
+            unused.extend(self.unload_loader(loader));
+        }
+        self.reclaim_symbols(unused);
+    }
+
+    /// Reclaim the symbols of unloaded classes that no remaining class, loader constraint, or
+    /// class being loaded uses
+    fn reclaim_symbols(&self, mut unused: HashSet<Symbol>) {
+        for (id, _) in self.live_classes() {
+            for symbol in self.class_symbols(id) {
+                unused.remove(&symbol);
+            }
+        }
+        for &(_, name) in self.class_map.keys() {
+            unused.remove(&name);
+        }
+        for constraint in &self.loader_constraints {
+            unused.remove(&constraint.name);
+        }
+        let placeholders = placeholders();
+        for (_, name) in placeholders
+            .owners
+            .keys()
+            .chain(placeholders.waiting.values())
+        {
+            unused.remove(name);
+        }
+        Symbol::reclaim(unused);
+    }
+
+    /// The symbols a class uses for its name, members, and constant pool
+    fn class_symbols(&self, id: ClassId) -> impl Iterator<Item = Symbol> + '_ {
+        let class = &self.classes[id];
+        let methods = class.methods.iter().flat_map(|&method| {
+            let method = &self.methods[method];
+            [method.name, method.descriptor]
+        });
+        let fields = class.fields.iter().flat_map(|&field| {
+            let field = &self.fields[field];
+            [field.name, field.descriptor]
+        });
+        [class.name, class.package]
+            .into_iter()
+            .chain(methods)
+            .chain(fields)
+            .chain(class.constant_pool.symbols())
+    }
+
+    /// Push the objects kept alive by the classes a loader has defined, and the loaders of the
+    /// classes it has initiated loading of
+    fn push_loader_roots(&self, loader: ClassLoader, worklist: &mut Vec<ObjectRef>) {
+        let classes = self
+            .live_classes()
+            .filter(|(_, class)| class.defining_loader == loader);
+        for (_, class) in classes {
+            worklist.extend(class.class_obj);
+            worklist.extend(class.constant_pool.resolved_objects());
+            for &field in &class.fields {
+                if let FieldBacking::StaticValue(val @ (Value::Object(_) | Value::Array(_))) =
+                    self.fields[field].backing
+                {
+                    worklist.extend(val.object());
+                }
+            }
+        }
+        for (&(initiating, _), &class) in &self.class_map {
+            if initiating == loader {
+                worklist.extend(self.classes[class].defining_loader.obj());
+            }
+        }
+    }
+
+    /// Unload every class defined by a user-defined loader, and reclaim their methods and fields.
+    /// Returns the symbols the classes used, which can be reclaimed unless other classes use them.
+    ///
+    /// The loader object, the mirrors of its classes, and all of their instances must be
+    /// unreachable. Any loader that delegates to this one keeps it reachable, so no class from
+    /// another loader can refer to the unloaded classes.
+    fn unload_loader(&mut self, loader: ClassLoader) -> HashSet<Symbol> {
+        assert!(
+            loader != ClassLoader::Bootstrap,
+            "the bootstrap loader can't be unloaded"
+        );
+        let unloaded: HashSet<ClassId> = self
+            .live_classes()
+            .filter(|(_, class)| class.defining_loader == loader)
+            .map(|(id, _)| id)
+            .collect();
+
+        self.class_map
+            .retain(|&(initiating, _), class| initiating != loader && !unloaded.contains(class));
+        self.class_objs.retain(|_, class| !unloaded.contains(class));
+        self.array_classes.retain(|component, arr_class| {
+            !unloaded.contains(component) && !unloaded.contains(arr_class)
+        });
+        for constraint in &mut self.loader_constraints {
+            constraint.loaders.retain(|&other| other != loader);
+            if constraint
+                .class
+                .is_some_and(|class| unloaded.contains(&class))
+            {
+                constraint.class = None;
+            }
+        }
+        self.loader_constraints
+            .retain(|constraint| constraint.loaders.len() > 1);
+
+        let unloaded_methods: HashSet<MethodId> = unloaded
+            .iter()
+            .flat_map(|&id| self.classes[id].methods.iter().copied())
+            .collect();
+        let symbols = unloaded
+            .iter()
+            .flat_map(|&id| self.class_symbols(id))
+            .collect();
+        for &id in &unloaded {
+            self.free_class(id);
+        }
+        if profile::is_enabled() {
+            profile().forget(&unloaded_methods, &unloaded);
+        }
+
+        // Call sites elsewhere may have cached an unloaded receiver class, whose id will be reused
+        for (_, method) in self.methods.iter_mut() {
+            for cache in method.inline_caches.values_mut() {
+                if cache.mentions_any(&unloaded) {
+                    *cache = InlineCache::Empty;
+                }
+            }
+        }
+        symbols
+    }
+
+    /// Free the slots of a class that nothing refers to anymore, along with its methods and fields
+    fn free_class(&mut self, id: ClassId) {
+        let class = &mut self.classes[id];
+        let methods = mem::take(&mut class.methods);
+        let fields = mem::take(&mut class.fields);
+        class.constant_pool = Arc::new(RuntimeConstantPool::default());
+        class.class_obj = None;
+        class.interfaces = Vec::new();
+        class.primary_supers = Vec::new();
+        class.secondary_supers = Vec::new();
+        class.vtable = Vec::new();
+        class.itables = Vec::new();
+
+        // Drop the bytecode and static values now rather than when the slots are reused
+        for &method in &methods {
+            let method = &mut self.methods[method];
+            method.code = None;
+            method.inline_caches = HashMap::new();
+        }
+        for &field in &fields {
+            self.fields[field].backing = FieldBacking::Unprepared;
+        }
+        self.free_methods.extend(methods);
+        self.free_fields.extend(fields);
+        self.free_classes.insert(id);
+    }
+
+    /// The array class with elements of the given type, resolved by the bootstrap loader
     pub fn resolve_arr_class(&mut self, elem_ty: &FieldType) -> ClassId {
-        let id = self.array_classes.get(&elem_ty).cloned();
-        match id {
-            Some(id) => id,
-            None => load_arr_class_bootstrap(self, elem_ty.clone()),
+        let component = match elem_ty {
+            FieldType::BaseType(base_type) => {
+                self.resolve_primitive_class(Symbol::intern(&base_type.to_string()))
+            }
+            FieldType::ObjectType(obj_type) => self
+                .resolve_class(Symbol::intern(&obj_type.class_name))
+                .unwrap_or_else(|err| err.throw()),
+            FieldType::ArrayType(arr_type) => self.resolve_arr_class(&arr_type.0 .0),
+        };
+        self.array_class_of(component)
+    }
+
+    /// The array class with the given component class
+    pub fn array_class_of(&mut self, component: ClassId) -> ClassId {
+        match self.array_classes.get(&component) {
+            Some(&id) => id,
+            None => load_arr_class(self, component),
         }
     }
 
-    pub fn resolve_class(&mut self, name: &str) -> ClassId {
-        if name.starts_with('[') {
-            let desc = FieldDescriptor::read(name);
+    /// The class for a primitive type or `void`, such as `int`
+    pub fn resolve_primitive_class(&mut self, name: Symbol) -> ClassId {
+        match self.primitive_classes.get(&name) {
+            Some(&id) => id,
+            None => load_primitive_class(self, name),
+        }
+    }
+
+    /// A class the bootstrap loader has already loaded. Classes can't be loaded while holding the
+    /// method area, so the ones the VM uses here are loaded at startup by [`preload_classes`], and
+    /// others should be resolved with [`resolve_class`] instead.
+    pub fn resolve_class(&mut self, name: Symbol) -> JavaResult<ClassId> {
+        if name.is_array() {
+            let desc = FieldDescriptor::read(&name.as_str());
             let elem_ty = match desc.0 {
                 FieldType::ArrayType(arr_ty) => arr_ty.0 .0,
                 _ => unreachable!(),
             };
-            return self.resolve_arr_class(&elem_ty);
+            return Ok(self.resolve_arr_class(&elem_ty));
         }
 
-        let id = self.class_map.get(name).cloned();
-        match id {
-            Some(id) => id,
-            None => load_class_bootstrap(self, name),
+        self.class_map
+            .get(&(ClassLoader::Bootstrap, name))
+            .copied()
+            .ok_or_else(|| {
+                JavaError::new(
+                    "java/lang/NoClassDefFoundError",
+                    format!("{} has not been loaded", name),
+                )
+            })
+    }
+
+    /// Record that `loader` is an initiating loader of a class, as long as that doesn't violate a
+    /// loading constraint
+    fn record_class(&mut self, loader: ClassLoader, name: Symbol, id: ClassId) -> JavaResult<()> {
+        for constraint in &self.loader_constraints {
+            match constraint.class {
+                Some(class) if class != id && constraint.applies_to(name, loader) => {
+                    return Err(constraint_violation(name, loader))
+                }
+                _ => {}
+            }
+        }
+        for constraint in &mut self.loader_constraints {
+            if constraint.applies_to(name, loader) {
+                constraint.class = Some(id);
+            }
         }
+        self.class_map.insert((loader, name), id);
+        Ok(())
+    }
+
+    /// Add the constraint that the class `name` is the same when loaded by either loader
+    /// (JVMS 5.3.4)
+    fn add_loader_constraint(
+        &mut self,
+        name: Symbol,
+        loader1: ClassLoader,
+        loader2: ClassLoader,
+    ) -> JavaResult<()> {
+        if loader1 == loader2 {
+            return Ok(());
+        }
+        let (merged, rest): (Vec<LoaderConstraint>, Vec<LoaderConstraint>) =
+            mem::take(&mut self.loader_constraints)
+                .into_iter()
+                .partition(|constraint| {
+                    constraint.applies_to(name, loader1) || constraint.applies_to(name, loader2)
+                });
+        self.loader_constraints = rest;
+
+        let mut constraint = LoaderConstraint {
+            name,
+            loaders: vec![loader1, loader2],
+            class: None,
+        };
+        let loaded = [loader1, loader2]
+            .map(|loader| self.class_map.get(&(loader, name)).copied())
+            .into_iter()
+            .chain(merged.iter().map(|constraint| constraint.class));
+        let mut violated = false;
+        for class in loaded.flatten() {
+            violated |= *constraint.class.get_or_insert(class) != class;
+        }
+        for other in &merged {
+            for &loader in &other.loaders {
+                if !constraint.loaders.contains(&loader) {
+                    constraint.loaders.push(loader);
+                }
+            }
+        }
+
+        if violated {
+            // Keep the constraints that were already there
+            self.loader_constraints.extend(merged);
+            return Err(constraint_violation(name, loader1));
+        }
+        self.loader_constraints.push(constraint);
+        Ok(())
+    }
+
+    /// Constrain every class named in a field or method descriptor to be the same for both
+    /// loaders. Used when a member is referenced or overridden across loaders.
+    pub fn add_descriptor_constraints(
+        &mut self,
+        descriptor: Symbol,
+        loader1: ClassLoader,
+        loader2: ClassLoader,
+    ) -> JavaResult<()> {
+        for name in descriptor_class_names(&descriptor.as_str()) {
+            self.add_loader_constraint(name, loader1, loader2)?;
+        }
+        Ok(())
+    }
+
+    /// Link a loaded class (JVMS 5.4). Its superclass and superinterfaces are linked first, then
+    /// the class is checked and its method tables are laid out.
+    pub fn link_class(&mut self, id: ClassId) -> JavaResult<()> {
+        if self.classes[id].init_state != InitState::Loaded {
+            return Ok(());
+        }
+        let class = &self.classes[id];
+        let (name, super_class, interfaces) =
+            (class.name, class.super_class, class.interfaces.clone());
+        for super_id in super_class.iter().chain(&interfaces) {
+            self.link_class(*super_id)?;
+        }
+
+        self.verify(id)?;
+
+        // Preparation
+        let class = &self.classes[id];
+        for &field in &class.fields {
+            let field = &mut self.fields[field];
+            if let FieldBacking::Unprepared = field.backing {
+                field.backing =
+                    FieldBacking::StaticValue(Value::default_for_ty(&field.parsed_descriptor.0));
+            }
+        }
+        let class = &self.classes[id];
+        let methods = class.methods.clone();
+        if class.is_interface() {
+            // Interface methods are dispatched through the itables of implementing classes
+            for (idx, &method) in methods.iter().enumerate() {
+                if self.methods[method].is_virtual() {
+                    self.methods[method].itable_index = Some(idx);
+                }
+            }
+        } else {
+            let all_interfaces = class.secondary_supers.clone();
+            let vtable = build_vtable(self, name, super_class, &methods, &all_interfaces)?;
+            let itables = build_itables(self, &vtable, &all_interfaces);
+            let class = &mut self.classes[id];
+            class.vtable = vtable;
+            class.itables = itables;
+        }
+
+        self.classes[id].init_state = InitState::Linked;
+        Ok(())
+    }
+
+    /// The checks on a class's relationship to its supertypes that don't need a bytecode verifier
+    fn verify(&self, id: ClassId) -> JavaResult<()> {
+        let class = &self.classes[id];
+        if let Some(super_class) = class.super_class {
+            let super_class = &self.classes[super_class];
+            if super_class.is_interface() {
+                return Err(JavaError::new(
+                    "java/lang/IncompatibleClassChangeError",
+                    format!(
+                        "class {} has interface {} as super class",
+                        class.name, super_class.name
+                    ),
+                ));
+            }
+            if super_class.access_flags & ACC_FINAL != 0 {
+                return Err(JavaError::new(
+                    "java/lang/VerifyError",
+                    format!(
+                        "Cannot inherit from final class {} in {}",
+                        super_class.name, class.name
+                    ),
+                ));
+            }
+        }
+        for &interface in &class.interfaces {
+            let interface = &self.classes[interface];
+            if !interface.is_interface() {
+                return Err(JavaError::new(
+                    "java/lang/IncompatibleClassChangeError",
+                    format!(
+                        "class {} can not implement {}, because it is not an interface",
+                        class.name, interface.name
+                    ),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// The method declared by `class` itself with the given name and descriptor
+    pub fn declared_method(
+        &self,
+        class: ClassId,
+        name: Symbol,
+        descriptor: Symbol,
+    ) -> Option<MethodId> {
+        self.classes[class].methods.iter().copied().find(|&id| {
+            let method = &self.methods[id];
+            method.name == name && method.descriptor == descriptor
+        })
+    }
+
+    /// All direct and indirect superinterfaces of a class or interface
+    pub fn all_superinterfaces(&self, class: ClassId) -> Vec<ClassId> {
+        let class = &self.classes[class];
+        if class.is_interface() {
+            superinterfaces(self, &class.interfaces)
+        } else {
+            class.secondary_supers.clone()
+        }
+    }
+
+    /// Shared by method and interface method resolution once the class (and for interfaces,
+    /// `Object`) has been searched
+    fn resolve_superinterface_method(
+        &self,
+        class: ClassId,
+        name: Symbol,
+        descriptor: Symbol,
+    ) -> JavaResult<MethodId> {
+        let interfaces = self.all_superinterfaces(class);
+        let candidates = maximally_specific_methods(self, &interfaces, name, descriptor);
+        let mut non_abstract = candidates
+            .iter()
+            .filter(|&&method| !self.methods[method].is_abstract());
+        if let (Some(&method), None) = (non_abstract.next(), non_abstract.next()) {
+            return Ok(method);
+        }
+
+        interfaces
+            .iter()
+            .find_map(|&interface| {
+                self.declared_method(interface, name, descriptor)
+                    .filter(|&method| {
+                        let method = &self.methods[method];
+                        !method.is_private() && !method.is_static()
+                    })
+            })
+            .ok_or_else(|| {
+                JavaError::new(
+                    "java/lang/NoSuchMethodError",
+                    format!("{}.{}{}", self.classes[class].name, name, descriptor),
+                )
+            })
     }
 
     pub fn resolve_method(
         &self,
         class: ClassId,
-        name: &str,
-        descriptor: &MethodDescriptor,
-    ) -> MethodId {
+        name: Symbol,
+        descriptor: Symbol,
+    ) -> JavaResult<MethodId> {
         // 5.4.3.3. Method Resolution
-        let class = &self.classes[class];
-        let method = class.methods.iter().copied().find(|&id| {
-            let method = &self.methods[id];
-            method.name == name && method.descriptor == *descriptor
-        });
+        if self.classes[class].is_interface() {
+            return Err(JavaError::new(
+                "java/lang/IncompatibleClassChangeError",
+                format!(
+                    "Found interface {}, but class was expected",
+                    self.classes[class].name
+                ),
+            ));
+        }
 
-        match method {
-            Some(method) => method,
-            None => {
-                if let Some(super_class) = class.super_class {
-                    self.resolve_method(super_class, name, descriptor)
-                } else {
-                    panic!("NoSuchMethodError");
-                }
+        let mut cur = Some(class);
+        while let Some(id) = cur {
+            if let Some(method) = self.signature_polymorphic_method(id, name) {
+                return Ok(method);
             }
+            if let Some(method) = self.declared_method(id, name, descriptor) {
+                return Ok(method);
+            }
+            cur = self.classes[id].super_class;
+        }
+        self.resolve_superinterface_method(class, name, descriptor)
+    }
+
+    /// The method of `class` with the given name, if it is the only one and it is signature
+    /// polymorphic, in which case it is found regardless of the descriptor
+    fn signature_polymorphic_method(&self, class: ClassId, name: Symbol) -> Option<MethodId> {
+        let class = &self.classes[class];
+        if class.name != Symbol::METHOD_HANDLE && class.name != Symbol::VAR_HANDLE {
+            return None;
+        }
+        let mut named = class
+            .methods
+            .iter()
+            .copied()
+            .filter(|&id| self.methods[id].name == name);
+        match (named.next(), named.next()) {
+            (Some(method), None) if self.methods[method].is_signature_polymorphic(class.name) => {
+                Some(method)
+            }
+            _ => None,
+        }
+    }
+
+    pub fn resolve_interface_method(
+        &self,
+        interface: ClassId,
+        name: Symbol,
+        descriptor: Symbol,
+    ) -> JavaResult<MethodId> {
+        // 5.4.3.4. Interface Method Resolution
+        if !self.classes[interface].is_interface() {
+            return Err(JavaError::new(
+                "java/lang/IncompatibleClassChangeError",
+                format!(
+                    "Found class {}, but interface was expected",
+                    self.classes[interface].name
+                ),
+            ));
+        }
+
+        if let Some(method) = self.declared_method(interface, name, descriptor) {
+            return Ok(method);
+        }
+        // Interfaces have no superclass, but inherit the public methods of Object
+        let object = self.classes[interface].super_class;
+        let object_method = object
+            .and_then(|object| self.declared_method(object, name, descriptor))
+            .filter(|&method| {
+                let method = &self.methods[method];
+                method.is_public() && !method.is_static()
+            });
+        if let Some(method) = object_method {
+            return Ok(method);
         }
+        self.resolve_superinterface_method(interface, name, descriptor)
+    }
+
+    pub fn resolve_field(
+        &self,
+        class: ClassId,
+        name: Symbol,
+        descriptor: Symbol,
+    ) -> JavaResult<FieldId> {
+        self.lookup_field(class, name, descriptor).ok_or_else(|| {
+            JavaError::new(
+                "java/lang/NoSuchFieldError",
+                format!("{}.{} {}", self.classes[class].name, name, descriptor),
+            )
+        })
     }
 
-    pub fn resolve_field(&self, class: ClassId, name: &str) -> FieldId {
-        // 5.4.3.2. Field Resolution
+    /// 5.4.3.2. Field Resolution
+    fn lookup_field(&self, class: ClassId, name: Symbol, descriptor: Symbol) -> Option<FieldId> {
         let class = &self.classes[class];
         let field = class.fields.iter().copied().find(|&id| {
             let field = &self.fields[id];
-            field.name == name
+            field.name == name && field.descriptor == descriptor
         });
+        field
+            .or_else(|| {
+                class
+                    .interfaces
+                    .iter()
+                    .find_map(|&interface| self.lookup_field(interface, name, descriptor))
+            })
+            .or_else(|| {
+                class
+                    .super_class
+                    .and_then(|super_class| self.lookup_field(super_class, name, descriptor))
+            })
+    }
+}
+
+fn size_and_alignment_of_field(field_desc: &FieldDescriptor) -> (usize, usize) {
+    let layout = layout_for_field(&field_desc.0);
+    (layout.size(), layout.align())
+}
+
+/// Instance fields are laid out in groups in this order, to keep padding to a minimum
+fn layout_group(field_desc: &FieldDescriptor) -> u8 {
+    match &field_desc.0 {
+        FieldType::BaseType(BaseType::J | BaseType::D) => 0,
+        FieldType::BaseType(BaseType::I | BaseType::F) => 1,
+        FieldType::BaseType(BaseType::S | BaseType::C) => 2,
+        FieldType::BaseType(BaseType::B | BaseType::Z) => 3,
+        FieldType::ObjectType(_) | FieldType::ArrayType(_) => 4,
+    }
+}
+
+/// Assigns offsets to instance fields. Fields go into gaps left by padding, including gaps in the
+/// superclass, before the object is made any larger.
+struct FieldLayout {
+    size: u32,
+    alignment: u8,
+    gaps: Vec<(u32, u32)>,
+}
 
-        match field {
-            Some(field) => field,
-            None => {
-                if let Some(super_class) = class.super_class {
-                    self.resolve_field(super_class, name)
-                } else {
-                    panic!("NoSuchFieldError");
+impl FieldLayout {
+    fn place(&mut self, field_size: u32, field_alignment: u32) -> u32 {
+        for idx in 0..self.gaps.len() {
+            let (start, len) = self.gaps[idx];
+            let offset = start.next_multiple_of(field_alignment);
+            let end = start + len;
+            if offset + field_size <= end {
+                self.gaps.remove(idx);
+                if offset + field_size < end {
+                    self.gaps
+                        .insert(idx, (offset + field_size, end - offset - field_size));
+                }
+                if start < offset {
+                    self.gaps.insert(idx, (start, offset - start));
                 }
+                return offset;
             }
         }
+
+        let offset = self.size.next_multiple_of(field_alignment);
+        if offset > self.size {
+            self.gaps.push((self.size, offset - self.size));
+        }
+        self.size = offset + field_size;
+        self.alignment = self.alignment.max(field_alignment as u8);
+        offset
     }
 }
 
-fn size_and_alignment_of<T>() -> (usize, usize) {
-    (std::mem::size_of::<T>(), std::mem::align_of::<T>())
+/// Collects the given interfaces and all of their superinterfaces, without duplicates
+fn superinterfaces(ma: &MethodArea, interfaces: &[ClassId]) -> Vec<ClassId> {
+    let mut all = Vec::new();
+    let mut stack: Vec<ClassId> = interfaces.iter().rev().copied().collect();
+    while let Some(interface) = stack.pop() {
+        if !all.contains(&interface) {
+            all.push(interface);
+            stack.extend(ma.classes[interface].interfaces.iter().rev());
+        }
+    }
+    all
 }
 
-fn size_and_alignment_of_field(field_desc: &FieldDescriptor) -> (usize, usize) {
-    match field_desc.0 {
-        FieldType::ArrayType(_) | FieldType::ObjectType(_) => size_and_alignment_of::<*const ()>(),
-        FieldType::BaseType(BaseType::B | BaseType::C) => size_and_alignment_of::<u8>(),
-        FieldType::BaseType(BaseType::D) => size_and_alignment_of::<f64>(),
-        FieldType::BaseType(BaseType::F) => size_and_alignment_of::<f32>(),
-        FieldType::BaseType(BaseType::I) => size_and_alignment_of::<u32>(),
-        FieldType::BaseType(BaseType::J) => size_and_alignment_of::<u64>(),
-        FieldType::BaseType(BaseType::S) => size_and_alignment_of::<u16>(),
-        FieldType::BaseType(BaseType::Z) => size_and_alignment_of::<bool>(),
+/// All interfaces implemented by a class, including those implemented by its superclasses
+fn all_interfaces(
+    ma: &MethodArea,
+    super_class: Option<ClassId>,
+    interfaces: &[ClassId],
+) -> Vec<ClassId> {
+    let mut all: Vec<ClassId> = super_class
+        .map(|super_class| ma.classes[super_class].secondary_supers.clone())
+        .unwrap_or_default();
+    for interface in superinterfaces(ma, interfaces) {
+        if !all.contains(&interface) {
+            all.push(interface);
+        }
     }
+    all
 }
 
-pub fn load_class_bootstrap(ma: &mut MethodArea, name: &str) -> ClassId {
-    if ma.class_map.contains_key(name) {
-        panic!("LinkageError");
-    }
+/// Finds the maximally-specific superinterface methods with the given name and descriptor
+/// (JVMS 5.4.3.3)
+pub fn maximally_specific_methods(
+    ma: &MethodArea,
+    interfaces: &[ClassId],
+    name: Symbol,
+    descriptor: Symbol,
+) -> Vec<MethodId> {
+    let candidates: Vec<MethodId> = interfaces
+        .iter()
+        .filter_map(|&interface| {
+            ma.classes[interface].methods.iter().copied().find(|&id| {
+                let method = &ma.methods[id];
+                method.name == name && method.descriptor == descriptor && method.is_virtual()
+            })
+        })
+        .collect();
 
-    let data = CONFIG
-        .classpath
+    candidates
         .iter()
-        .find_map(|classpath| {
-            let mut path = PathBuf::from(classpath);
-            path.push(name);
-            path.set_extension("class");
-            if path.exists() {
-                println!("Loading class at {}", path.display());
-                Some(fs::read(path).unwrap())
-            } else {
-                None
+        .copied()
+        .filter(|&candidate| {
+            let interface = ma.methods[candidate].defining_class;
+            !candidates.iter().any(|&other| {
+                let other = ma.methods[other].defining_class;
+                other != interface
+                    && superinterfaces(ma, &ma.classes[other].interfaces).contains(&interface)
+            })
+        })
+        .collect()
+}
+
+/// Selects the default method inherited from `interfaces`, falling back to an abstract method if
+/// there isn't a single non-abstract maximally-specific method.
+fn select_default_method(
+    ma: &MethodArea,
+    interfaces: &[ClassId],
+    name: Symbol,
+    descriptor: Symbol,
+) -> Option<MethodId> {
+    let methods = maximally_specific_methods(ma, interfaces, name, descriptor);
+    let mut non_abstract = methods.iter().filter(|&&m| !ma.methods[m].is_abstract());
+    match (non_abstract.next(), non_abstract.next()) {
+        (Some(&method), None) => Some(method),
+        _ => methods.first().copied(),
+    }
+}
+
+/// Builds the vtable for a class by extending the vtable of its superclass (JVMS 5.4.5). Also
+/// assigns vtable indices to the class's own methods.
+fn build_vtable(
+    ma: &mut MethodArea,
+    class_name: Symbol,
+    super_class: Option<ClassId>,
+    methods: &[MethodId],
+    interfaces: &[ClassId],
+) -> JavaResult<Vec<MethodId>> {
+    let mut vtable = super_class
+        .map(|super_class| ma.classes[super_class].vtable.clone())
+        .unwrap_or_default();
+    let package = class_name.package();
+
+    for &id in methods {
+        let method = &ma.methods[id];
+        if !method.is_virtual() {
+            continue;
+        }
+
+        let mut vtable_index = None;
+        let mut overridden_loaders = Vec::new();
+        for (idx, entry) in vtable.iter_mut().enumerate() {
+            let overridden = &ma.methods[*entry];
+            if overridden.name != method.name || overridden.descriptor != method.descriptor {
+                continue;
+            }
+            // Package-private methods can only be overridden from within the same package
+            if overridden.is_package_private()
+                && ma.classes[overridden.defining_class].package_name() != package
+            {
+                continue;
             }
+            if overridden.is_final() {
+                return Err(JavaError::new(
+                    "java/lang/VerifyError",
+                    format!("{}.{} overrides final method", class_name, method.name),
+                ));
+            }
+            overridden_loaders.push(ma.classes[overridden.defining_class].defining_loader);
+            *entry = id;
+            vtable_index.get_or_insert(idx);
+        }
+
+        // Overriding across loaders requires the types in the descriptor to be the same classes
+        let (loader, descriptor) = (
+            ma.classes[method.defining_class].defining_loader,
+            method.descriptor,
+        );
+        for overridden_loader in overridden_loaders {
+            ma.add_descriptor_constraints(descriptor, loader, overridden_loader)?;
+        }
+
+        let vtable_index = vtable_index.unwrap_or_else(|| {
+            vtable.push(id);
+            vtable.len() - 1
+        });
+        ma.methods[id].vtable_index = Some(vtable_index);
+    }
+
+    // Methods inherited from interfaces get an entry as well so that subclasses overriding them
+    // share a slot
+    for &interface in interfaces {
+        for &id in &ma.classes[interface].methods {
+            let method = &ma.methods[id];
+            if !method.is_virtual() {
+                continue;
+            }
+            let exists = vtable.iter().any(|&entry| {
+                let entry = &ma.methods[entry];
+                entry.name == method.name && entry.descriptor == method.descriptor
+            });
+            if !exists {
+                let selected =
+                    select_default_method(ma, interfaces, method.name, method.descriptor);
+                vtable.push(selected.unwrap_or(id));
+            }
+        }
+    }
+
+    Ok(vtable)
+}
+
+/// Builds the itables for a class by selecting each interface method from the vtable (JVMS 5.4.6)
+fn build_itables(
+    ma: &MethodArea,
+    vtable: &[MethodId],
+    interfaces: &[ClassId],
+) -> Vec<(ClassId, Vec<MethodId>)> {
+    interfaces
+        .iter()
+        .map(|&interface| {
+            let itable = ma.classes[interface]
+                .methods
+                .iter()
+                .map(|&id| {
+                    let method = &ma.methods[id];
+                    if !method.is_virtual() {
+                        return id;
+                    }
+                    // Later entries come from more derived classes
+                    vtable
+                        .iter()
+                        .rev()
+                        .copied()
+                        .find(|&entry| {
+                            let entry = &ma.methods[entry];
+                            entry.name == method.name && entry.descriptor == method.descriptor
+                        })
+                        .unwrap_or(id)
+                })
+                .collect();
+            (interface, itable)
         })
-        .unwrap_or_else(|| panic!("ClassNotFoundException: {}", name));
-    let class_file = ClassFile::from_bytes((&data, 0))
-        .expect("ClassFormatError")
+        .collect()
+}
+
+/// Read and parse a class file from the bootstrap class path
+fn find_class_bootstrap(name: Symbol) -> JavaResult<ClassFile> {
+    let data = class_path()
+        .find_class(&name.as_str())
+        .ok_or_else(|| JavaError::new("java/lang/NoClassDefFoundError", name.to_string()))?;
+    parse_class_file(&data)
+}
+
+/// Classes the VM uses while holding the method area, to create strings, mirrors, and array
+/// classes
+const PRELOADED_CLASSES: [&str; 6] = [
+    "java/lang/Object",
+    "java/lang/Cloneable",
+    "java/io/Serializable",
+    "java/lang/String",
+    "java/lang/Class",
+    "java/lang/Throwable",
+];
+
+/// Load the classes the VM needs before any Java code runs
+pub fn preload_classes() -> JavaResult<()> {
+    for name in PRELOADED_CLASSES {
+        resolve_class(ClassLoader::Bootstrap, Symbol::intern(name))?;
+    }
+    Ok(())
+}
+
+/// Load a class with the bootstrap loader without holding the method area while its class file is
+/// parsed and its superclass and superinterfaces are loaded, so that threads can load unrelated
+/// classes in parallel
+fn load_class_bootstrap_parallel(name: Symbol) -> JavaResult<ClassId> {
+    let key = (ClassLoader::Bootstrap, name);
+    let _placeholder = loop {
+        if let Some(placeholder) = Placeholder::claim(key, true)? {
+            break placeholder;
+        }
+    };
+    // The class may have been loaded while waiting
+    if let Some(&id) = method_area().class_map.get(&key) {
+        return Ok(id);
+    }
+    let class_file = find_class_bootstrap(name)?;
+
+    let super_class_id = match class_file.super_class {
+        0 => None,
+        idx => Some(resolve_class(
+            ClassLoader::Bootstrap,
+            class_file.constant_pool.class_symbol(idx),
+        )?),
+    };
+    let mut interfaces = Vec::new();
+    for &interface in &class_file.interfaces {
+        let interface_name = class_file.constant_pool.class_symbol(interface);
+        interfaces.push(resolve_class(ClassLoader::Bootstrap, interface_name)?);
+    }
+
+    let mut ma = method_area();
+    // `define_class` doesn't wait for placeholders, so it may have defined the class meanwhile
+    if let Some(&id) = ma.class_map.get(&key) {
+        return Ok(id);
+    }
+    create_class(
+        &mut ma,
+        ClassLoader::Bootstrap,
+        class_file,
+        super_class_id,
+        interfaces,
+    )
+}
+
+/// Define a class from bytes on behalf of a class loader. Its superclass and superinterfaces are
+/// resolved through that loader.
+pub fn define_class(loader: ClassLoader, data: &[u8]) -> JavaResult<ClassId> {
+    let class_file = parse_class_file(data)?;
+    // Loaders synchronize their own definitions, so this only detects circularity
+    let name = class_file.constant_pool.class_symbol(class_file.this_class);
+    let _placeholder = Placeholder::claim((loader, name), false)?;
+    let super_class_id = match class_file.super_class {
+        0 => None,
+        idx => Some(resolve_class(
+            loader,
+            class_file.constant_pool.class_symbol(idx),
+        )?),
+    };
+    let mut interfaces = Vec::new();
+    for &interface in &class_file.interfaces {
+        let interface_name = class_file.constant_pool.class_symbol(interface);
+        interfaces.push(resolve_class(loader, interface_name)?);
+    }
+    create_class(
+        &mut method_area(),
+        loader,
+        class_file,
+        super_class_id,
+        interfaces,
+    )
+}
+
+/// Resolve a class by name with `loader` as the initiating loader (JVMS 5.3). User-defined
+/// loaders are asked for the class through `ClassLoader.loadClass`.
+pub fn resolve_class(loader: ClassLoader, name: Symbol) -> JavaResult<ClassId> {
+    if let Some(&id) = method_area().class_map.get(&(loader, name)) {
+        return Ok(id);
+    }
+    let id = if name.is_array() {
+        // Array classes are created by the JVM, only their component class is loaded by `loader`
+        let FieldType::ArrayType(arr_ty) = FieldDescriptor::read(&name.as_str()).0 else {
+            unreachable!()
+        };
+        let component = match arr_ty.0 .0 {
+            FieldType::BaseType(base_type) => Class::primitive(&base_type.to_string()),
+            elem_ty @ FieldType::ArrayType(_) => {
+                resolve_class(loader, Symbol::intern(&elem_ty.descriptor()))?
+            }
+            FieldType::ObjectType(obj_ty) => {
+                resolve_class(loader, Symbol::intern(&obj_ty.class_name))?
+            }
+        };
+        method_area().array_class_of(component)
+    } else if let ClassLoader::UserDefined(loader_obj) = loader {
+        let class_loader_class = resolve_class(
+            ClassLoader::Bootstrap,
+            Symbol::intern("java/lang/ClassLoader"),
+        )?;
+        let loader_class = heap().get_obj_class(loader_obj);
+        let ma = method_area();
+        let load_class = ma
+            .resolve_method(
+                class_loader_class,
+                Symbol::intern("loadClass"),
+                Symbol::intern("(Ljava/lang/String;)Ljava/lang/Class;"),
+            )
+            .and_then(|method| Class::select_method(&ma, loader_class, method))?;
+        drop(ma);
+
+        let binary_name = name.as_str().replace('/', ".");
+        let name_obj = heap().create_string(&mut method_area(), &binary_name);
+        let args = [
+            Value::Object(Some(loader_obj)),
+            Value::Object(Some(name_obj)),
+        ];
+        let class_obj = Thread::invoke(load_class, &args)
+            .and_then(Value::object)
+            .ok_or_else(|| JavaError::new("java/lang/NoClassDefFoundError", name.to_string()))?;
+
+        let ma = method_area();
+        let id = ma.class_objs[&class_obj];
+        if ma.classes[id].name != name {
+            return Err(JavaError::new(
+                "java/lang/NoClassDefFoundError",
+                format!("{} (wrong name: {})", name, ma.classes[id].name),
+            ));
+        }
+        id
+    } else {
+        return load_class_bootstrap_parallel(name);
+    };
+    method_area().record_class(loader, name, id)?;
+    Ok(id)
+}
+
+/// Parse a class file and check that it's one this JVM supports
+pub fn parse_class_file(data: &[u8]) -> JavaResult<ClassFile> {
+    let mut class_file = ClassFile::from_bytes((data, 0))
+        .map_err(|err| JavaError::new("java/lang/ClassFormatError", err.to_string()))?
         .1;
+    class_file.constant_pool.intern_symbols()?;
 
-    assert!(class_file.magic == 0xCAFEBABE);
+    if class_file.magic != 0xCAFEBABE {
+        return Err(JavaError::new(
+            "java/lang/ClassFormatError",
+            format!("incompatible magic value {:#x}", class_file.magic),
+        ));
+    }
     // Major version 66 corresponds to Java 22
-    assert!((45..=66).contains(&class_file.major_version));
+    if !(45..=66).contains(&class_file.major_version) {
+        return Err(JavaError::new(
+            "java/lang/UnsupportedClassVersionError",
+            format!(
+                "{} has class file version {}.{}",
+                class_file.constant_pool.class_symbol(class_file.this_class),
+                class_file.major_version,
+                class_file.minor_version
+            ),
+        ));
+    }
+    Ok(class_file)
+}
+
+/// Create a class from its parsed class file once its superclass and superinterfaces have been
+/// resolved, recording `loader` as its defining loader
+pub fn create_class(
+    ma: &mut MethodArea,
+    loader: ClassLoader,
+    class_file: ClassFile,
+    super_class_id: Option<ClassId>,
+    interfaces: Vec<ClassId>,
+) -> JavaResult<ClassId> {
+    let name = class_file.constant_pool.class_symbol(class_file.this_class);
+    if ma.class_map.contains_key(&(loader, name)) {
+        return Err(JavaError::new(
+            "java/lang/LinkageError",
+            format!("duplicate class definition for {}", name),
+        ));
+    }
 
     for attribute in class_file.attributes {
         let name = class_file
@@ -164,38 +1314,61 @@ pub fn load_class_bootstrap(ma: &mut MethodArea, name: &str) -> ClassId {
         println!("Class Attribute: {}", name);
     }
 
-    let super_class_id = if class_file.super_class > 0 {
-        let super_name = class_file.constant_pool.class_name(class_file.super_class);
-        Some(ma.resolve_class(&super_name))
-    } else {
-        // Super class should only be None for Object
-        None
-    };
-    let name = class_file.constant_pool.class_name(class_file.this_class);
-
-    let (base_size, base_alignment) = if let Some(super_class_id) = super_class_id {
+    let mut layout = if let Some(super_class_id) = super_class_id {
         let super_class = &ma.classes[super_class_id];
-        (super_class.size, super_class.alignment)
+        FieldLayout {
+            size: super_class.size,
+            alignment: super_class.alignment,
+            gaps: super_class.field_gaps.clone(),
+        }
     } else {
-        (
-            std::mem::size_of::<Object>() as u32,
-            std::mem::align_of::<Object>() as u8,
-        )
+        FieldLayout {
+            size: std::mem::size_of::<Object>() as u32,
+            alignment: MIN_OBJECT_ALIGN,
+            gaps: Vec::new(),
+        }
     };
 
-    let mut interfaces = Vec::new();
-    for interface in class_file.interfaces {
-        let interface_name = class_file.constant_pool.class_name(interface);
-        interfaces.push(ma.resolve_class(&interface_name));
+    // Check the fields before anything is allocated, so that nothing needs to be freed on error
+    for field in &class_file.fields {
+        for attribute in &field.attributes {
+            let attr_name = class_file
+                .constant_pool
+                .utf8(attribute.attribute_name_index);
+            println!("Field Attribute: {}", attr_name);
+            if attr_name != "ConstantValue" {
+                continue;
+            }
+            let cva = attribute.constant_value();
+            let cp_info = &class_file.constant_pool.table[cva.constantvalue_index as usize - 1];
+            if !matches!(
+                cp_info,
+                CPInfo::Integer { .. }
+                    | CPInfo::Float { .. }
+                    | CPInfo::Double { .. }
+                    | CPInfo::Long { .. }
+                    | CPInfo::String { .. }
+            ) {
+                return Err(JavaError::new(
+                    "java/lang/ClassFormatError",
+                    format!(
+                        "Invalid ConstantValue attribute of field {}.{}",
+                        name,
+                        class_file.constant_pool.symbol(field.name_index)
+                    ),
+                ));
+            }
+        }
     }
 
     // No new classes can be loaded after this point
-    let class_id = ma.classes.next_id();
+    let class_id = ma.next_class_id();
 
     let mut methods = Vec::new();
+    let name_str = name.as_str();
     for method in class_file.methods {
-        let name = class_file.constant_pool.utf8(method.name_index);
-        let descriptor = class_file.constant_pool.utf8(method.descriptor_index);
+        let method_name = class_file.constant_pool.symbol(method.name_index);
+        let descriptor = class_file.constant_pool.symbol(method.descriptor_index);
         let mut code = None;
         for attr in method.attributes {
             let attr_name = class_file.constant_pool.utf8(attr.attribute_name_index);
@@ -203,144 +1376,475 @@ pub fn load_class_bootstrap(ma: &mut MethodArea, name: &str) -> ClassId {
                 code = Some(Arc::new(attr.code()));
             }
         }
-        let id = ma.methods.alloc(Method {
+        let id = ma.alloc_method(Method {
             defining_class: class_id,
-            name,
-            descriptor: MethodDescriptor::read(&descriptor),
+            name: method_name,
+            descriptor,
+            parsed_descriptor: MethodDescriptor::read(&descriptor.as_str()),
             access_flags: method.access_flags,
             code,
+            inline_caches: HashMap::new(),
+            vtable_index: None,
+            itable_index: None,
+            intrinsic: CONFIG
+                .use_intrinsics
+                .then(|| intrinsics::lookup(&name_str, &method_name.as_str(), &descriptor.as_str()))
+                .flatten(),
         });
         methods.push(id);
     }
 
-    let mut size = base_size;
-    let mut alignment = base_alignment;
+    let is_interface = class_file.access_flags & ACC_INTERFACE != 0;
+    let all_interfaces = all_interfaces(ma, super_class_id, &interfaces);
 
     let mut fields = Vec::new();
     for field in class_file.fields {
-        let field_name = class_file.constant_pool.utf8(field.name_index);
-        let descriptor = class_file.constant_pool.utf8(field.descriptor_index);
-        let descriptor = FieldDescriptor::read(&descriptor);
+        let field_name = class_file.constant_pool.symbol(field.name_index);
+        let descriptor_sym = class_file.constant_pool.symbol(field.descriptor_index);
+        let descriptor = FieldDescriptor::read(&descriptor_sym.as_str());
 
-        let mut constant_val = None;
-        for attribute in field.attributes {
-            let attr_name = class_file
-                .constant_pool
-                .utf8(attribute.attribute_name_index);
-            println!("Field Attribute: {}", attr_name);
-            if attr_name == "ConstantValue" {
-                let cva = attribute.constant_value();
-                let cp_info = &class_file.constant_pool.table[cva.constantvalue_index as usize - 1];
-                let val = match cp_info {
-                    CPInfo::Integer { val } => Value::Int(*val).store_ty(&descriptor.0),
-                    CPInfo::Float { val } => Value::Float(*val),
-                    CPInfo::Double { val } => Value::Double(*val),
-                    CPInfo::Long { val } => Value::Long(*val),
-                    CPInfo::String { string_index } => {
-                        let str = class_file.constant_pool.utf8(*string_index);
-                        let str_obj = heap().create_string(ma, &str);
-                        Value::Object(Some(str_obj))
-                    }
-                    _ => panic!("Field ConstantValue attribute has incorrect type"),
-                };
-                constant_val = Some(val);
-            }
-        }
+        let constant_value = field
+            .attributes
+            .iter()
+            .find(|attribute| {
+                class_file
+                    .constant_pool
+                    .utf8(attribute.attribute_name_index)
+                    == "ConstantValue"
+            })
+            .map(|attribute| attribute.constant_value().constantvalue_index);
 
-        let is_static = field.access_flags & fields::acc::STATIC != 0;
-        let backing = if is_static {
-            FieldBacking::StaticValue(
-                constant_val.unwrap_or_else(|| Value::default_for_ty(&descriptor.0)),
-            )
+        // Static fields get their storage when the class is prepared. Instance fields get their
+        // offsets below.
+        let backing = if field.access_flags & fields::acc::STATIC != 0 {
+            FieldBacking::Unprepared
         } else {
-            let (field_size, field_alignment) = size_and_alignment_of_field(&descriptor);
-            let padding = size % field_alignment as u32;
-            size += padding;
-            let offset = size;
-            size += field_size as u32;
-            alignment = alignment.max(field_alignment as u8);
-            FieldBacking::Instance(offset)
+            FieldBacking::Instance(0)
         };
 
-        let id = ma.fields.alloc(Field {
+        let id = ma.alloc_field(Field {
             name: field_name,
             defining_class: class_id,
             access_flags: field.access_flags,
-            descriptor,
+            descriptor: descriptor_sym,
+            parsed_descriptor: descriptor,
             backing,
+            constant_value,
         });
         fields.push(id);
     }
 
-    let mut references = HashMap::new();
-    for (i, entry) in class_file.constant_pool.table.iter().enumerate() {
-        match entry {
-            CPInfo::Fieldref { .. }
-            | CPInfo::Methodref { .. }
-            | CPInfo::InterfaceMethodref { .. }
-            | CPInfo::Class { .. } => {
-                references.insert(i as u16 + 1, Reference::Unresolved);
-            }
-            _ => {}
-        }
+    let mut instance_fields: Vec<FieldId> = fields
+        .iter()
+        .copied()
+        .filter(|&id| !ma.fields[id].is_static())
+        .collect();
+    instance_fields.sort_by_key(|&id| layout_group(&ma.fields[id].parsed_descriptor));
+    for id in instance_fields {
+        let (field_size, field_alignment) =
+            size_and_alignment_of_field(&ma.fields[id].parsed_descriptor);
+        let offset = layout.place(field_size as u32, field_alignment as u32);
+        ma.fields[id].backing = FieldBacking::Instance(offset);
+    }
+
+    let mut primary_supers = super_class_id
+        .map(|super_class| ma.classes[super_class].primary_supers.clone())
+        .unwrap_or_default();
+    if !is_interface {
+        primary_supers.push(class_id);
     }
 
     let class = Class {
-        initialized: false,
-        defining_loader: ClassLoader::Bootstrap,
-        references,
+        init_state: InitState::Loaded,
+        defining_loader: loader,
         class_obj: None,
-        name: name.clone(),
+        name,
+        package: name.package(),
         super_class: super_class_id,
         interfaces,
         methods,
         fields,
         access_flags: class_file.access_flags,
-        constant_pool: class_file.constant_pool,
+        constant_pool: Arc::new(RuntimeConstantPool::new(&class_file.constant_pool, loader)),
         elem_ty: None,
-        alignment,
-        size,
+        component_class: None,
+        primary_supers,
+        secondary_supers: all_interfaces,
+        vtable: Vec::new(),
+        itables: Vec::new(),
+        alignment: layout.alignment,
+        size: layout.size,
+        field_gaps: layout.gaps,
     };
 
-    let id = ma.classes.alloc(class);
-    ma.class_map.insert(name, id);
-
-    // See if this class is an interface of itself
-    let class = &ma.classes[id];
-    for &interface in &class.interfaces {
-        if interface == id {
-            panic!("ClassCircularityError");
-        }
+    let id = ma.alloc_class(class);
+    if let Err(err) = ma.record_class(loader, name, id) {
+        ma.free_class(id);
+        return Err(err);
     }
+    Ok(id)
+}
 
+fn load_primitive_class(ma: &mut MethodArea, name: Symbol) -> ClassId {
+    assert!(
+        matches!(
+            name.as_str().as_ref(),
+            "byte" | "short" | "int" | "long" | "char" | "float" | "double" | "boolean" | "void"
+        ),
+        "{} is not a primitive type",
+        name
+    );
+    let class = Class {
+        init_state: InitState::Initialized,
+        defining_loader: ClassLoader::Bootstrap,
+        class_obj: None,
+        name,
+        package: name.package(),
+        super_class: None,
+        interfaces: Vec::new(),
+        methods: Vec::new(),
+        fields: Vec::new(),
+        access_flags: ACC_PUBLIC | ACC_FINAL | ACC_ABSTRACT,
+        constant_pool: Default::default(),
+        elem_ty: None,
+        component_class: None,
+        primary_supers: vec![ma.next_class_id()],
+        secondary_supers: Vec::new(),
+        vtable: Vec::new(),
+        itables: Vec::new(),
+        size: 0,
+        alignment: MIN_OBJECT_ALIGN,
+        field_gaps: Vec::new(),
+    };
+
+    let id = ma.alloc_class(class);
+    ma.primitive_classes.insert(name, id);
     id
 }
 
-pub fn load_arr_class_bootstrap(ma: &mut MethodArea, elem_ty: FieldType) -> ClassId {
-    let name = format!("{}[]", elem_ty);
-    let super_class_id = ma.resolve_class("java/lang/Object");
+/// Create the array class for a component class. It has the same defining loader as the
+/// component class.
+fn load_arr_class(ma: &mut MethodArea, component_class: ClassId) -> ClassId {
+    let component = &ma.classes[component_class];
+    let elem_ty = if let Some(elem_ty) = &component.elem_ty {
+        FieldType::ArrayType(ArrayType(Box::new(ComponentType(elem_ty.clone()))))
+    } else if ma
+        .primitive_classes
+        .values()
+        .any(|&id| id == component_class)
+    {
+        let desc = match component.name.as_str().as_ref() {
+            "byte" => "B",
+            "char" => "C",
+            "double" => "D",
+            "float" => "F",
+            "int" => "I",
+            "long" => "J",
+            "short" => "S",
+            "boolean" => "Z",
+            _ => panic!("array of {}", component.name),
+        };
+        FieldDescriptor::read(desc).0
+    } else {
+        FieldType::ObjectType(ObjectType {
+            class_name: component.name.to_string(),
+        })
+    };
+    let loader = component.defining_loader;
+    let name = Symbol::intern(
+        &FieldType::ArrayType(ArrayType(Box::new(ComponentType(elem_ty.clone())))).descriptor(),
+    );
+    let super_class_id = ma
+        .resolve_class(Symbol::intern("java/lang/Object"))
+        .unwrap();
+    // Every array type implements these (JLS 4.10.3)
+    let interfaces = ["java/lang/Cloneable", "java/io/Serializable"]
+        .map(|name| ma.resolve_class(Symbol::intern(name)).unwrap())
+        .to_vec();
+    // Arrays dispatch through a copy of Object's vtable, which is only built once it's linked
+    ma.link_class(super_class_id)
+        .unwrap_or_else(|err| err.throw());
+    let vtable = ma.classes[super_class_id].vtable.clone();
+    let itables = build_itables(ma, &vtable, &interfaces);
     let super_class = &ma.classes[super_class_id];
+    let primary_supers = vec![super_class_id, ma.next_class_id()];
     let class = Class {
-        initialized: false,
-        defining_loader: ClassLoader::Bootstrap,
-        references: HashMap::new(),
+        // Array classes have nothing to initialize
+        init_state: InitState::Initialized,
+        defining_loader: loader,
         class_obj: None,
-        name: name.clone(),
+        name,
+        package: name.package(),
         super_class: Some(super_class_id),
-        interfaces: vec![],
-        methods: vec![], // FIXME: this should have clone
-        fields: vec![],  // FIXME: this should have length
+        interfaces: interfaces.clone(),
+        // `clone` and `length` are resolved to Object.clone and handled by arraylength
+        methods: vec![],
+        fields: vec![],
         access_flags: Default::default(),
         constant_pool: Default::default(),
-        elem_ty: Some(elem_ty.clone()),
+        elem_ty: Some(elem_ty),
+        component_class: Some(component_class),
+        primary_supers,
+        secondary_supers: interfaces,
+        vtable,
+        itables,
         size: super_class.size,
         alignment: super_class.alignment,
+        field_gaps: Vec::new(),
     };
     println!("Created array class: {}", name);
 
-    let id = ma.classes.alloc(class);
-    ma.class_map.insert(name, id);
-    ma.array_classes.insert(elem_ty, id);
+    let id = ma.alloc_class(class);
+    ma.class_map.insert((loader, name), id);
+    ma.array_classes.insert(component_class, id);
 
     id
 }
+
+#[test]
+fn unload_unreachable_loaders_test() {
+    use crate::class_file::methods::acc;
+    use crate::heap::Heap;
+    let assemble = |name, method| {
+        crate::class_file::assemble(
+            name,
+            None,
+            &[(0, "next", "Ljava/lang/Object;")],
+            &[(acc::PUBLIC, "run", "()V", Some(&[0xb1])), method],
+        )
+    };
+    let new = |class| {
+        let mut ma = method_area();
+        ma.link_class(class).unwrap();
+        Heap::new_object(class, ma.classes[class].instance_layout())
+    };
+    let holder_class = define_class(
+        ClassLoader::Bootstrap,
+        &assemble("test/Holder", (acc::STATIC, "main", "()V", Some(&[0xb1]))),
+    )
+    .unwrap();
+    let loader = ClassLoader::UserDefined(new(holder_class));
+    // Symbols that only the plugin class uses
+    let plugin_symbols = ["test/UnloadedPlugin", "reload", "(Ltest/UnloadedPlugin;)V"];
+    let interned = || {
+        plugin_symbols
+            .iter()
+            .filter(|str| Symbol::lookup(str).is_some())
+            .count()
+    };
+    let name = Symbol::intern("test/UnloadedPlugin");
+    let reload = (acc::PUBLIC, "reload", "(Ltest/UnloadedPlugin;)V", None);
+    let plugin = define_class(loader, &assemble("test/UnloadedPlugin", reload)).unwrap();
+
+    // An instance of one of its classes keeps the loader alive
+    let holder = new(holder_class);
+    let instance = new(plugin);
+    let next = method_area()
+        .resolve_field(
+            holder_class,
+            Symbol::intern("next"),
+            Symbol::intern("Ljava/lang/Object;"),
+        )
+        .unwrap();
+    heap().store_field(&method_area(), holder, next, Value::Object(Some(instance)));
+    method_area().unload_unreachable_loaders(vec![holder]);
+    assert_eq!(method_area().class_map.get(&(loader, name)), Some(&plugin));
+    assert_eq!(interned(), 3);
+
+    method_area().unload_unreachable_loaders(Vec::new());
+    let ma = method_area();
+    assert!(!ma.class_map.contains_key(&(loader, name)));
+    assert!(ma.live_classes().any(|(id, _)| id == holder_class));
+    // The plugin's own symbols are reclaimed, but not those the holder still uses
+    assert_eq!(interned(), 0);
+    assert!(Symbol::lookup("next").is_some());
+    assert!(Symbol::lookup("run").is_some());
+}
+
+#[test]
+fn field_layout_test() {
+    let mut layout = FieldLayout {
+        size: 12,
+        alignment: 8,
+        gaps: Vec::new(),
+    };
+    assert_eq!(layout.place(8, 8), 16);
+    assert_eq!(layout.place(2, 2), 12);
+    assert_eq!(layout.place(1, 1), 14);
+    assert_eq!(layout.place(1, 1), 15);
+    assert_eq!(layout.place(4, 4), 24);
+    assert_eq!(layout.place(1, 1), 28);
+    assert!(layout.gaps.is_empty());
+    assert_eq!(layout.size, 29);
+}
+
+#[test]
+fn placeholder_test() {
+    let key = (ClassLoader::Bootstrap, Symbol::intern("test/Placeholder"));
+    let placeholder = Placeholder::claim(key, true).unwrap().unwrap();
+    let err = Placeholder::claim(key, true).err().unwrap();
+    assert_eq!(err.class_name, "java/lang/ClassCircularityError");
+
+    let waiter = thread::spawn(move || Placeholder::claim(key, true).unwrap().is_none());
+    // Not waiting lets another thread load the class alongside the owner
+    let other = thread::spawn(move || Placeholder::claim(key, false).unwrap().is_some());
+    assert!(other.join().unwrap());
+    wait_until_blocked(waiter.thread().id());
+    drop(placeholder);
+    assert!(waiter.join().unwrap());
+    assert!(!placeholders().owners.contains_key(&key));
+}
+
+#[cfg(test)]
+fn wait_until_blocked(thread: ThreadId) {
+    while !placeholders().waiting.contains_key(&thread) {
+        thread::yield_now();
+    }
+}
+
+#[test]
+fn placeholder_cycle_test() {
+    // Each thread loads a class whose superclass the other is loading
+    let x = (ClassLoader::Bootstrap, Symbol::intern("test/CycleX"));
+    let y = (ClassLoader::Bootstrap, Symbol::intern("test/CycleY"));
+    let placeholder = Placeholder::claim(x, true).unwrap().unwrap();
+    let other = thread::spawn(move || {
+        let _placeholder = Placeholder::claim(y, true).unwrap().unwrap();
+        Placeholder::claim(x, true).unwrap().is_none()
+    });
+    wait_until_blocked(other.thread().id());
+    let err = Placeholder::claim(y, true).err().unwrap();
+    assert_eq!(err.class_name, "java/lang/ClassCircularityError");
+    assert!(!placeholders().waiting.contains_key(&thread::current().id()));
+    drop(placeholder);
+    assert!(other.join().unwrap());
+    assert!(!placeholders().owners.contains_key(&x));
+    assert!(!placeholders().owners.contains_key(&y));
+}
+
+#[test]
+fn signature_polymorphic_test() {
+    use crate::class_file::methods::acc;
+    let polymorphic = acc::PUBLIC | acc::FINAL | acc::NATIVE | acc::VARARGS;
+    let data = crate::class_file::assemble(
+        "java/lang/invoke/VarHandle",
+        None,
+        &[],
+        &[
+            (
+                polymorphic,
+                "get",
+                "([Ljava/lang/Object;)Ljava/lang/Object;",
+                None,
+            ),
+            (
+                acc::PUBLIC | acc::VARARGS,
+                "of",
+                "([Ljava/lang/Object;)V",
+                Some(&[0xb1]),
+            ),
+        ],
+    );
+    let var_handle = define_class(ClassLoader::Bootstrap, &data).unwrap();
+    let ma = method_area();
+    let get = ma
+        .resolve_method(var_handle, Symbol::intern("get"), Symbol::intern("(I)J"))
+        .unwrap();
+    assert_eq!(ma.methods[get].name, "get");
+    // Only native varargs methods are signature polymorphic
+    let err = ma
+        .resolve_method(var_handle, Symbol::intern("of"), Symbol::intern("(I)V"))
+        .unwrap_err();
+    assert_eq!(err.class_name, "java/lang/NoSuchMethodError");
+}
+
+#[test]
+fn create_class_test() {
+    use crate::class_file::fields::acc;
+    let parse = || {
+        let data =
+            crate::class_file::assemble("test/Plugin", None, &[(acc::STATIC, "count", "I")], &[]);
+        parse_class_file(&data).unwrap()
+    };
+    let mut ma = MethodArea::default();
+    let loader =
+        ClassLoader::UserDefined(unsafe { ObjectRef::from_ptr(std::ptr::dangling_mut()) }.unwrap());
+    let plugin = create_class(&mut ma, loader, parse(), None, Vec::new()).unwrap();
+
+    // Static fields get their storage when the class is prepared
+    let count = ma.classes[plugin].fields[0];
+    assert!(matches!(ma.fields[count].backing, FieldBacking::Unprepared));
+    ma.link_class(plugin).unwrap();
+    assert!(matches!(
+        ma.fields[count].backing,
+        FieldBacking::StaticValue(Value::Int(0))
+    ));
+
+    // A definition that violates a loader constraint doesn't keep its slots
+    ma.add_loader_constraint(
+        Symbol::intern("test/Plugin"),
+        ClassLoader::Bootstrap,
+        loader,
+    )
+    .unwrap();
+    let next = ma.next_class_id();
+    let err = create_class(&mut ma, ClassLoader::Bootstrap, parse(), None, Vec::new()).unwrap_err();
+    assert_eq!(err.class_name, "java/lang/LinkageError");
+    assert_eq!(ma.next_class_id(), next);
+    assert_eq!(ma.free_fields.len(), 1);
+}
+
+#[test]
+fn array_vtable_test() {
+    use crate::class_file::methods::acc;
+    fn define(
+        ma: &mut MethodArea,
+        name: &str,
+        methods: &[(u16, &str, &str, Option<&[u8]>)],
+    ) -> ClassId {
+        let data = crate::class_file::assemble(name, None, &[], methods);
+        let class_file = parse_class_file(&data).unwrap();
+        create_class(ma, ClassLoader::Bootstrap, class_file, None, Vec::new()).unwrap()
+    }
+    let mut ma = MethodArea::default();
+    // aconst_null, areturn
+    let to_string = (
+        acc::PUBLIC,
+        "toString",
+        "()Ljava/lang/String;",
+        Some(&[0x01, 0xb0][..]),
+    );
+    let object = define(&mut ma, "java/lang/Object", &[to_string]);
+    for name in ["java/lang/Cloneable", "java/io/Serializable"] {
+        let interface = define(&mut ma, name, &[]);
+        ma.classes[interface].access_flags |= ACC_INTERFACE | ACC_ABSTRACT;
+    }
+
+    // Creating an array class before Object is linked still gives it Object's methods
+    assert_eq!(ma.classes[object].init_state, InitState::Loaded);
+    let int = ma.resolve_primitive_class(Symbol::intern("int"));
+    let int_array = ma.array_class_of(int);
+    let to_string = ma.classes[object].methods[0];
+    assert_eq!(ma.classes[int_array].vtable, [to_string]);
+    assert_eq!(
+        Class::select_method(&ma, int_array, to_string).unwrap(),
+        to_string
+    );
+}
+
+#[test]
+fn loaded_class_lookup_test() {
+    let mut memory = crate::class_path::MemorySource::default();
+    memory.insert(
+        "test/NotPreloaded",
+        crate::class_file::assemble("test/NotPreloaded", None, &[], &[]),
+    );
+    crate::class_path::class_path_mut().push(memory);
+
+    // Holding the method area never loads a class, even one on the class path
+    let name = Symbol::intern("test/NotPreloaded");
+    let err = method_area().resolve_class(name).unwrap_err();
+    assert_eq!(err.class_name, "java/lang/NoClassDefFoundError");
+    let class = resolve_class(ClassLoader::Bootstrap, name).unwrap();
+    assert_eq!(method_area().resolve_class(name).unwrap(), class);
+}
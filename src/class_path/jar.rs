@@ -0,0 +1,329 @@
+//! JAR and ZIP files on the class path
+//!
+//! Only the central directory is parsed up front. Entries are read from the local file header
+//! and inflated when a class is looked up.
+
+use super::{log_load, ClassSource};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::{fmt, fs, io};
+
+const END_OF_CENTRAL_DIR_SIG: u32 = 0x0605_4b50;
+const CENTRAL_DIR_ENTRY_SIG: u32 = 0x0201_4b50;
+const LOCAL_HEADER_SIG: u32 = 0x0403_4b50;
+const END_OF_CENTRAL_DIR_LEN: usize = 22;
+const LOCAL_HEADER_LEN: usize = 30;
+
+const METHOD_STORED: u16 = 0;
+const METHOD_DEFLATED: u16 = 8;
+
+/// The Java release whose `META-INF/versions` entries are used in multi-release JARs
+const RELEASE_VERSION: u32 = 22;
+/// Versioned entries were introduced in Java 9
+const FIRST_VERSIONED_RELEASE: u32 = 9;
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+fn read_u16(data: &[u8], offset: usize) -> io::Result<u16> {
+    data.get(offset..offset + 2)
+        .map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]))
+        .ok_or_else(|| invalid("truncated zip file"))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> io::Result<u32> {
+    data.get(offset..offset + 4)
+        .map(|bytes| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+        .ok_or_else(|| invalid("truncated zip file"))
+}
+
+#[derive(Debug, Clone, Copy)]
+struct ZipEntry {
+    method: u16,
+    compressed_size: u32,
+    size: u32,
+    local_header_offset: u32,
+}
+
+/// The main attributes of `META-INF/MANIFEST.MF`
+#[derive(Debug, Default, PartialEq)]
+pub struct Manifest {
+    pub main_class: Option<String>,
+    /// Relative URLs of other JARs and directories to add to the class path
+    pub class_path: Vec<String>,
+    pub multi_release: bool,
+}
+
+impl Manifest {
+    pub fn parse(text: &str) -> Manifest {
+        // Long values are continued on lines that start with a space
+        let mut lines: Vec<String> = Vec::new();
+        for line in text.lines() {
+            match (line.strip_prefix(' '), lines.last_mut()) {
+                (Some(continuation), Some(last)) => last.push_str(continuation),
+                _ => lines.push(line.to_string()),
+            }
+        }
+
+        let mut manifest = Manifest::default();
+        // The main section ends at the first blank line
+        for line in lines.iter().take_while(|line| !line.is_empty()) {
+            let Some((key, value)) = line.split_once(':') else {
+                continue;
+            };
+            let value = value.trim();
+            // Attribute names are case-insensitive
+            if key.eq_ignore_ascii_case("Main-Class") {
+                manifest.main_class = Some(value.to_string());
+            } else if key.eq_ignore_ascii_case("Class-Path") {
+                manifest.class_path = value.split_whitespace().map(str::to_string).collect();
+            } else if key.eq_ignore_ascii_case("Multi-Release") {
+                manifest.multi_release = value.eq_ignore_ascii_case("true");
+            }
+        }
+        manifest
+    }
+}
+
+/// A JAR or ZIP file, read into memory
+pub struct JarSource {
+    path: PathBuf,
+    data: Vec<u8>,
+    entries: HashMap<String, ZipEntry>,
+    manifest: Manifest,
+}
+
+impl fmt::Debug for JarSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("JarSource")
+            .field("path", &self.path)
+            .field("entries", &self.entries.len())
+            .finish()
+    }
+}
+
+impl JarSource {
+    pub fn open(path: impl Into<PathBuf>) -> io::Result<JarSource> {
+        let path = path.into();
+        let data = fs::read(&path)?;
+        JarSource::from_bytes(path, data)
+    }
+
+    pub fn from_bytes(path: PathBuf, data: Vec<u8>) -> io::Result<JarSource> {
+        let entries = read_central_directory(&data)?;
+        let mut jar = JarSource {
+            path,
+            data,
+            entries,
+            manifest: Manifest::default(),
+        };
+        if let Some(manifest) = jar.read_entry("META-INF/MANIFEST.MF")? {
+            jar.manifest = Manifest::parse(&String::from_utf8_lossy(&manifest));
+        }
+        Ok(jar)
+    }
+
+    pub fn manifest(&self) -> &Manifest {
+        &self.manifest
+    }
+
+    /// Paths of the manifest's `Class-Path` entries, which are relative to the JAR's directory
+    pub fn class_path_entries(&self) -> Vec<PathBuf> {
+        let dir = self.path.parent().unwrap_or(Path::new("."));
+        self.manifest
+            .class_path
+            .iter()
+            .map(|url| dir.join(url))
+            .collect()
+    }
+
+    /// The uncompressed contents of an entry
+    pub fn read_entry(&self, name: &str) -> io::Result<Option<Vec<u8>>> {
+        let Some(entry) = self.entries.get(name) else {
+            return Ok(None);
+        };
+        let offset = entry.local_header_offset as usize;
+        if read_u32(&self.data, offset)? != LOCAL_HEADER_SIG {
+            return Err(invalid("bad local file header signature"));
+        }
+        // The local header's name and extra field lengths can differ from the central directory's
+        let name_len = read_u16(&self.data, offset + 26)? as usize;
+        let extra_len = read_u16(&self.data, offset + 28)? as usize;
+        let start = offset + LOCAL_HEADER_LEN + name_len + extra_len;
+        let compressed = self
+            .data
+            .get(start..start + entry.compressed_size as usize)
+            .ok_or_else(|| invalid("truncated zip entry"))?;
+
+        let contents = match entry.method {
+            METHOD_STORED => compressed.to_vec(),
+            METHOD_DEFLATED => {
+                miniz_oxide::inflate::decompress_to_vec_with_limit(compressed, entry.size as usize)
+                    .map_err(|_| invalid("corrupt deflate stream"))?
+            }
+            _ => return Err(invalid("unsupported compression method")),
+        };
+        if contents.len() != entry.size as usize {
+            return Err(invalid("zip entry has the wrong size"));
+        }
+        Ok(Some(contents))
+    }
+}
+
+impl ClassSource for JarSource {
+    fn find_class(&self, name: &str) -> Option<Vec<u8>> {
+        let entry_name = format!("{}.class", name);
+        let versioned = self
+            .manifest
+            .multi_release
+            .then(|| {
+                (FIRST_VERSIONED_RELEASE..=RELEASE_VERSION)
+                    .rev()
+                    .map(|version| format!("META-INF/versions/{}/{}", version, entry_name))
+                    .find(|versioned| self.entries.contains_key(versioned))
+            })
+            .flatten();
+        let entry_name = versioned.unwrap_or(entry_name);
+
+        let data = self.read_entry(&entry_name).unwrap_or_else(|err| {
+            eprintln!(
+                "Error reading {} from {}: {}",
+                entry_name,
+                self.path.display(),
+                err
+            );
+            None
+        })?;
+        log_load(name, self.path.display());
+        Some(data)
+    }
+
+    fn fingerprint(&self) -> String {
+        super::file_fingerprint("jar", &self.path)
+    }
+}
+
+fn read_central_directory(data: &[u8]) -> io::Result<HashMap<String, ZipEntry>> {
+    // The end of central directory record is followed by a comment of up to 64 KiB
+    let last = data
+        .len()
+        .checked_sub(END_OF_CENTRAL_DIR_LEN)
+        .ok_or_else(|| invalid("not a zip file"))?;
+    let end = (last.saturating_sub(u16::MAX as usize)..=last)
+        .rev()
+        .find(|&offset| read_u32(data, offset).ok() == Some(END_OF_CENTRAL_DIR_SIG))
+        .ok_or_else(|| invalid("missing end of central directory"))?;
+
+    let count = read_u16(data, end + 10)?;
+    let mut offset = read_u32(data, end + 16)? as usize;
+    let mut entries = HashMap::new();
+    for _ in 0..count {
+        if read_u32(data, offset)? != CENTRAL_DIR_ENTRY_SIG {
+            return Err(invalid("bad central directory entry signature"));
+        }
+        let name_len = read_u16(data, offset + 28)? as usize;
+        let extra_len = read_u16(data, offset + 30)? as usize;
+        let comment_len = read_u16(data, offset + 32)? as usize;
+        let name = data
+            .get(offset + 46..offset + 46 + name_len)
+            .ok_or_else(|| invalid("truncated zip file"))?;
+        let entry = ZipEntry {
+            method: read_u16(data, offset + 10)?,
+            compressed_size: read_u32(data, offset + 20)?,
+            size: read_u32(data, offset + 24)?,
+            local_header_offset: read_u32(data, offset + 42)?,
+        };
+        entries.insert(String::from_utf8_lossy(name).into_owned(), entry);
+        offset += 46 + name_len + extra_len + comment_len;
+    }
+    Ok(entries)
+}
+
+#[test]
+fn manifest_test() {
+    let manifest = Manifest::parse(
+        "Manifest-Version: 1.0\r\nMain-Class: com.example.Main\r\nClass-Path: lib/a.jar lib/\r\n \
+         b.jar\r\nMulti-Release: true\r\n\r\nName: foo\r\nMain-Class: Other\r\n",
+    );
+    assert_eq!(
+        manifest,
+        Manifest {
+            main_class: Some("com.example.Main".to_string()),
+            class_path: vec!["lib/a.jar".to_string(), "lib/b.jar".to_string()],
+            multi_release: true,
+        }
+    );
+    assert_eq!(
+        Manifest::parse("main-class: Main\nCLASS-PATH: a.jar\nmulti-release: TRUE\n"),
+        Manifest {
+            main_class: Some("Main".to_string()),
+            class_path: vec!["a.jar".to_string()],
+            multi_release: true,
+        }
+    );
+}
+
+/// Write a zip file with the given entries, deflating the ones marked as compressed
+#[cfg(test)]
+pub fn zip(entries: &[(&str, &[u8], bool)]) -> Vec<u8> {
+    let mut data = Vec::new();
+    let mut central_dir = Vec::new();
+    for &(name, contents, deflate) in entries {
+        let (method, stored) = if deflate {
+            (
+                METHOD_DEFLATED,
+                miniz_oxide::deflate::compress_to_vec(contents, 6),
+            )
+        } else {
+            (METHOD_STORED, contents.to_vec())
+        };
+        let mut header = Vec::new();
+        header.extend(2u16.to_le_bytes()); // version needed
+        header.extend(0u16.to_le_bytes()); // flags
+        header.extend(method.to_le_bytes());
+        header.extend([0; 8]); // time, date, and crc
+        header.extend((stored.len() as u32).to_le_bytes());
+        header.extend((contents.len() as u32).to_le_bytes());
+        header.extend((name.len() as u16).to_le_bytes());
+        header.extend(0u16.to_le_bytes()); // extra field length
+
+        central_dir.extend(CENTRAL_DIR_ENTRY_SIG.to_le_bytes());
+        central_dir.extend(2u16.to_le_bytes()); // version made by
+        central_dir.extend(&header);
+        central_dir.extend([0; 10]); // comment length, disk, and attributes
+        central_dir.extend((data.len() as u32).to_le_bytes());
+        central_dir.extend(name.as_bytes());
+
+        data.extend(LOCAL_HEADER_SIG.to_le_bytes());
+        data.extend(&header);
+        data.extend(name.as_bytes());
+        data.extend(stored);
+    }
+    let central_dir_offset = data.len() as u32;
+    data.extend(&central_dir);
+    data.extend(END_OF_CENTRAL_DIR_SIG.to_le_bytes());
+    data.extend([0; 4]); // disk numbers
+    data.extend((entries.len() as u16).to_le_bytes());
+    data.extend((entries.len() as u16).to_le_bytes());
+    data.extend((central_dir.len() as u32).to_le_bytes());
+    data.extend(central_dir_offset.to_le_bytes());
+    data.extend(0u16.to_le_bytes()); // comment length
+    data
+}
+
+#[test]
+fn jar_source_test() {
+    let data = zip(&[
+        ("META-INF/MANIFEST.MF", b"Multi-Release: true\n", true),
+        ("a/A.class", b"base A", false),
+        ("a/B.class", b"base B", true),
+        ("META-INF/versions/11/a/A.class", b"A for 11", true),
+        ("META-INF/versions/99/a/B.class", b"B for 99", true),
+    ]);
+    let jar = JarSource::from_bytes(PathBuf::from("lib/test.jar"), data).unwrap();
+    assert!(jar.manifest().multi_release);
+    assert_eq!(jar.find_class("a/A"), Some(b"A for 11".to_vec()));
+    assert_eq!(jar.find_class("a/B"), Some(b"base B".to_vec()));
+    assert_eq!(jar.find_class("a/C"), None);
+}
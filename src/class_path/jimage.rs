@@ -0,0 +1,505 @@
+//! The JDK's runtime image, `lib/modules`
+//!
+//! The image starts with an index: a header, a perfect hash table of resource names (the redirect
+//! table), the offsets of each resource's location attributes, the attributes themselves, and a
+//! table of strings. Resource contents follow the index.
+
+use super::{log_load, ClassSource};
+use std::path::{Path, PathBuf};
+use std::{fmt, fs, io};
+
+const MAGIC: u32 = 0xCAFE_DADA;
+const MAJOR_VERSION: u32 = 1;
+const HEADER_LEN: usize = 7 * 4;
+const HASH_MULTIPLIER: u32 = 0x0100_0193;
+
+const ATTRIBUTE_END: u8 = 0;
+const ATTRIBUTE_MODULE: u8 = 1;
+const ATTRIBUTE_PARENT: u8 = 2;
+const ATTRIBUTE_BASE: u8 = 3;
+const ATTRIBUTE_EXTENSION: u8 = 4;
+const ATTRIBUTE_OFFSET: u8 = 5;
+const ATTRIBUTE_COMPRESSED: u8 = 6;
+const ATTRIBUTE_UNCOMPRESSED: u8 = 7;
+const ATTRIBUTE_COUNT: usize = 8;
+
+const COMPRESSED_HEADER_MAGIC: u32 = 0xCAFE_FAFA;
+const COMPRESSED_HEADER_LEN: usize = 29;
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+/// The hash used by the redirect table, over the UTF-8 bytes of a name
+fn hash(name: &str, seed: u32) -> u32 {
+    let mut hash = seed;
+    for &byte in name.as_bytes() {
+        hash = hash.wrapping_mul(HASH_MULTIPLIER) ^ byte as u32;
+    }
+    hash & 0x7FFF_FFFF
+}
+
+/// Attributes of a resource, indexed by attribute kind
+#[derive(Debug, Default, Clone, Copy)]
+struct Location([u64; ATTRIBUTE_COUNT]);
+
+impl Location {
+    /// Attributes are a byte stream. The high 5 bits of each header byte are the kind, and the low
+    /// 3 bits are one less than the length of the big-endian value that follows.
+    fn decode(data: &[u8]) -> io::Result<Location> {
+        let mut location = Location::default();
+        let mut pos = 0;
+        loop {
+            let header = *data.get(pos).ok_or_else(|| invalid("truncated location"))?;
+            let kind = header >> 3;
+            if kind == ATTRIBUTE_END {
+                return Ok(location);
+            }
+            if kind as usize >= ATTRIBUTE_COUNT {
+                return Err(invalid("invalid location attribute"));
+            }
+            let len = (header & 7) as usize + 1;
+            let bytes = data
+                .get(pos + 1..pos + 1 + len)
+                .ok_or_else(|| invalid("truncated location"))?;
+            location.0[kind as usize] = bytes
+                .iter()
+                .fold(0, |value, &byte| value << 8 | byte as u64);
+            pos += 1 + len;
+        }
+    }
+
+    fn get(&self, kind: u8) -> u64 {
+        self.0[kind as usize]
+    }
+}
+
+const CONSTANT_UTF8: u8 = 1;
+const CONSTANT_LONG: u8 = 5;
+const CONSTANT_DOUBLE: u8 = 6;
+/// A UTF-8 constant moved into the strings table
+const EXTERNALIZED_STRING: u8 = 23;
+/// A descriptor whose class names were moved into the strings table
+const EXTERNALIZED_STRING_DESCRIPTOR: u8 = 25;
+
+/// Size of a constant pool entry after its tag, for the entries that `compact-cp` leaves alone
+fn constant_size(tag: u8) -> Option<usize> {
+    Some(match tag {
+        3 | 4 | 9..=12 | 17 | 18 => 4,
+        5 | 6 => 8,
+        7 | 8 | 16 | 19 | 20 => 2,
+        15 => 3,
+        _ => return None,
+    })
+}
+
+/// Reads the big-endian values in a `compact-cp` resource
+struct CompressedReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> CompressedReader<'a> {
+    fn bytes(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        let bytes = self
+            .data
+            .get(self.pos..self.pos + len)
+            .ok_or_else(|| invalid("truncated compact-cp resource"))?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    /// An int takes 1 to 3 bytes if the top bit of the first is set, with the length in the next
+    /// 2 bits and the value in the rest. Otherwise it's a plain 4 byte int.
+    fn compressed_int(&mut self) -> io::Result<u32> {
+        let header = self.bytes(1)?[0];
+        let (len, mut value) = if header & 0x80 != 0 {
+            ((header >> 5) & 3, (header & 0x1F) as u32)
+        } else {
+            (4, header as u32)
+        };
+        for &byte in self.bytes(len.saturating_sub(1) as usize)? {
+            value = value << 8 | byte as u32;
+        }
+        Ok(value)
+    }
+}
+
+/// A jimage file, read into memory
+pub struct JImage {
+    path: PathBuf,
+    data: Vec<u8>,
+    big_endian: bool,
+    table_length: usize,
+    redirect_start: usize,
+    offsets_start: usize,
+    locations_start: usize,
+    strings_start: usize,
+    /// Where resource contents start
+    index_size: usize,
+}
+
+impl fmt::Debug for JImage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("JImage")
+            .field("path", &self.path)
+            .field("table_length", &self.table_length)
+            .finish()
+    }
+}
+
+impl JImage {
+    /// Whether a file starts with the jimage magic number, in either byte order
+    pub fn is_jimage(path: &Path) -> bool {
+        let mut magic = [0; 4];
+        fs::File::open(path)
+            .and_then(|mut file| io::Read::read_exact(&mut file, &mut magic))
+            .is_ok()
+            && (u32::from_le_bytes(magic) == MAGIC || u32::from_be_bytes(magic) == MAGIC)
+    }
+
+    pub fn open(path: impl Into<PathBuf>) -> io::Result<JImage> {
+        let path = path.into();
+        let data = fs::read(&path)?;
+        JImage::from_bytes(path, data)
+    }
+
+    pub fn from_bytes(path: PathBuf, data: Vec<u8>) -> io::Result<JImage> {
+        let magic = data.get(..4).ok_or_else(|| invalid("not a jimage file"))?;
+        let big_endian = match magic {
+            _ if u32::from_le_bytes(magic.try_into().unwrap()) == MAGIC => false,
+            _ if u32::from_be_bytes(magic.try_into().unwrap()) == MAGIC => true,
+            _ => return Err(invalid("not a jimage file")),
+        };
+        let mut image = JImage {
+            path,
+            data,
+            big_endian,
+            table_length: 0,
+            redirect_start: HEADER_LEN,
+            offsets_start: 0,
+            locations_start: 0,
+            strings_start: 0,
+            index_size: 0,
+        };
+
+        let version = image.read_u32(4)?;
+        if version >> 16 != MAJOR_VERSION {
+            return Err(invalid("unsupported jimage version"));
+        }
+        image.table_length = image.read_u32(16)? as usize;
+        let locations_size = image.read_u32(20)? as usize;
+        let strings_size = image.read_u32(24)? as usize;
+        image.offsets_start = image.redirect_start + image.table_length * 4;
+        image.locations_start = image.offsets_start + image.table_length * 4;
+        image.strings_start = image.locations_start + locations_size;
+        image.index_size = image.strings_start + strings_size;
+        if image.index_size > image.data.len() {
+            return Err(invalid("truncated jimage index"));
+        }
+        Ok(image)
+    }
+
+    /// Read a u32 in the image's byte order
+    fn u32_at(&self, bytes: &[u8], offset: usize) -> io::Result<u32> {
+        let bytes: [u8; 4] = bytes
+            .get(offset..offset + 4)
+            .ok_or_else(|| invalid("truncated jimage data"))?
+            .try_into()
+            .unwrap();
+        Ok(if self.big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        })
+    }
+
+    fn read_u32(&self, offset: usize) -> io::Result<u32> {
+        self.u32_at(&self.data, offset)
+    }
+
+    /// The modified UTF-8 bytes of a NUL-terminated string from the strings table
+    fn string_bytes(&self, offset: u64) -> io::Result<&[u8]> {
+        let start = self.strings_start + offset as usize;
+        let strings = self
+            .data
+            .get(start..self.index_size)
+            .ok_or_else(|| invalid("string offset out of bounds"))?;
+        let len = strings
+            .iter()
+            .position(|&byte| byte == 0)
+            .ok_or_else(|| invalid("unterminated string"))?;
+        Ok(&strings[..len])
+    }
+
+    fn string(&self, offset: u64) -> io::Result<&str> {
+        std::str::from_utf8(self.string_bytes(offset)?)
+            .map_err(|_| invalid("string is not valid UTF-8"))
+    }
+
+    /// The full name of a resource, e.g. `/java.base/java/lang/Object.class`
+    fn location_name(&self, location: &Location) -> io::Result<String> {
+        let mut name = String::new();
+        let module = self.string(location.get(ATTRIBUTE_MODULE))?;
+        if !module.is_empty() {
+            name.push('/');
+            name.push_str(module);
+            name.push('/');
+        }
+        let parent = self.string(location.get(ATTRIBUTE_PARENT))?;
+        if !parent.is_empty() {
+            name.push_str(parent);
+            name.push('/');
+        }
+        name.push_str(self.string(location.get(ATTRIBUTE_BASE))?);
+        let extension = self.string(location.get(ATTRIBUTE_EXTENSION))?;
+        if !extension.is_empty() {
+            name.push('.');
+            name.push_str(extension);
+        }
+        Ok(name)
+    }
+
+    fn find_location(&self, name: &str) -> io::Result<Option<Location>> {
+        if self.table_length == 0 {
+            return Ok(None);
+        }
+        // A negative redirect is the index itself, and a positive one is the seed to rehash with
+        let idx = hash(name, HASH_MULTIPLIER) as usize % self.table_length;
+        let redirect = self.read_u32(self.redirect_start + idx * 4)? as i32;
+        let idx = match redirect {
+            0 => return Ok(None),
+            ..0 => (-1 - redirect) as usize,
+            _ => hash(name, redirect as u32) as usize % self.table_length,
+        };
+        if idx >= self.table_length {
+            return Err(invalid("redirect out of bounds"));
+        }
+
+        let offset = self.read_u32(self.offsets_start + idx * 4)? as usize;
+        let attributes = self
+            .data
+            .get(self.locations_start + offset..self.strings_start)
+            .ok_or_else(|| invalid("location offset out of bounds"))?;
+        let location = Location::decode(attributes)?;
+        // The hash table is perfect for the names in the image, so other names can collide
+        Ok((self.location_name(&location)? == name).then_some(location))
+    }
+
+    /// The contents of a resource, decompressed
+    pub fn read_resource(&self, name: &str) -> io::Result<Option<Vec<u8>>> {
+        let Some(location) = self.find_location(name)? else {
+            return Ok(None);
+        };
+        let start = self.index_size + location.get(ATTRIBUTE_OFFSET) as usize;
+        let compressed_size = location.get(ATTRIBUTE_COMPRESSED) as usize;
+        let size = location.get(ATTRIBUTE_UNCOMPRESSED) as usize;
+        let stored_size = if compressed_size == 0 {
+            size
+        } else {
+            compressed_size
+        };
+        let mut contents = self
+            .data
+            .get(start..start + stored_size)
+            .ok_or_else(|| invalid("resource out of bounds"))?
+            .to_vec();
+        if compressed_size != 0 {
+            contents = self.decompress(contents)?;
+        }
+        if contents.len() != size {
+            return Err(invalid("resource has the wrong size"));
+        }
+        Ok(Some(contents))
+    }
+
+    /// Undo each layer of compression. Every layer starts with a header that names its
+    /// decompressor.
+    fn decompress(&self, mut contents: Vec<u8>) -> io::Result<Vec<u8>> {
+        // The header is the magic, the compressed and uncompressed sizes as u64s, the offset of
+        // the decompressor's name, the offset of its configuration, and an "is terminal" flag
+        while contents.len() >= COMPRESSED_HEADER_LEN
+            && self.u32_at(&contents, 0)? == COMPRESSED_HEADER_MAGIC
+        {
+            let (first, second) = (self.u32_at(&contents, 12)?, self.u32_at(&contents, 16)?);
+            let size = if self.big_endian {
+                (first as u64) << 32 | second as u64
+            } else {
+                (second as u64) << 32 | first as u64
+            };
+            let decompressor = self.string(self.u32_at(&contents, 20)? as u64)?;
+            let payload = &contents[COMPRESSED_HEADER_LEN..];
+            contents = match decompressor {
+                "zip" => {
+                    miniz_oxide::inflate::decompress_to_vec_zlib_with_limit(payload, size as usize)
+                        .map_err(|_| invalid("corrupt zip resource"))?
+                }
+                "compact-cp" => self.expand_shared_strings(payload)?,
+                _ => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::Unsupported,
+                        format!("unsupported jimage decompressor {}", decompressor),
+                    ))
+                }
+            };
+            if contents.len() as u64 != size {
+                return Err(invalid("decompressed resource has the wrong size"));
+            }
+        }
+        Ok(contents)
+    }
+
+    /// Undo the `compact-cp` compression of a class file, which moves constant pool strings into
+    /// the image's strings table
+    fn expand_shared_strings(&self, data: &[u8]) -> io::Result<Vec<u8>> {
+        let mut reader = CompressedReader { data, pos: 0 };
+        let mut out = Vec::with_capacity(data.len() * 2);
+        // Magic and version
+        out.extend(reader.bytes(8)?);
+        let count = u16::from_be_bytes(reader.bytes(2)?.try_into().unwrap());
+        out.extend(count.to_be_bytes());
+
+        let mut idx = 1;
+        while idx < count {
+            let tag = reader.bytes(1)?[0];
+            let utf8 = match tag {
+                CONSTANT_UTF8 => {
+                    let len = u16::from_be_bytes(reader.bytes(2)?.try_into().unwrap());
+                    reader.bytes(len as usize)?.to_vec()
+                }
+                EXTERNALIZED_STRING => self.string_bytes(reader.compressed_int()? as u64)?.to_vec(),
+                EXTERNALIZED_STRING_DESCRIPTOR => self.expand_descriptor(&mut reader)?,
+                _ => {
+                    let size = constant_size(tag).ok_or_else(|| {
+                        invalid("invalid constant pool tag in compact-cp resource")
+                    })?;
+                    out.push(tag);
+                    out.extend(reader.bytes(size)?);
+                    // Longs and doubles take up two entries
+                    idx += if matches!(tag, CONSTANT_LONG | CONSTANT_DOUBLE) {
+                        2
+                    } else {
+                        1
+                    };
+                    continue;
+                }
+            };
+            out.push(CONSTANT_UTF8);
+            out.extend((utf8.len() as u16).to_be_bytes());
+            out.extend(utf8);
+            idx += 1;
+        }
+        // The rest of the class file is unchanged
+        out.extend(&data[reader.pos..]);
+        Ok(out)
+    }
+
+    /// A descriptor is stored with its class names replaced by `L`, followed by the string
+    /// indices of the package and simple name of each class
+    fn expand_descriptor(&self, reader: &mut CompressedReader) -> io::Result<Vec<u8>> {
+        let desc = self.string_bytes(reader.compressed_int()? as u64)?;
+        let indices_len = reader.compressed_int()? as usize;
+        let mut indices = CompressedReader {
+            data: reader.bytes(indices_len)?,
+            pos: 0,
+        };
+
+        let mut expanded = Vec::new();
+        for &byte in desc {
+            expanded.push(byte);
+            if byte == b'L' && indices.pos < indices.data.len() {
+                let package = self.string_bytes(indices.compressed_int()? as u64)?;
+                if !package.is_empty() {
+                    expanded.extend(package);
+                    expanded.push(b'/');
+                }
+                expanded.extend(self.string_bytes(indices.compressed_int()? as u64)?);
+            }
+        }
+        Ok(expanded)
+    }
+
+    /// The module that contains a package, e.g. `java.base` for `java.lang`
+    fn module_of_package(&self, package: &str) -> io::Result<Option<String>> {
+        // The contents of `/packages/<package>` are pairs of an "is empty" flag and a module name
+        let Some(contents) = self.read_resource(&format!("/packages/{}", package))? else {
+            return Ok(None);
+        };
+        for entry in (0..contents.len() / 8).map(|idx| idx * 8) {
+            if self.u32_at(&contents, entry)? == 0 {
+                let module = self.string(self.u32_at(&contents, entry + 4)? as u64)?;
+                return Ok(Some(module.to_string()));
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// Classes in a runtime image, from whichever module contains their package
+#[derive(Debug)]
+pub struct JImageSource {
+    image: JImage,
+}
+
+impl JImageSource {
+    pub fn open(path: impl Into<PathBuf>) -> io::Result<JImageSource> {
+        Ok(JImageSource {
+            image: JImage::open(path)?,
+        })
+    }
+
+    fn read_class(&self, name: &str) -> io::Result<Option<Vec<u8>>> {
+        let package = name.rsplit_once('/').map_or("", |(package, _)| package);
+        let Some(module) = self.image.module_of_package(&package.replace('/', "."))? else {
+            return Ok(None);
+        };
+        self.image
+            .read_resource(&format!("/{}/{}.class", module, name))
+    }
+}
+
+impl ClassSource for JImageSource {
+    fn find_class(&self, name: &str) -> Option<Vec<u8>> {
+        let data = self.read_class(name).unwrap_or_else(|err| {
+            eprintln!(
+                "Error reading {} from {}: {}",
+                name,
+                self.image.path.display(),
+                err
+            );
+            None
+        })?;
+        log_load(name, self.image.path.display());
+        Some(data)
+    }
+
+    fn fingerprint(&self) -> String {
+        super::file_fingerprint("jimage", &self.image.path)
+    }
+}
+
+#[test]
+fn location_test() {
+    // Module at string 1, base at string 0x102, extension at 3, offset 0x10000, and size 5
+    let location =
+        Location::decode(&[0x08, 1, 0x19, 1, 2, 0x20, 3, 0x2A, 1, 0, 0, 0x38, 5, 0]).unwrap();
+    assert_eq!(location.get(ATTRIBUTE_MODULE), 1);
+    assert_eq!(location.get(ATTRIBUTE_PARENT), 0);
+    assert_eq!(location.get(ATTRIBUTE_BASE), 0x102);
+    assert_eq!(location.get(ATTRIBUTE_EXTENSION), 3);
+    assert_eq!(location.get(ATTRIBUTE_OFFSET), 0x10000);
+    assert_eq!(location.get(ATTRIBUTE_UNCOMPRESSED), 5);
+    assert!(Location::decode(&[0x08, 1]).is_err());
+}
+
+#[test]
+fn compressed_int_test() {
+    let mut reader = CompressedReader {
+        data: &[0xA5, 0xC1, 0x23, 0x01, 0x02, 0x03, 0x04],
+        pos: 0,
+    };
+    assert_eq!(reader.compressed_int().unwrap(), 5);
+    assert_eq!(reader.compressed_int().unwrap(), 0x123);
+    assert_eq!(reader.compressed_int().unwrap(), 0x0102_0304);
+    assert!(reader.compressed_int().is_err());
+}
@@ -0,0 +1,334 @@
+//! Sources of class files for the bootstrap loader
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::fmt;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{LazyLock, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+mod jar;
+mod jimage;
+
+pub use jar::JarSource;
+pub use jimage::{JImage, JImageSource};
+
+static CLASS_PATH: LazyLock<RwLock<CompositeSource>> = LazyLock::new(Default::default);
+
+/// Where the bootstrap loader looks for classes, in order. Threads loading classes in parallel
+/// read it at the same time.
+pub fn class_path() -> RwLockReadGuard<'static, CompositeSource> {
+    CLASS_PATH.read().unwrap_or_else(PoisonError::into_inner)
+}
+
+pub fn class_path_mut() -> RwLockWriteGuard<'static, CompositeSource> {
+    CLASS_PATH.write().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Replace the class path. This should be done before any classes are loaded.
+pub fn set_class_path(source: CompositeSource) {
+    *class_path_mut() = source;
+}
+
+static VERBOSE: AtomicBool = AtomicBool::new(false);
+
+/// Print where each class is loaded from, as requested with `-verbose:class`
+pub fn enable_verbose() {
+    VERBOSE.store(true, Ordering::Relaxed);
+}
+
+fn log_load(name: &str, source: impl fmt::Display) {
+    if VERBOSE.load(Ordering::Relaxed) {
+        println!("[class,load] {} source: {}", name.replace('/', "."), source);
+    }
+}
+
+pub trait ClassSource: Send + Sync + fmt::Debug {
+    /// The contents of the class file for a binary name such as `java/lang/Object`
+    fn find_class(&self, name: &str) -> Option<Vec<u8>>;
+
+    /// Identifies this source and the version of its contents, so that a CDS archive can tell
+    /// whether it was dumped from the same class path
+    fn fingerprint(&self) -> String;
+}
+
+/// The path of a file with its size and modification time
+fn file_fingerprint(kind: &str, path: &Path) -> String {
+    let metadata = fs::metadata(path).ok();
+    let len = metadata.as_ref().map_or(0, |metadata| metadata.len());
+    let modified = metadata
+        .and_then(|metadata| metadata.modified().ok())
+        .and_then(|time| time.duration_since(std::time::UNIX_EPOCH).ok())
+        .map_or(0, |time| time.as_nanos());
+    format!("{}:{}:{}:{}", kind, path.display(), len, modified)
+}
+
+/// Loose class files in a directory tree laid out by package
+#[derive(Debug)]
+pub struct DirectorySource {
+    root: PathBuf,
+}
+
+impl DirectorySource {
+    pub fn new(root: impl Into<PathBuf>) -> DirectorySource {
+        DirectorySource { root: root.into() }
+    }
+}
+
+impl ClassSource for DirectorySource {
+    fn find_class(&self, name: &str) -> Option<Vec<u8>> {
+        let mut path = self.root.join(name);
+        path.set_extension("class");
+        let data = fs::read(&path).ok()?;
+        log_load(name, path.display());
+        Some(data)
+    }
+
+    /// Covers every class file in the tree, since any of them could be archived, and new ones
+    /// could hide classes in later sources
+    fn fingerprint(&self) -> String {
+        /// Directories are visited once each, so symbolic links can't make this loop
+        fn visit(dir: &Path, visited: &mut HashSet<PathBuf>, files: &mut Vec<PathBuf>) {
+            let Ok(canonical) = dir.canonicalize() else {
+                return;
+            };
+            let Ok(entries) = fs::read_dir(dir) else {
+                return;
+            };
+            if !visited.insert(canonical) {
+                return;
+            }
+            for entry in entries.flatten() {
+                let path = entry.path();
+                let is_dir = match entry.file_type() {
+                    Ok(file_type) if file_type.is_symlink() => path.is_dir(),
+                    Ok(file_type) => file_type.is_dir(),
+                    Err(_) => continue,
+                };
+                if is_dir {
+                    visit(&path, visited, files);
+                } else if path.extension().is_some_and(|ext| ext == "class") {
+                    files.push(path);
+                }
+            }
+        }
+        let mut files = Vec::new();
+        visit(&self.root, &mut HashSet::new(), &mut files);
+        files.sort();
+
+        let mut hasher = DefaultHasher::new();
+        for path in files {
+            file_fingerprint("file", &path).hash(&mut hasher);
+        }
+        format!("dir:{}:{:016x}", self.root.display(), hasher.finish())
+    }
+}
+
+/// Class files held in memory, keyed by binary name, e.g. generated by an embedder and pushed
+/// onto the class path with `class_path_mut`. The launcher itself only reads from disk.
+#[allow(dead_code)]
+#[derive(Debug, Default)]
+pub struct MemorySource {
+    classes: HashMap<String, Vec<u8>>,
+}
+
+#[allow(dead_code)]
+impl MemorySource {
+    pub fn insert(&mut self, name: impl Into<String>, data: Vec<u8>) {
+        self.classes.insert(name.into(), data);
+    }
+}
+
+impl<N: Into<String>> FromIterator<(N, Vec<u8>)> for MemorySource {
+    fn from_iter<I: IntoIterator<Item = (N, Vec<u8>)>>(classes: I) -> MemorySource {
+        MemorySource {
+            classes: classes
+                .into_iter()
+                .map(|(name, data)| (name.into(), data))
+                .collect(),
+        }
+    }
+}
+
+impl ClassSource for MemorySource {
+    fn find_class(&self, name: &str) -> Option<Vec<u8>> {
+        let data = self.classes.get(name)?.clone();
+        log_load(name, "memory");
+        Some(data)
+    }
+
+    /// Covers the contents of the classes, which may be replaced between runs
+    fn fingerprint(&self) -> String {
+        let mut classes: Vec<_> = self.classes.iter().collect();
+        classes.sort();
+        let mut hasher = DefaultHasher::new();
+        classes.hash(&mut hasher);
+        format!("memory:{:016x}", hasher.finish())
+    }
+}
+
+/// Searches each source in turn, returning the first class found
+#[derive(Debug, Default)]
+pub struct CompositeSource {
+    sources: Vec<Box<dyn ClassSource>>,
+}
+
+impl CompositeSource {
+    /// Parse a list of directories, JAR files, and runtime images separated by the platform's path
+    /// separator, as in `-cp` or `CLASSPATH`. Empty entries mean the current directory.
+    pub fn parse(class_path: &str) -> CompositeSource {
+        let mut composite = CompositeSource::default();
+        let mut visited = HashSet::new();
+        for entry in env::split_paths(class_path) {
+            let entry = if entry.as_os_str().is_empty() {
+                PathBuf::from(".")
+            } else {
+                entry
+            };
+            composite.push_path(&entry, &mut visited);
+        }
+        composite
+    }
+
+    /// Add a directory or JAR file, followed by the entries of the JAR's `Class-Path` attribute.
+    /// Entries that don't exist are ignored, like in the JDK.
+    fn push_path(&mut self, path: &Path, visited: &mut HashSet<PathBuf>) {
+        if !visited.insert(path.to_path_buf()) {
+            return;
+        }
+        if path.is_dir() {
+            self.push(DirectorySource::new(path));
+            return;
+        }
+        if JImage::is_jimage(path) {
+            match JImageSource::open(path) {
+                Ok(image) => self.push(image),
+                Err(err) => eprintln!("Error opening {}: {}", path.display(), err),
+            }
+            return;
+        }
+        let is_archive = path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("jar") || ext.eq_ignore_ascii_case("zip"));
+        if !is_archive || !path.exists() {
+            return;
+        }
+        match JarSource::open(path) {
+            Ok(jar) => {
+                let class_path_entries = jar.class_path_entries();
+                self.push(jar);
+                for entry in class_path_entries {
+                    self.push_path(&entry, visited);
+                }
+            }
+            Err(err) => eprintln!("Error opening {}: {}", path.display(), err),
+        }
+    }
+
+    pub fn push(&mut self, source: impl ClassSource + 'static) {
+        self.sources.push(Box::new(source));
+    }
+}
+
+impl ClassSource for CompositeSource {
+    fn find_class(&self, name: &str) -> Option<Vec<u8>> {
+        self.sources
+            .iter()
+            .find_map(|source| source.find_class(name))
+    }
+
+    fn fingerprint(&self) -> String {
+        let sources: Vec<String> = self
+            .sources
+            .iter()
+            .map(|source| source.fingerprint())
+            .collect();
+        format!("[{}]", sources.join(";"))
+    }
+}
+
+#[test]
+fn composite_source_test() {
+    let dir = env::temp_dir().join(format!("leprd-class-path-{}", std::process::id()));
+    fs::create_dir_all(dir.join("a")).unwrap();
+    fs::write(dir.join("a/A.class"), b"dir A").unwrap();
+    let jar_path = dir.join("lib.jar");
+    let jar = jar::zip(&[
+        ("a/A.class", b"jar A", false),
+        ("a/B.class", b"jar B", true),
+    ]);
+    fs::write(&jar_path, jar).unwrap();
+
+    // Missing entries are skipped
+    let class_path = env::join_paths([&dir, &dir.join("missing.jar"), &jar_path]).unwrap();
+    let composite = CompositeSource::parse(class_path.to_str().unwrap());
+    assert_eq!(composite.sources.len(), 2);
+    assert_eq!(composite.find_class("a/A"), Some(b"dir A".to_vec()));
+    assert_eq!(composite.find_class("a/B"), Some(b"jar B".to_vec()));
+    assert_eq!(composite.find_class("a/C"), None);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn directory_fingerprint_test() {
+    let dir = env::temp_dir().join(format!("leprd-fingerprint-{}", std::process::id()));
+    fs::create_dir_all(dir.join("a")).unwrap();
+    fs::write(dir.join("a/A.class"), b"A").unwrap();
+    let source = DirectorySource::new(&dir);
+    let fingerprint = source.fingerprint();
+    assert_eq!(source.fingerprint(), fingerprint);
+
+    fs::write(dir.join("a/A.class"), b"new A").unwrap();
+    let changed = source.fingerprint();
+    assert_ne!(changed, fingerprint);
+    fs::write(dir.join("B.class"), b"B").unwrap();
+    let added = source.fingerprint();
+    assert_ne!(added, changed);
+
+    // A link back to the root is only followed once
+    #[cfg(unix)]
+    {
+        std::os::unix::fs::symlink(&dir, dir.join("a/loop")).unwrap();
+        assert_eq!(source.fingerprint(), added);
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn memory_source_test() {
+    use crate::class_loader::{self, method_area, ClassLoader};
+    use crate::symbol::Symbol;
+    let data = crate::class_file::assemble("test/FromMemory", None, &[], &[]);
+    let source: MemorySource = [("test/FromMemory", data)].into_iter().collect();
+    let fingerprint = source.fingerprint();
+    class_path_mut().push(source);
+
+    let name = Symbol::intern("test/FromMemory");
+    let class = class_loader::resolve_class(ClassLoader::Bootstrap, name).unwrap();
+    assert_eq!(method_area().classes[class].name, name);
+
+    let mut changed = MemorySource::default();
+    changed.insert("test/FromMemory", Vec::new());
+    assert_ne!(changed.fingerprint(), fingerprint);
+}
+
+#[test]
+fn memory_source_order_test() {
+    let dir = env::temp_dir().join(format!("leprd-memory-{}", std::process::id()));
+    fs::create_dir_all(dir.join("a")).unwrap();
+    fs::write(dir.join("a/A.class"), b"dir A").unwrap();
+    fs::write(dir.join("a/B.class"), b"dir B").unwrap();
+    let mut memory = MemorySource::default();
+    memory.insert("a/A", b"memory A".to_vec());
+
+    // Sources pushed first are searched first
+    let mut composite = CompositeSource::default();
+    composite.push(memory);
+    composite.push(DirectorySource::new(&dir));
+    assert_eq!(composite.find_class("a/A"), Some(b"memory A".to_vec()));
+    assert_eq!(composite.find_class("a/B"), Some(b"dir B".to_vec()));
+    fs::remove_dir_all(&dir).unwrap();
+}
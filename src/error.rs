@@ -0,0 +1,108 @@
+use crate::class::Class;
+use crate::class_loader::{self, method_area, ClassLoader};
+use crate::heap::{heap, ObjectRef};
+use crate::symbol::Symbol;
+use std::any::Any;
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+
+/// An error or exception raised by the VM that should be visible to Java code
+#[derive(Clone, PartialEq)]
+pub struct JavaError {
+    /// The binary name of the exception class, e.g. `java/lang/NoSuchFieldError`
+    pub class_name: Symbol,
+    pub message: String,
+}
+
+pub type JavaResult<T> = Result<T, JavaError>;
+
+impl JavaError {
+    pub fn new(class_name: &str, message: impl Into<String>) -> JavaError {
+        JavaError {
+            class_name: Symbol::intern(class_name),
+            message: message.into(),
+        }
+    }
+
+    /// The error for a `java.lang.Throwable` thrown by Java code, with its class and detail
+    /// message
+    pub fn from_throwable(throwable: ObjectRef) -> JavaError {
+        let class_id = heap().get_obj_class(throwable);
+        let throwable_class = class_loader::resolve_class(
+            ClassLoader::Bootstrap,
+            Symbol::intern("java/lang/Throwable"),
+        )
+        .unwrap();
+        let ma = method_area();
+        let class_name = ma.classes[class_id].name;
+        let details_field = ma
+            .resolve_field(
+                throwable_class,
+                Symbol::intern("detailMessage"),
+                Symbol::intern("Ljava/lang/String;"),
+            )
+            .unwrap();
+        drop(ma);
+        let message = match heap().load_field(throwable, details_field).object() {
+            Some(details) => heap().read_string(details),
+            None => String::new(),
+        };
+        JavaError {
+            class_name,
+            message,
+        }
+    }
+
+    /// Unwind with this error as the panic payload. Until exceptions are implemented, the only
+    /// place these get caught is class initialization.
+    pub fn throw(&self) -> ! {
+        panic::panic_any(self.clone());
+    }
+
+    /// Whether the class of this is `java.lang.Error` or a subclass of it
+    pub fn is_error(&self) -> bool {
+        let class = class_loader::resolve_class(ClassLoader::Bootstrap, self.class_name);
+        let error =
+            class_loader::resolve_class(ClassLoader::Bootstrap, Symbol::intern("java/lang/Error"));
+        match (class, error) {
+            (Ok(class), Ok(error)) => Class::instance_of(class, error),
+            _ => false,
+        }
+    }
+
+    /// Run `f`, catching any `JavaError` it throws. Other panics keep unwinding.
+    pub fn catch<T>(f: impl FnOnce() -> T) -> JavaResult<T> {
+        panic::catch_unwind(AssertUnwindSafe(f)).map_err(|payload| match payload.downcast() {
+            Ok(err) => *err,
+            Err(payload) => panic::resume_unwind(payload),
+        })
+    }
+
+    /// Report errors that were thrown and never caught, leaving other panics to the default hook
+    pub fn install_panic_hook() {
+        let default_hook = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            let payload: &dyn Any = info.payload();
+            if payload.downcast_ref::<JavaError>().is_none() {
+                default_hook(info);
+            }
+        }));
+    }
+}
+
+impl fmt::Display for JavaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let class_name = self.class_name.as_str().replace('/', ".");
+        if self.message.is_empty() {
+            f.write_str(&class_name)
+        } else {
+            write!(f, "{}: {}", class_name, self.message)
+        }
+    }
+}
+
+impl fmt::Debug for JavaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
@@ -0,0 +1,772 @@
+use crate::class::{Class, FieldBacking};
+use crate::class_file::descriptors::{BaseType, FieldType};
+use crate::class_loader::{method_area, ClassId, FieldId, MethodArea};
+use crate::symbol::Symbol;
+use crate::value::{MatchesFieldType, Value};
+use crate::CONFIG;
+use id_arena::{ArenaBehavior, DefaultArenaBehavior};
+use std::alloc::Layout;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::OnceLock;
+
+mod tlab;
+
+/// Retrieve the heap. It has no state of its own: objects are allocated from the thread's
+/// allocation buffer, and accesses are to the objects themselves, so no lock is needed.
+pub fn heap() -> Heap {
+    Heap
+}
+
+/// The layout of a field or array element of the given type
+pub fn layout_for_field(field_ty: &FieldType) -> Layout {
+    ValueKind::of(field_ty).layout()
+}
+
+/// How a field or array element is stored, which is all that is needed to load and store it.
+/// The kind of an array's elements is kept in its class word, so elements can be accessed without
+/// looking up the array's class.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ValueKind {
+    Base(BaseType),
+    Object,
+    Array,
+}
+
+impl ValueKind {
+    const BASE_TYPES: [BaseType; 8] = [
+        BaseType::B,
+        BaseType::C,
+        BaseType::D,
+        BaseType::F,
+        BaseType::I,
+        BaseType::J,
+        BaseType::S,
+        BaseType::Z,
+    ];
+
+    pub fn of(ty: &FieldType) -> ValueKind {
+        match ty {
+            FieldType::BaseType(base_type) => ValueKind::Base(*base_type),
+            FieldType::ObjectType(_) => ValueKind::Object,
+            FieldType::ArrayType(_) => ValueKind::Array,
+        }
+    }
+
+    pub fn layout(self) -> Layout {
+        match self {
+            ValueKind::Base(ty) => match ty {
+                BaseType::B => Layout::new::<i8>(),
+                BaseType::C => Layout::new::<u16>(),
+                BaseType::D => Layout::new::<f64>(),
+                BaseType::F => Layout::new::<f32>(),
+                BaseType::I => Layout::new::<i32>(),
+                BaseType::J => Layout::new::<i64>(),
+                BaseType::S => Layout::new::<i16>(),
+                BaseType::Z => Layout::new::<bool>(),
+            },
+            ValueKind::Object | ValueKind::Array if CONFIG.compressed_refs => Layout::new::<u32>(),
+            ValueKind::Object | ValueKind::Array => Layout::new::<*mut Object>(),
+        }
+    }
+
+    /// Narrow an int to the type that is stored, like [`Value::store_ty`]
+    pub fn store_ty(self, val: Value) -> Value {
+        match self {
+            ValueKind::Base(base_type) => val.store_ty(&FieldType::BaseType(base_type)),
+            ValueKind::Object | ValueKind::Array => val,
+        }
+    }
+
+    /// Non-zero, so that the class words of objects that aren't arrays have no kind
+    fn to_bits(self) -> u32 {
+        match self {
+            ValueKind::Base(ty) => {
+                Self::BASE_TYPES.iter().position(|&t| t == ty).unwrap() as u32 + 1
+            }
+            ValueKind::Object => 9,
+            ValueKind::Array => 10,
+        }
+    }
+
+    fn from_bits(bits: u32) -> Option<ValueKind> {
+        match bits {
+            0 => None,
+            1..=8 => Some(ValueKind::Base(Self::BASE_TYPES[bits as usize - 1])),
+            9 => Some(ValueKind::Object),
+            _ => Some(ValueKind::Array),
+        }
+    }
+}
+
+/// Gets the layout for an array in memory. Returns the complete layout of the array, the offset to
+/// the start of the elements, and the stride.
+pub fn arr_layout(elem: ValueKind, len: usize) -> (Layout, usize, usize) {
+    let base_layout = Layout::new::<Array>()
+        .align_to(MIN_OBJECT_ALIGN as usize)
+        .unwrap();
+    let (arr_layout, stride) = elem.layout().repeat(len).unwrap();
+    let (combined_layout, offset) = base_layout.extend(arr_layout).unwrap();
+    (combined_layout, offset, stride)
+}
+
+/// Encode a reference for storing in an object or array. With compressed references, this is the
+/// offset of the object from the start of the heap, where 0 is null.
+pub fn compress_ref(ptr: *mut Object) -> u32 {
+    if ptr.is_null() {
+        0
+    } else {
+        (ptr as usize - tlab::heap_base()) as u32
+    }
+}
+
+pub fn decompress_ref(offset: u32) -> *mut Object {
+    if offset == 0 {
+        std::ptr::null_mut()
+    } else {
+        (tlab::heap_base() + offset as usize) as *mut Object
+    }
+}
+
+/// Store a reference into a reference slot of an object or array
+pub unsafe fn store_ref(ptr: *mut u8, obj: *mut Object) {
+    if CONFIG.compressed_refs {
+        ptr.cast::<u32>().write(compress_ref(obj));
+    } else {
+        ptr.cast::<*mut Object>().write(obj);
+    }
+}
+
+pub unsafe fn load_ref(ptr: *const u8) -> *mut Object {
+    if CONFIG.compressed_refs {
+        decompress_ref(ptr.cast::<u32>().read())
+    } else {
+        ptr.cast::<*mut Object>().read()
+    }
+}
+
+unsafe fn store_value(ptr: *mut u8, val: Value) {
+    match val {
+        Value::Byte(val) => ptr.cast::<i8>().write(val),
+        Value::Char(val) => ptr.cast::<u16>().write(val),
+        Value::Double(val) => ptr.cast::<f64>().write(val),
+        Value::Float(val) => ptr.cast::<f32>().write(val),
+        Value::Int(val) => ptr.cast::<i32>().write(val),
+        Value::Long(val) => ptr.cast::<i64>().write(val),
+        Value::Short(val) => ptr.cast::<i16>().write(val),
+        Value::Boolean(val) => ptr.cast::<bool>().write(val),
+        Value::Array(arr_ref) => {
+            let arr_ptr = arr_ref.map_or(std::ptr::null_mut(), |r| r.0);
+            store_ref(ptr, arr_ptr.cast::<Object>());
+        }
+        Value::Object(obj_ref) => {
+            let obj_ptr = obj_ref.map_or(std::ptr::null_mut(), |r| r.0);
+            store_ref(ptr, obj_ptr);
+        }
+    }
+}
+
+unsafe fn load_value(ptr: *const u8, kind: ValueKind) -> Value {
+    match kind {
+        ValueKind::Base(ty) => match ty {
+            BaseType::B => Value::Byte(ptr.cast::<i8>().read()),
+            BaseType::C => Value::Char(ptr.cast::<u16>().read()),
+            BaseType::D => Value::Double(ptr.cast::<f64>().read()),
+            BaseType::F => Value::Float(ptr.cast::<f32>().read()),
+            BaseType::I => Value::Int(ptr.cast::<i32>().read()),
+            BaseType::J => Value::Long(ptr.cast::<i64>().read()),
+            BaseType::S => Value::Short(ptr.cast::<i16>().read()),
+            BaseType::Z => Value::Boolean(ptr.cast::<bool>().read()),
+        },
+        ValueKind::Array => {
+            let arr_ptr = load_ref(ptr).cast::<Array>();
+            Value::Array(ArrayRef::from_ptr(arr_ptr))
+        }
+        ValueKind::Object => Value::Object(ObjectRef::from_ptr(load_ref(ptr))),
+    }
+}
+
+/// The id of the arena classes are allocated in, needed to turn class words back into ids
+static CLASS_ARENA_ID: OnceLock<u32> = OnceLock::new();
+
+/// The class word holds the kind of an array's elements above the class index
+const ELEM_KIND_SHIFT: u32 = 28;
+
+/// Classes are stored in object headers as their index in the method area. Arrays also store the
+/// kind of their elements.
+fn class_word(class_id: ClassId, elem: Option<ValueKind>) -> u32 {
+    CLASS_ARENA_ID.get_or_init(|| DefaultArenaBehavior::<Class>::arena_id(class_id));
+    let index = class_id.index() as u32;
+    assert!(index < 1 << ELEM_KIND_SHIFT, "too many classes");
+    index | elem.map_or(0, ValueKind::to_bits) << ELEM_KIND_SHIFT
+}
+
+fn class_from_word(word: u32) -> ClassId {
+    let index = word & ((1 << ELEM_KIND_SHIFT) - 1);
+    DefaultArenaBehavior::new_id(*CLASS_ARENA_ID.get().unwrap(), index as usize)
+}
+
+fn elem_kind_from_word(word: u32) -> Option<ValueKind> {
+    ValueKind::from_bits(word >> ELEM_KIND_SHIFT)
+}
+
+/// Objects are at least 8 byte aligned so that the mark word can be accessed atomically
+pub const MIN_OBJECT_ALIGN: u8 = 8;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LockState {
+    /// The rest of the mark word points to the lock record of the owning thread
+    Locked = 0b00,
+    Unlocked = 0b01,
+    /// The rest of the mark word points to a monitor
+    Inflated = 0b10,
+    /// Marked by the garbage collector. If the object was moved, the rest of the mark word is the
+    /// forwarding pointer.
+    Marked = 0b11,
+}
+
+/// The first word of every object and array. The low two bits are the [`LockState`]. When an
+/// object is unlocked, bits 3 to 6 hold its age and bits 8 to 38 hold its identity hash, with 0
+/// meaning that no hash has been assigned yet. In the other states, the rest of the word is a
+/// pointer, and the hash and age are kept wherever it points to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MarkWord(u64);
+
+// Locking and garbage collection will use the rest of these
+#[allow(dead_code)]
+impl MarkWord {
+    const LOCK_MASK: u64 = 0b11;
+    const AGE_SHIFT: u32 = 3;
+    const AGE_MASK: u64 = 0b1111;
+    const HASH_SHIFT: u32 = 8;
+    const HASH_MASK: u64 = 0x7fff_ffff;
+
+    /// The mark word of a newly allocated object
+    pub const INITIAL: MarkWord = MarkWord(LockState::Unlocked as u64);
+
+    pub fn lock_state(self) -> LockState {
+        match self.0 & Self::LOCK_MASK {
+            0b00 => LockState::Locked,
+            0b01 => LockState::Unlocked,
+            0b10 => LockState::Inflated,
+            _ => LockState::Marked,
+        }
+    }
+
+    /// A mark word in any state other than unlocked, pointing to `ptr`, which must be 4 byte
+    /// aligned
+    pub fn from_pointer(state: LockState, ptr: usize) -> MarkWord {
+        debug_assert!(state != LockState::Unlocked && ptr as u64 & Self::LOCK_MASK == 0);
+        MarkWord(ptr as u64 | state as u64)
+    }
+
+    pub fn pointer(self) -> usize {
+        debug_assert!(self.lock_state() != LockState::Unlocked);
+        (self.0 & !Self::LOCK_MASK) as usize
+    }
+
+    pub fn age(self) -> u8 {
+        debug_assert!(self.lock_state() == LockState::Unlocked);
+        ((self.0 >> Self::AGE_SHIFT) & Self::AGE_MASK) as u8
+    }
+
+    /// Ages saturate at 15
+    pub fn with_age(self, age: u8) -> MarkWord {
+        debug_assert!(self.lock_state() == LockState::Unlocked);
+        let age = (age as u64).min(Self::AGE_MASK);
+        MarkWord(self.0 & !(Self::AGE_MASK << Self::AGE_SHIFT) | age << Self::AGE_SHIFT)
+    }
+
+    pub fn hash(self) -> Option<i32> {
+        debug_assert!(self.lock_state() == LockState::Unlocked);
+        let hash = (self.0 >> Self::HASH_SHIFT) & Self::HASH_MASK;
+        (hash != 0).then_some(hash as i32)
+    }
+
+    /// `hash` must be a non-zero 31-bit value
+    pub fn with_hash(self, hash: i32) -> MarkWord {
+        debug_assert!(self.lock_state() == LockState::Unlocked);
+        debug_assert!(hash > 0);
+        let hash = hash as u64 & Self::HASH_MASK;
+        MarkWord(self.0 & !(Self::HASH_MASK << Self::HASH_SHIFT) | hash << Self::HASH_SHIFT)
+    }
+}
+
+/// Generates identity hashes using Marsaglia's xor-shift
+fn next_identity_hash() -> i32 {
+    static STATE: AtomicU32 = AtomicU32::new(0x2545_f491);
+    loop {
+        let mut x = STATE.load(Ordering::Relaxed);
+        let old = x;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        if STATE
+            .compare_exchange(old, x, Ordering::Relaxed, Ordering::Relaxed)
+            .is_ok()
+        {
+            let hash = (x & MarkWord::HASH_MASK as u32) as i32;
+            if hash != 0 {
+                return hash;
+            }
+        }
+    }
+}
+
+#[derive(Default, Debug, Clone, Copy)]
+pub struct Heap;
+
+impl Heap {
+    /// Allocate an object of a linked class with the layout given by [`Class::instance_layout`]
+    pub fn new_object(class_id: ClassId, layout: Layout) -> ObjectRef {
+        let object = Object {
+            mark: MarkWord::INITIAL.0,
+            class: class_word(class_id, None),
+        };
+        let object_ptr = unsafe {
+            let ptr = tlab::allocate(layout).cast::<Object>();
+            ptr.write(object);
+            ptr
+        };
+
+        ObjectRef(object_ptr)
+    }
+
+    pub fn clone_object(&self, obj_ref: ObjectRef) -> ObjectRef {
+        let class_word = unsafe { (*obj_ref.0).class };
+        let layout = if let Some(elem) = elem_kind_from_word(class_word) {
+            // We know it's an array because the class word has an element kind
+            let arr = unsafe { obj_ref.cast_to_array() };
+            arr_layout(elem, self.arr_len(arr)).0
+        } else {
+            method_area().classes[class_from_word(class_word)].instance_layout()
+        };
+
+        let object_ptr = unsafe {
+            let ptr = tlab::allocate(layout);
+            ptr.copy_from(obj_ref.0.cast::<u8>(), layout.size());
+            ptr.cast::<Object>()
+        };
+        // The clone has its own identity and is not locked
+        let new_ref = ObjectRef(object_ptr);
+        self.mark_word_atomic(new_ref)
+            .store(MarkWord::INITIAL.0, Ordering::Relaxed);
+
+        new_ref
+    }
+
+    /// Allocate an array with elements of a type resolved by the bootstrap loader
+    pub fn new_array(ma: &mut MethodArea, elem_ty: FieldType, len: usize) -> ArrayRef {
+        let arr_class = ma.resolve_arr_class(&elem_ty);
+        Heap::new_array_of_class(arr_class, ValueKind::of(&elem_ty), len)
+    }
+
+    /// Allocate an array of `arr_class`, whose elements are stored as `elem`
+    pub fn new_array_of_class(arr_class: ClassId, elem: ValueKind, len: usize) -> ArrayRef {
+        let (layout, _, _) = arr_layout(elem, len);
+
+        let array = Array {
+            header: Object {
+                mark: MarkWord::INITIAL.0,
+                class: class_word(arr_class, Some(elem)),
+            },
+            len: len.try_into().unwrap(),
+        };
+        let array_ptr = unsafe {
+            let ptr = tlab::allocate(layout).cast::<Array>();
+            ptr.write(array);
+            ptr
+        };
+
+        ArrayRef(array_ptr)
+    }
+
+    pub fn arr_len(&self, arr: ArrayRef) -> usize {
+        unsafe { (*arr.0).len as usize }
+    }
+
+    /// How the elements of an array are stored. This is kept in the array's header, so it doesn't
+    /// need the method area.
+    pub fn arr_elem_kind(&self, arr: ArrayRef) -> ValueKind {
+        elem_kind_from_word(unsafe { (*arr.0).header.class }).unwrap()
+    }
+
+    /// The non-null references held by the fields of an object or the elements of an array
+    pub fn references(&self, ma: &MethodArea, obj_ref: ObjectRef) -> Vec<ObjectRef> {
+        let class_word = unsafe { (*obj_ref.0).class };
+        if let Some(elem) = elem_kind_from_word(class_word) {
+            if let ValueKind::Base(_) = elem {
+                return Vec::new();
+            }
+            let arr = unsafe { obj_ref.cast_to_array() };
+            return (0..self.arr_len(arr))
+                .filter_map(|idx| self.load_arr_elem(arr, idx).object())
+                .collect();
+        }
+
+        let mut refs = Vec::new();
+        let mut cur = Some(class_from_word(class_word));
+        while let Some(class) = cur {
+            for &field in &ma.classes[class].fields {
+                let field = &ma.fields[field];
+                let kind = ValueKind::of(&field.parsed_descriptor.0);
+                if let (FieldBacking::Instance(offset), ValueKind::Object | ValueKind::Array) =
+                    (&field.backing, kind)
+                {
+                    let val =
+                        unsafe { load_value(obj_ref.0.byte_add(*offset as usize).cast(), kind) };
+                    refs.extend(val.object());
+                }
+            }
+            cur = ma.classes[class].super_class;
+        }
+        refs
+    }
+
+    pub fn get_obj_class(&self, obj_ref: ObjectRef) -> ClassId {
+        class_from_word(unsafe { (*obj_ref.0).class })
+    }
+
+    fn mark_word_atomic(&self, obj_ref: ObjectRef) -> &AtomicU64 {
+        // Safety: objects are aligned to MIN_OBJECT_ALIGN, and the mark word is at offset 0
+        unsafe { AtomicU64::from_ptr(std::ptr::addr_of_mut!((*obj_ref.0).mark)) }
+    }
+
+    pub fn mark_word(&self, obj_ref: ObjectRef) -> MarkWord {
+        MarkWord(self.mark_word_atomic(obj_ref).load(Ordering::Acquire))
+    }
+
+    /// Returns the identity hash of the object, assigning one if it does not have one yet. A
+    /// locked or inflated object's mark word points to a lock record or monitor whose first word
+    /// is the displaced mark word, which is where the hash is kept until the object is unlocked.
+    pub fn identity_hash(&self, obj_ref: ObjectRef) -> i32 {
+        let mut mark = self.mark_word(obj_ref);
+        loop {
+            let word = match mark.lock_state() {
+                LockState::Unlocked => self.mark_word_atomic(obj_ref),
+                LockState::Locked | LockState::Inflated => {
+                    // Safety: the record outlives the lock, and the object can't be unlocked and
+                    // the record reused while we still see its address in the mark word
+                    let displaced = unsafe { AtomicU64::from_ptr(mark.pointer() as *mut u64) };
+                    if let Some(hash) = Self::install_hash(displaced) {
+                        return hash;
+                    }
+                    // The owner restored the mark word before we could install the hash
+                    mark = self.mark_word(obj_ref);
+                    continue;
+                }
+                LockState::Marked => panic!("identity hash of an object during GC"),
+            };
+            match Self::install_hash(word) {
+                Some(hash) => return hash,
+                None => mark = self.mark_word(obj_ref),
+            }
+        }
+    }
+
+    /// Reads the hash from an unlocked mark word, assigning one if needed. Returns None if the
+    /// word is no longer unlocked.
+    fn install_hash(word: &AtomicU64) -> Option<i32> {
+        let mut mark = MarkWord(word.load(Ordering::Acquire));
+        while mark.lock_state() == LockState::Unlocked {
+            if let Some(hash) = mark.hash() {
+                return Some(hash);
+            }
+            let new = mark.with_hash(next_identity_hash());
+            match word.compare_exchange(mark.0, new.0, Ordering::AcqRel, Ordering::Acquire) {
+                Ok(_) => return new.hash(),
+                Err(current) => mark = MarkWord(current),
+            }
+        }
+        None
+    }
+
+    pub fn array_copy(
+        &mut self,
+        src_ref: ArrayRef,
+        src_idx: usize,
+        dst_ref: ArrayRef,
+        dst_idx: usize,
+        len: usize,
+    ) {
+        unsafe {
+            let src_arr = &*src_ref.0;
+            let dst_arr = &*dst_ref.0;
+            assert_eq!({ src_arr.header.class }, { dst_arr.header.class });
+            assert!(src_idx + len <= src_arr.len as usize);
+            assert!(dst_idx + len <= dst_arr.len as usize);
+
+            let (_, offset, stride) = arr_layout(self.arr_elem_kind(src_ref), 0);
+            let src_ptr = src_ref.0.cast::<u8>().add(offset + src_idx * stride);
+            let dst_ptr = dst_ref.0.cast::<u8>().add(offset + dst_idx * stride);
+            std::ptr::copy(src_ptr, dst_ptr, len * stride);
+        }
+    }
+
+    pub fn load_field(&self, obj_ref: ObjectRef, field_id: FieldId) -> Value {
+        let field = &method_area().fields[field_id];
+        let ty = &field.parsed_descriptor.0;
+        let offset = match field.backing {
+            FieldBacking::Instance(offset) => offset,
+            _ => panic!("tried to load from a static field with instance obj"),
+        };
+
+        unsafe {
+            let field_ptr = obj_ref.0.byte_offset(offset as isize);
+            load_value(field_ptr.cast::<u8>(), ValueKind::of(ty))
+        }
+    }
+
+    pub fn store_field(
+        &mut self,
+        ma: &MethodArea,
+        obj_ref: ObjectRef,
+        field_id: FieldId,
+        val: Value,
+    ) {
+        let field = &ma.fields[field_id];
+        let offset = match field.backing {
+            FieldBacking::Instance(offset) => offset,
+            _ => panic!("tried to store into a static field with instance obj"),
+        };
+
+        unsafe {
+            let field_ptr = obj_ref.0.byte_offset(offset as isize);
+            store_value(field_ptr.cast::<u8>(), val);
+        }
+    }
+
+    unsafe fn arr_elem_ptr(arr_ref: ArrayRef, elem: ValueKind, idx: usize) -> *mut u8 {
+        let (_, offset, stride) = arr_layout(elem, 0);
+        arr_ref.0.cast::<u8>().add(offset + idx * stride)
+    }
+
+    pub fn load_arr_elem(&self, arr_ref: ArrayRef, idx: usize) -> Value {
+        let elem = self.arr_elem_kind(arr_ref);
+        unsafe {
+            let elem_ptr = Self::arr_elem_ptr(arr_ref, elem, idx);
+            load_value(elem_ptr, elem)
+        }
+    }
+
+    pub fn store_arr_elem(&self, arr_ref: ArrayRef, idx: usize, val: Value) {
+        let elem = self.arr_elem_kind(arr_ref);
+        unsafe {
+            let elem_ptr = Self::arr_elem_ptr(arr_ref, elem, idx);
+            store_value(elem_ptr, val);
+        }
+    }
+
+    /// The caller must make sure that `T` has the same layout as the elements of the array
+    pub unsafe fn array_contents_unchecked<T>(&mut self, arr_ref: ArrayRef) -> &mut [T] {
+        let len = (*arr_ref.0).len as usize;
+        let (_, offset) = Layout::new::<Array>().extend(Layout::new::<T>()).unwrap();
+        let data_ptr = arr_ref.0.byte_add(offset);
+        std::slice::from_raw_parts_mut(data_ptr.cast::<T>(), len)
+    }
+
+    pub fn array_contents<T: MatchesFieldType>(&mut self, arr_ref: ArrayRef) -> &mut [T] {
+        let ValueKind::Base(elem_ty) = self.arr_elem_kind(arr_ref) else {
+            panic!("array of references");
+        };
+        assert!(T::matches_field_type(&FieldType::BaseType(elem_ty)));
+        unsafe { self.array_contents_unchecked(arr_ref) }
+    }
+
+    pub fn read_string(&mut self, str_obj: ObjectRef) -> String {
+        let str_class = self.get_obj_class(str_obj);
+
+        let ma = method_area();
+        let value_field = ma
+            .resolve_field(str_class, Symbol::intern("value"), Symbol::intern("[B"))
+            .unwrap();
+        let coder_field = ma
+            .resolve_field(str_class, Symbol::intern("coder"), Symbol::intern("B"))
+            .unwrap();
+        drop(ma);
+
+        let value = match self.load_field(str_obj, value_field) {
+            Value::Array(Some(arr)) => arr,
+            _ => unreachable!(),
+        };
+        let coder = match self.load_field(str_obj, coder_field) {
+            Value::Byte(val) => val,
+            _ => unreachable!(),
+        };
+        if coder == 0 {
+            let utf8 = self
+                .array_contents(value)
+                .iter()
+                .map(|c: &i8| *c as u8)
+                .collect();
+            String::from_utf8(utf8).unwrap()
+        } else {
+            let utf16 = self
+                .array_contents(value)
+                .chunks_exact(2)
+                .map(|c: &[i8]| match *c {
+                    [a, b] => u16::from_ne_bytes([a as u8, b as u8]),
+                    _ => unreachable!(),
+                })
+                .collect::<Vec<_>>();
+            String::from_utf16(&utf16).unwrap()
+        }
+    }
+
+    pub fn create_string(&mut self, ma: &mut MethodArea, str: &str) -> ObjectRef {
+        let arr: Vec<i8> = str
+            .encode_utf16()
+            .flat_map(|x| x.to_ne_bytes())
+            .map(|b| b as i8)
+            .collect();
+        let arr_ref = Heap::new_array(ma, FieldType::BaseType(BaseType::B), arr.len());
+        // Safety: this was just created as a byte array
+        unsafe { self.array_contents_unchecked(arr_ref) }.copy_from_slice(&arr);
+
+        let str_class = ma
+            .resolve_class(Symbol::intern("java/lang/String"))
+            .unwrap();
+        let value_field = ma
+            .resolve_field(str_class, Symbol::intern("value"), Symbol::intern("[B"))
+            .unwrap();
+        let coder_field = ma
+            .resolve_field(str_class, Symbol::intern("coder"), Symbol::intern("B"))
+            .unwrap();
+
+        // The VM creates strings before String is initialized
+        ma.link_class(str_class).unwrap_or_else(|err| err.throw());
+        let layout = ma.classes[str_class].instance_layout();
+        let str_obj_id = Heap::new_object(str_class, layout);
+        self.store_field(ma, str_obj_id, value_field, Value::Array(Some(arr_ref)));
+        self.store_field(ma, str_obj_id, coder_field, Value::Byte(1));
+
+        str_obj_id
+    }
+}
+
+/// We CANNOT keep these around between GC runs unless it is somewhere the GC can see,
+/// i.e. in an object or array.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ObjectRef(*mut Object);
+
+impl ObjectRef {
+    pub fn inner_ptr(self) -> *mut Object {
+        self.0
+    }
+
+    pub unsafe fn from_ptr(ptr: *mut Object) -> Option<ObjectRef> {
+        (!ptr.is_null()).then_some(ObjectRef(ptr))
+    }
+
+    pub unsafe fn cast_to_array(self) -> ArrayRef {
+        ArrayRef(self.0.cast::<Array>())
+    }
+}
+
+unsafe impl Send for ObjectRef {}
+unsafe impl Sync for ObjectRef {}
+
+/// We CANNOT keep these around between GC runs unless it is somewhere the GC can see,
+/// i.e. in an object or array.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[repr(transparent)]
+pub struct ArrayRef(*mut Array);
+
+impl ArrayRef {
+    pub unsafe fn inner_ptr(self) -> *mut Array {
+        self.0
+    }
+
+    pub unsafe fn from_ptr(ptr: *mut Array) -> Option<ArrayRef> {
+        (!ptr.is_null()).then_some(ArrayRef(ptr))
+    }
+
+    pub fn cast_to_object(self) -> ObjectRef {
+        ObjectRef(self.0.cast::<Object>())
+    }
+}
+
+unsafe impl Send for ArrayRef {}
+unsafe impl Sync for ArrayRef {}
+
+// We need Object and Array to be repr(C) to guarantee the order of the fields are as written.
+// They are packed so that fields and the array length can start right after the class word.
+/// The header of every object and array
+#[derive(Clone, Copy, Debug)]
+#[repr(C, packed(4))]
+pub struct Object {
+    /// See [`MarkWord`]
+    mark: u64,
+    /// See [`class_word`]
+    class: u32,
+}
+
+/// The elements follow the header, aligned for the element type
+#[derive(Debug)]
+#[repr(C, packed(4))]
+pub struct Array {
+    // Having Object as the first field allows us to cast array pointers to object pointers
+    header: Object,
+    len: u32,
+}
+
+#[test]
+fn mark_word_test() {
+    let mark = MarkWord::INITIAL;
+    assert_eq!(mark.lock_state(), LockState::Unlocked);
+    assert_eq!(mark.hash(), None);
+    assert_eq!(mark.age(), 0);
+
+    let mark = mark.with_hash(0x7fff_ffff).with_age(20);
+    assert_eq!(mark.lock_state(), LockState::Unlocked);
+    assert_eq!(mark.hash(), Some(0x7fff_ffff));
+    assert_eq!(mark.age(), 15);
+    assert_eq!(mark.with_age(3).hash(), Some(0x7fff_ffff));
+
+    let forwarded = MarkWord::from_pointer(LockState::Marked, 0x1234_5678);
+    assert_eq!(forwarded.lock_state(), LockState::Marked);
+    assert_eq!(forwarded.pointer(), 0x1234_5678);
+
+    assert_eq!(std::mem::size_of::<Object>(), 12);
+    assert_eq!(std::mem::size_of::<Array>(), 16);
+}
+
+#[test]
+fn identity_hash_test() {
+    let heap = Heap;
+    // Backing storage for an object header, aligned for the mark word
+    let mut storage = [0u64; 2];
+    let obj = ObjectRef(storage.as_mut_ptr().cast::<Object>());
+    heap.mark_word_atomic(obj)
+        .store(MarkWord::INITIAL.0, Ordering::Relaxed);
+    let hash = heap.identity_hash(obj);
+    assert_eq!(heap.identity_hash(obj), hash);
+
+    // Locking displaces the mark word into the lock record, which keeps the hash
+    let record = AtomicU64::new(heap.mark_word(obj).0);
+    let locked = MarkWord::from_pointer(LockState::Locked, record.as_ptr() as usize);
+    heap.mark_word_atomic(obj)
+        .store(locked.0, Ordering::Relaxed);
+    assert_eq!(heap.identity_hash(obj), hash);
+
+    // An object that gets its hash while locked keeps it once unlocked
+    heap.mark_word_atomic(obj)
+        .store(MarkWord::INITIAL.0, Ordering::Relaxed);
+    let record = AtomicU64::new(MarkWord::INITIAL.0);
+    let inflated = MarkWord::from_pointer(LockState::Inflated, record.as_ptr() as usize);
+    heap.mark_word_atomic(obj)
+        .store(inflated.0, Ordering::Relaxed);
+    let hash = heap.identity_hash(obj);
+    heap.mark_word_atomic(obj)
+        .store(record.load(Ordering::Relaxed), Ordering::Relaxed);
+    assert_eq!(heap.identity_hash(obj), hash);
+
+    for kind in [
+        ValueKind::Base(BaseType::B),
+        ValueKind::Base(BaseType::Z),
+        ValueKind::Object,
+        ValueKind::Array,
+    ] {
+        assert_eq!(ValueKind::from_bits(kind.to_bits()), Some(kind));
+    }
+    assert_eq!(ValueKind::from_bits(0), None);
+}
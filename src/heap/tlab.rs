@@ -0,0 +1,161 @@
+//! Thread-local allocation buffers
+//!
+//! Each thread allocates by bumping a pointer through its own buffer, without taking any locks.
+//! When a buffer runs out, a new one is carved out of a large region which is shared between all
+//! threads. Regions are zeroed when they are created, and memory is never reused, so everything
+//! handed out is already zeroed.
+//!
+//! With compressed references, there is only one shared region: the whole heap, which is reserved
+//! up front so that every object can be addressed by a 32-bit offset from its start.
+
+use crate::CONFIG;
+use nix::sys::mman::{mmap_anonymous, MapFlags, ProtFlags};
+use std::alloc::{alloc_zeroed, handle_alloc_error, Layout};
+use std::cell::Cell;
+use std::num::NonZeroUsize;
+use std::sync::{LazyLock, Mutex};
+
+const REGION_SIZE: usize = 32 << 20;
+/// The most that 32-bit offsets can address
+const COMPRESSED_HEAP_SIZE: usize = 4 << 30;
+const TLAB_SIZE: usize = 256 << 10;
+/// Larger allocations go straight to the shared region so they don't waste most of a buffer
+const MAX_TLAB_ALLOC: usize = TLAB_SIZE / 8;
+/// Alignment of regions and buffers, which is enough for any object or array
+const ALIGN: usize = 16;
+
+/// A span of zeroed memory that is allocated from by bumping `top` towards `end`
+#[derive(Clone, Copy)]
+struct BumpRegion {
+    top: usize,
+    end: usize,
+}
+
+impl BumpRegion {
+    const EMPTY: BumpRegion = BumpRegion { top: 0, end: 0 };
+
+    fn alloc(&mut self, layout: Layout) -> Option<*mut u8> {
+        let start = self.top.checked_next_multiple_of(layout.align())?;
+        let new_top = start.checked_add(layout.size())?;
+        if new_top > self.end {
+            return None;
+        }
+        self.top = new_top;
+        Some(start as *mut u8)
+    }
+}
+
+static SHARED_REGION: Mutex<BumpRegion> = Mutex::new(BumpRegion::EMPTY);
+
+/// The start of the reserved heap, only used with compressed references. Pages are only backed by
+/// memory once they are touched.
+static HEAP_BASE: LazyLock<usize> = LazyLock::new(|| {
+    let ptr = unsafe {
+        mmap_anonymous(
+            None,
+            NonZeroUsize::new(COMPRESSED_HEAP_SIZE).unwrap(),
+            ProtFlags::PROT_READ | ProtFlags::PROT_WRITE,
+            MapFlags::MAP_PRIVATE | MapFlags::MAP_NORESERVE,
+        )
+    };
+    ptr.expect("failed to reserve heap").as_ptr() as usize
+});
+
+pub fn heap_base() -> usize {
+    *HEAP_BASE
+}
+
+thread_local! {
+    static TLAB: Cell<BumpRegion> = const { Cell::new(BumpRegion::EMPTY) };
+}
+
+fn alloc_zeroed_checked(layout: Layout) -> *mut u8 {
+    let ptr = unsafe { alloc_zeroed(layout) };
+    if ptr.is_null() {
+        handle_alloc_error(layout);
+    }
+    ptr
+}
+
+/// Allocate zeroed memory from the current thread's buffer
+pub fn allocate(layout: Layout) -> *mut u8 {
+    if layout.size() > MAX_TLAB_ALLOC {
+        return allocate_shared(layout);
+    }
+
+    TLAB.with(|tlab| {
+        let mut region = tlab.get();
+        let ptr = match region.alloc(layout) {
+            Some(ptr) => ptr,
+            None => {
+                // The rest of the old buffer is abandoned
+                let start = allocate_shared(Layout::from_size_align(TLAB_SIZE, ALIGN).unwrap());
+                region = BumpRegion {
+                    top: start as usize,
+                    end: start as usize + TLAB_SIZE,
+                };
+                region.alloc(layout).unwrap()
+            }
+        };
+        tlab.set(region);
+        ptr
+    })
+}
+
+/// The slow path, which allocates from the shared region under a lock
+fn allocate_shared(layout: Layout) -> *mut u8 {
+    if !CONFIG.compressed_refs && layout.size() > REGION_SIZE / 2 {
+        return alloc_zeroed_checked(layout.align_to(ALIGN).unwrap());
+    }
+
+    let mut shared = SHARED_REGION.lock().unwrap();
+    if let Some(ptr) = shared.alloc(layout) {
+        return ptr;
+    }
+    if CONFIG.compressed_refs {
+        if shared.end != 0 {
+            panic!("OutOfMemoryError");
+        }
+        // Offset 0 is used for null, so nothing is allocated there
+        *shared = BumpRegion {
+            top: heap_base() + ALIGN,
+            end: heap_base() + COMPRESSED_HEAP_SIZE,
+        };
+    } else {
+        let start = alloc_zeroed_checked(Layout::from_size_align(REGION_SIZE, ALIGN).unwrap());
+        *shared = BumpRegion {
+            top: start as usize,
+            end: start as usize + REGION_SIZE,
+        };
+    }
+    shared
+        .alloc(layout)
+        .unwrap_or_else(|| panic!("OutOfMemoryError"))
+}
+
+#[test]
+fn tlab_test() {
+    let layouts = [
+        Layout::from_size_align(12, 4).unwrap(),
+        Layout::from_size_align(24, 8).unwrap(),
+        Layout::from_size_align(MAX_TLAB_ALLOC + 1, 8).unwrap(),
+        Layout::from_size_align(REGION_SIZE, 8).unwrap(),
+    ];
+    let mut allocations = Vec::new();
+    for _ in 0..2 * TLAB_SIZE / 36 {
+        for layout in &layouts[..2] {
+            allocations.push((allocate(*layout), *layout));
+        }
+    }
+    for layout in &layouts[2..] {
+        allocations.push((allocate(*layout), *layout));
+    }
+
+    for &(ptr, layout) in &allocations {
+        assert_eq!(ptr as usize % layout.align(), 0);
+        let bytes = unsafe { std::slice::from_raw_parts_mut(ptr, layout.size()) };
+        assert!(bytes.iter().all(|&b| b == 0));
+        // Any later allocation that overlaps this one would no longer be zeroed
+        bytes.fill(0xff);
+    }
+}
@@ -1,11 +1,11 @@
 use super::Thread;
 use crate::class::Class;
-use crate::class_file::constant_pool::CPInfo;
-use crate::class_file::descriptors::{BaseType, FieldType, ObjectType};
+use crate::class_file::descriptors::{BaseType, FieldType};
 use crate::class_loader::method_area;
-use crate::heap::heap;
+use crate::error::JavaError;
+use crate::heap::{heap, Heap, ValueKind};
+use crate::runtime_constant_pool::Entry;
 use crate::value::Value;
-use std::cmp::Ordering;
 
 macro_rules! binary_op {
     ($self:ident, $op:tt) => {{
@@ -27,37 +27,48 @@ macro_rules! unary_op {
     }};
 }
 
-macro_rules! cast {
-    ($self:ident, $from:ident, $to:ident, $val:ident -> $expr:expr) => {{
+macro_rules! convert {
+    ($self:ident, $to:ident) => {{
         let val = $self.pop();
-        let new = match val {
-            Value::$from($val) => Value::$to($expr),
-            _ => unreachable!(),
-        };
-        $self.operand_stack.push(new);
+        $self.operand_stack.push(val.convert(BaseType::$to));
     }};
 }
 
 impl Thread {
     fn ldc(&mut self, cp_idx: u16) {
-        let class_id = self.class_id();
-        let mut ma = method_area();
-        let cp_info = &ma.classes[class_id].constant_pool.table[cp_idx as usize - 1];
-        let val = match *cp_info {
-            CPInfo::Integer { val } => Value::Int(val),
-            CPInfo::Float { val } => Value::Float(val),
-            CPInfo::Long { val } => Value::Long(val),
-            CPInfo::Double { val } => Value::Double(val),
-            CPInfo::String { string_index } => {
-                let str = ma.classes[class_id].constant_pool.utf8(string_index);
-                Value::Object(Some(heap().create_string(&mut ma, &str)))
-            }
-            CPInfo::Class { .. } => {
-                drop(ma);
-                let c = Class::class_reference(class_id, cp_idx);
+        let val = match *self.constant_pool.entry(cp_idx) {
+            Entry::Integer(val) => Value::Int(val),
+            Entry::Float(val) => Value::Float(val),
+            Entry::Long(val) => Value::Long(val),
+            Entry::Double(val) => Value::Double(val),
+            Entry::String { .. } => Value::Object(Some(self.constant_pool.string(cp_idx))),
+            Entry::Class { .. } => {
+                let c = self
+                    .constant_pool
+                    .class(cp_idx)
+                    .unwrap_or_else(|err| err.throw());
                 Value::Object(Some(Class::obj(c)))
             }
-            _ => unimplemented!(),
+            Entry::MethodHandle { .. } => {
+                let handle = self
+                    .constant_pool
+                    .method_handle(cp_idx, self.class_id())
+                    .unwrap_or_else(|err| err.throw());
+                Value::Object(Some(handle))
+            }
+            Entry::MethodType { .. } => {
+                let method_type = self
+                    .constant_pool
+                    .method_type(cp_idx)
+                    .unwrap_or_else(|err| err.throw());
+                Value::Object(Some(method_type))
+            }
+            Entry::Dynamic { name, .. } => JavaError::new(
+                "java/lang/BootstrapMethodError",
+                format!("dynamically-computed constant {} is not supported", name),
+            )
+            .throw(),
+            _ => panic!("ClassFormatError"),
         };
         self.operand_stack.push(val);
     }
@@ -67,8 +78,8 @@ impl Thread {
             let cur_pc = self.pc;
             let opcode = self.read_ins();
             let c = self.class_id();
-            let class_name = method_area().classes[c].name.clone();
-            let method_name = method_area().methods[self.method].name.clone();
+            let class_name = method_area().classes[c].name;
+            let method_name = method_area().methods[self.method].name;
             println!(
                 "m: {}.{}, pc: {}, opcode: {}",
                 class_name, method_name, cur_pc, opcode
@@ -208,7 +219,7 @@ impl Thread {
                         panic!("NullPointerException");
                     };
                     let heap = heap();
-                    let store_val = val.store_ty(heap.arr_ty(arr));
+                    let store_val = heap.arr_elem_kind(arr).store_ty(val);
                     heap.store_arr_elem(arr, idx as usize, store_val);
                 }
                 // pop
@@ -316,21 +327,37 @@ impl Thread {
                 // dmul
                 107 => binary_op!(self, *),
                 // idiv
-                108 => binary_op!(self, /),
+                108 => {
+                    binary_op!(self, (lhs, rhs) => lhs.div(rhs).unwrap_or_else(|err| err.throw()))
+                }
                 // ldiv
-                109 => binary_op!(self, /),
+                109 => {
+                    binary_op!(self, (lhs, rhs) => lhs.div(rhs).unwrap_or_else(|err| err.throw()))
+                }
                 // fdiv
-                110 => binary_op!(self, /),
+                110 => {
+                    binary_op!(self, (lhs, rhs) => lhs.div(rhs).unwrap_or_else(|err| err.throw()))
+                }
                 // ddiv
-                111 => binary_op!(self, /),
+                111 => {
+                    binary_op!(self, (lhs, rhs) => lhs.div(rhs).unwrap_or_else(|err| err.throw()))
+                }
                 // irem
-                112 => binary_op!(self, %),
+                112 => {
+                    binary_op!(self, (lhs, rhs) => lhs.rem(rhs).unwrap_or_else(|err| err.throw()))
+                }
                 // lrem
-                113 => binary_op!(self, %),
+                113 => {
+                    binary_op!(self, (lhs, rhs) => lhs.rem(rhs).unwrap_or_else(|err| err.throw()))
+                }
                 // frem
-                114 => binary_op!(self, %),
+                114 => {
+                    binary_op!(self, (lhs, rhs) => lhs.rem(rhs).unwrap_or_else(|err| err.throw()))
+                }
                 // drem
-                115 => binary_op!(self, %),
+                115 => {
+                    binary_op!(self, (lhs, rhs) => lhs.rem(rhs).unwrap_or_else(|err| err.throw()))
+                }
                 // ineg
                 116 => unary_op!(self, -),
                 // lneg
@@ -368,67 +395,46 @@ impl Thread {
                     let idx = self.read_ins() as usize;
                     let c = self.read_ins() as i8 as i32;
                     match &mut self.locals[idx] {
-                        Some(Value::Int(val)) => *val += c,
+                        Some(Value::Int(val)) => *val = val.wrapping_add(c),
                         _ => unreachable!(),
                     }
                 }
                 // i2l
-                133 => cast!(self, Int, Long, val -> val as i64),
+                133 => convert!(self, J),
                 // i2f
-                134 => cast!(self, Int, Float, val -> val as f32),
+                134 => convert!(self, F),
                 // i2d
-                135 => cast!(self, Int, Double, val -> val as f64),
+                135 => convert!(self, D),
                 // l2i
-                136 => cast!(self, Long, Int, val -> val as i32),
+                136 => convert!(self, I),
                 // l2f
-                137 => cast!(self, Long, Float, val -> val as f32),
+                137 => convert!(self, F),
                 // l2d
-                138 => cast!(self, Long, Double, val -> val as f64),
+                138 => convert!(self, D),
                 // f2i
-                139 => cast!(self, Float, Int, val -> val as i32),
+                139 => convert!(self, I),
                 // f2l
-                140 => cast!(self, Float, Long, val -> val as i64),
+                140 => convert!(self, J),
                 // f2d
-                141 => cast!(self, Float, Double, val -> val as f64),
+                141 => convert!(self, D),
                 // d2i
-                142 => cast!(self, Double, Int, val -> val as i32),
+                142 => convert!(self, I),
                 // d2l
-                143 => cast!(self, Double, Long, val -> val as i64),
+                143 => convert!(self, J),
                 // d2f
-                144 => cast!(self, Double, Float, val -> val as f32),
+                144 => convert!(self, F),
                 // i2b
-                145 => cast!(self, Int, Int, val -> (val as i8) as i32),
+                145 => convert!(self, B),
                 // i2c
-                146 => cast!(self, Int, Int, val -> val & 0xFF),
+                146 => convert!(self, C),
                 // i2s
-                147 => cast!(self, Int, Int, val -> val & 0xFFFF),
+                147 => convert!(self, S),
                 // lcmp
-                148 => {
-                    let value2 = self.pop().long();
-                    let value1 = self.pop().long();
-                    let res = match value1.cmp(&value2) {
-                        Ordering::Greater => 1,
-                        Ordering::Equal => 0,
-                        Ordering::Less => -1,
-                    };
-                    self.operand_stack.push(Value::Int(res));
-                }
-                // fcmpg, fcmpl
-                149..=150 => {
-                    let value2 = self.pop().float();
-                    let value1 = self.pop().float();
-                    let res = match value1.partial_cmp(&value2) {
-                        Some(Ordering::Greater) => 1,
-                        Some(Ordering::Equal) => 0,
-                        Some(Ordering::Less) => -1,
-                        _ => match opcode {
-                            149 => -1,
-                            150 => 1,
-                            _ => unreachable!(),
-                        },
-                    };
-                    self.operand_stack.push(Value::Int(res));
-                }
+                148 => binary_op!(self, (lhs, rhs) => Value::Int(lhs.compare(rhs, 0))),
+                // fcmpl, dcmpl
+                149 | 151 => binary_op!(self, (lhs, rhs) => Value::Int(lhs.compare(rhs, -1))),
+                // fcmpg, dcmpg
+                150 | 152 => binary_op!(self, (lhs, rhs) => Value::Int(lhs.compare(rhs, 1))),
                 // if<cond>
                 153..=158 => {
                     let val = self.pop().int();
@@ -487,13 +493,13 @@ impl Thread {
                     let high = self.read_i32();
 
                     if idx < low || idx > high {
-                        self.pc = base_pc.saturating_add_signed(default as isize);
+                        self.jump(base_pc, base_pc.saturating_add_signed(default as isize));
                         continue;
                     }
 
                     self.pc += 4 * (idx - low) as usize;
                     let offset = self.read_i32();
-                    self.pc = base_pc.saturating_add_signed(offset as isize);
+                    self.jump(base_pc, base_pc.saturating_add_signed(offset as isize));
                 }
                 // lookupswitch
                 171 => {
@@ -506,11 +512,11 @@ impl Thread {
                         let m = self.read_i32();
                         let offset = self.read_i32();
                         if key == m {
-                            self.pc = base_pc.saturating_add_signed(offset as isize);
+                            self.jump(base_pc, base_pc.saturating_add_signed(offset as isize));
                             continue 'inst;
                         }
                     }
-                    self.pc = base_pc.saturating_add_signed(default as isize);
+                    self.jump(base_pc, base_pc.saturating_add_signed(default as isize));
                 }
                 // ireturn
                 172 => return self.operand_stack.pop(),
@@ -527,8 +533,7 @@ impl Thread {
                 // getstatic
                 178 => {
                     let idx = self.read_u16();
-                    let class_id = self.class_id();
-                    let field = Class::field_reference(class_id, idx);
+                    let field = self.accessed_field(idx, true);
                     let defining_class = method_area().fields[field].defining_class;
                     self.ensure_initialized(defining_class);
                     self.operand_stack
@@ -537,8 +542,7 @@ impl Thread {
                 // putstatic
                 179 => {
                     let idx = self.read_u16();
-                    let class_id = self.class_id();
-                    let field = Class::field_reference(class_id, idx);
+                    let field = self.accessed_field(idx, true);
                     let defining_class = method_area().fields[field].defining_class;
                     self.ensure_initialized(defining_class);
                     method_area().fields[field].store_static(self.pop());
@@ -546,8 +550,7 @@ impl Thread {
                 // getfield
                 180 => {
                     let idx = self.read_u16();
-                    let class_id = self.class_id();
-                    let field = Class::field_reference(class_id, idx);
+                    let field = self.accessed_field(idx, false);
                     let Some(obj) = self.pop().object() else {
                         panic!("NullPointerException");
                     };
@@ -557,37 +560,34 @@ impl Thread {
                 // putfield
                 181 => {
                     let idx = self.read_u16();
-                    let class_id = self.class_id();
-                    let field = Class::field_reference(class_id, idx);
+                    let field = self.accessed_field(idx, false);
                     let val = self.pop();
                     let Some(obj) = self.pop().object() else {
                         panic!("NullPointerException");
                     };
                     let ma = method_area();
-                    let ty = &ma.fields[field].descriptor.0;
+                    let ty = &ma.fields[field].parsed_descriptor.0;
                     let store_val = val.store_ty(&ty);
                     heap().store_field(&ma, obj, field, store_val);
                 }
                 // invokevirtual
                 182 => {
                     let idx = self.read_u16();
-                    let class_id = self.class_id();
-                    let method = Class::method_reference(class_id, idx);
-                    let method = self.select_method(method);
+                    let method = self.invoked_method(idx, false);
+                    let method = self.select_method(cur_pc, method);
                     self.call_method(method);
                 }
                 // invokespecial
                 183 => {
                     let idx = self.read_u16();
-                    let class_id = self.class_id();
-                    let method = Class::method_reference(class_id, idx);
+                    let method = self.invoked_method(idx, false);
+                    let method = self.select_special_method(idx, method);
                     self.call_method(method);
                 }
                 // invokestatic
                 184 => {
                     let idx = self.read_u16();
-                    let class_id = self.class_id();
-                    let method = Class::method_reference(class_id, idx);
+                    let method = self.invoked_method(idx, true);
                     let defining_class = method_area().methods[method].defining_class;
                     self.ensure_initialized(defining_class);
                     self.call_method(method);
@@ -595,11 +595,24 @@ impl Thread {
                 // invokeinterface
                 185 => {
                     let idx = self.read_u16();
-                    let class_id = self.class_id();
-                    let method = Class::method_reference(class_id, idx);
-                    let method = self.select_method(method);
-                    let defining_class = method_area().methods[method].defining_class;
-                    self.ensure_initialized(defining_class);
+                    let resolved = self.invoked_method(idx, false);
+                    let method = self.select_method(cur_pc, resolved);
+                    let ma = method_area();
+                    if !ma.methods[resolved].is_private() && !ma.methods[method].is_public() {
+                        let method = &ma.methods[method];
+                        let err = JavaError::new(
+                            "java/lang/IllegalAccessError",
+                            format!(
+                                "{}.{}{} is not public",
+                                ma.classes[method.defining_class].name,
+                                method.name,
+                                method.descriptor
+                            ),
+                        );
+                        drop(ma);
+                        err.throw();
+                    }
+                    drop(ma);
                     self.call_method(method);
 
                     // here for historical reasons
@@ -609,10 +622,13 @@ impl Thread {
                 // new
                 187 => {
                     let idx = self.read_u16();
-                    let class_id = self.class_id();
-                    let obj_class = Class::class_reference(class_id, idx);
+                    let obj_class = self
+                        .constant_pool
+                        .class(idx)
+                        .unwrap_or_else(|err| err.throw());
                     self.ensure_initialized(obj_class);
-                    let obj_ref = heap().new_object(&mut method_area(), obj_class);
+                    let layout = self.constant_pool.instance_layout(idx);
+                    let obj_ref = Heap::new_object(obj_class, layout);
                     self.operand_stack.push(Value::Object(Some(obj_ref)))
                 }
                 // newarray
@@ -631,20 +647,24 @@ impl Thread {
                         11 => FieldType::BaseType(BaseType::J),
                         _ => panic!(),
                     };
-                    let arr = heap().new_array(&mut method_area(), ty, count as usize);
+                    let arr = Heap::new_array(&mut method_area(), ty, count as usize);
                     self.operand_stack.push(Value::Array(Some(arr)));
                 }
                 // anewarray
                 189 => {
                     let idx = self.read_u16();
-                    let class_id = self.class_id();
-                    let item_class = Class::class_reference(class_id, idx);
-                    let class_name = method_area().classes[item_class].name.clone();
+                    let item_class = self
+                        .constant_pool
+                        .class(idx)
+                        .unwrap_or_else(|err| err.throw());
                     let count = self.pop().int();
                     assert!(count >= 0, "NegativeArraySizeException");
 
-                    let ty = FieldType::ObjectType(ObjectType { class_name });
-                    let arr = heap().new_array(&mut method_area(), ty, count as usize);
+                    let mut ma = method_area();
+                    let arr_class = ma.array_class_of(item_class);
+                    let elem = ValueKind::of(ma.classes[arr_class].elem_ty.as_ref().unwrap());
+                    drop(ma);
+                    let arr = Heap::new_array_of_class(arr_class, elem, count as usize);
                     self.operand_stack.push(Value::Array(Some(arr)));
                 }
                 // arraylength
@@ -661,23 +681,8 @@ impl Thread {
                     let Some(throwable_obj) = self.pop().object() else {
                         panic!("NullPointerException");
                     };
-                    let class_id = heap().get_obj_class(throwable_obj);
-                    println!(
-                        "An exception was thrown: {}",
-                        method_area().classes[class_id].name
-                    );
-
-                    let mut ma = method_area();
-                    let throwable_class = ma.resolve_class("java/lang/Throwable");
-                    let details_field = ma.resolve_field(throwable_class, "detailMessage");
-                    drop(ma);
-                    let details = heap().load_field(throwable_obj, details_field).object();
-                    if let Some(details) = details {
-                        let details = heap().read_string(details);
-                        println!("The following details were given: {}", details);
-                    }
-
-                    panic!("todo: exceptions");
+                    // Exception handlers aren't supported yet, so this unwinds the thread
+                    JavaError::from_throwable(throwable_obj).throw();
                 }
                 // checkcast
                 192 => {
@@ -688,16 +693,14 @@ impl Thread {
                         continue;
                     }
 
-                    let class_id = self.class_id();
-                    let ref_class = Class::class_reference(class_id, cp_idx);
+                    let ref_class = self
+                        .constant_pool
+                        .class(cp_idx)
+                        .unwrap_or_else(|err| err.throw());
 
                     let obj_class = match val {
                         Value::Object(Some(obj)) => heap().get_obj_class(obj),
-                        Value::Array(Some(arr)) => {
-                            let heap = heap();
-                            let elem_ty = heap.arr_ty(arr);
-                            method_area().resolve_arr_class(elem_ty)
-                        }
+                        Value::Array(Some(arr)) => heap().get_obj_class(arr.cast_to_object()),
                         a => unreachable!("{a:?}"),
                     };
 
@@ -719,16 +722,14 @@ impl Thread {
 
                     let obj_class = match val {
                         Value::Object(Some(obj)) => heap().get_obj_class(obj),
-                        Value::Array(Some(arr)) => {
-                            let heap = heap();
-                            let elem_ty = heap.arr_ty(arr);
-                            method_area().resolve_arr_class(elem_ty)
-                        }
+                        Value::Array(Some(arr)) => heap().get_obj_class(arr.cast_to_object()),
                         a => unreachable!("{a:?}"),
                     };
 
-                    let class_id = self.class_id();
-                    let ref_class = Class::class_reference(class_id, cp_idx);
+                    let ref_class = self
+                        .constant_pool
+                        .class(cp_idx)
+                        .unwrap_or_else(|err| err.throw());
 
                     let instance_of = Class::instance_of(obj_class, ref_class);
                     self.operand_stack.push(Value::Int(instance_of as i32))
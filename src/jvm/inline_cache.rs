@@ -0,0 +1,61 @@
+//! Inline caches for invokevirtual and invokeinterface call sites
+
+use crate::class_loader::{ClassId, MethodId};
+use std::collections::HashSet;
+
+/// The number of receiver classes a polymorphic cache holds before going megamorphic
+const MAX_POLYMORPHIC_ENTRIES: usize = 4;
+
+/// Caches the result of method selection at a single call site, keyed on the receiver class.
+#[derive(Debug, Default)]
+pub enum InlineCache {
+    #[default]
+    Empty,
+    Monomorphic(ClassId, MethodId),
+    Polymorphic(Vec<(ClassId, MethodId)>),
+    /// Too many receiver classes have been seen, so the call site always does a full lookup
+    Megamorphic,
+}
+
+impl InlineCache {
+    pub fn lookup(&self, receiver: ClassId) -> Option<MethodId> {
+        match self {
+            InlineCache::Monomorphic(class, method) if *class == receiver => Some(*method),
+            InlineCache::Polymorphic(entries) => entries
+                .iter()
+                .find(|(class, _)| *class == receiver)
+                .map(|&(_, method)| method),
+            _ => None,
+        }
+    }
+
+    /// Whether any of the cached receiver classes are in `classes`
+    pub fn mentions_any(&self, classes: &HashSet<ClassId>) -> bool {
+        match self {
+            InlineCache::Monomorphic(class, _) => classes.contains(class),
+            InlineCache::Polymorphic(entries) => {
+                entries.iter().any(|(class, _)| classes.contains(class))
+            }
+            _ => false,
+        }
+    }
+
+    /// Record the method selected for a receiver class that missed the cache
+    pub fn update(&mut self, receiver: ClassId, method: MethodId) {
+        *self = match std::mem::take(self) {
+            InlineCache::Empty => InlineCache::Monomorphic(receiver, method),
+            InlineCache::Monomorphic(class, cached) => {
+                InlineCache::Polymorphic(vec![(class, cached), (receiver, method)])
+            }
+            InlineCache::Polymorphic(mut entries) => {
+                if entries.len() < MAX_POLYMORPHIC_ENTRIES {
+                    entries.push((receiver, method));
+                    InlineCache::Polymorphic(entries)
+                } else {
+                    InlineCache::Megamorphic
+                }
+            }
+            InlineCache::Megamorphic => InlineCache::Megamorphic,
+        };
+    }
+}
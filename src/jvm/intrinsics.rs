@@ -0,0 +1,515 @@
+//! Rust implementations of hot JDK methods
+//!
+//! An intrinsic replaces the bytecode of a method, even if it is not native. Like natives, they
+//! pop their arguments off of the caller's operand stack and push the return value. Intrinsics are
+//! looked up once when the method is loaded and stored in [`Method::intrinsic`].
+//!
+//! [`Method::intrinsic`]: crate::class::Method::intrinsic
+
+use super::Thread;
+use crate::class_loader::{self, method_area, ClassLoader};
+use crate::error::{JavaError, JavaResult};
+use crate::heap::{heap, ArrayRef, Heap, ObjectRef};
+use crate::symbol::Symbol;
+use crate::value::Value;
+
+pub type Intrinsic = fn(&mut Thread);
+
+/// Find the intrinsic for a method, if there is one
+pub fn lookup(class: &str, method: &str, descriptor: &str) -> Option<Intrinsic> {
+    let intrinsic: Intrinsic = match (class, method, descriptor) {
+        ("java/lang/Object", "<init>", "()V") => object_init,
+
+        ("java/lang/Math", "abs", "(I)I") => {
+            |t| unary(t, Value::int, |x| Value::Int(x.wrapping_abs()))
+        }
+        ("java/lang/Math", "abs", "(J)J") => {
+            |t| unary(t, Value::long, |x| Value::Long(x.wrapping_abs()))
+        }
+        ("java/lang/Math", "abs", "(F)F") => |t| unary(t, Value::float, |x| Value::Float(x.abs())),
+        ("java/lang/Math", "abs", "(D)D") => {
+            |t| unary(t, Value::double, |x| Value::Double(x.abs()))
+        }
+        ("java/lang/Math", "max", "(II)I") => {
+            |t| binary(t, Value::int, |a, b| Value::Int(a.max(b)))
+        }
+        ("java/lang/Math", "max", "(JJ)J") => {
+            |t| binary(t, Value::long, |a, b| Value::Long(a.max(b)))
+        }
+        ("java/lang/Math", "max", "(FF)F") => |t| {
+            binary(t, Value::float, |a, b| {
+                Value::Float(java_max(a as f64, b as f64) as f32)
+            })
+        },
+        ("java/lang/Math", "max", "(DD)D") => {
+            |t| binary(t, Value::double, |a, b| Value::Double(java_max(a, b)))
+        }
+        ("java/lang/Math", "min", "(II)I") => {
+            |t| binary(t, Value::int, |a, b| Value::Int(a.min(b)))
+        }
+        ("java/lang/Math", "min", "(JJ)J") => {
+            |t| binary(t, Value::long, |a, b| Value::Long(a.min(b)))
+        }
+        ("java/lang/Math", "min", "(FF)F") => |t| {
+            binary(t, Value::float, |a, b| {
+                Value::Float(java_min(a as f64, b as f64) as f32)
+            })
+        },
+        ("java/lang/Math", "min", "(DD)D") => {
+            |t| binary(t, Value::double, |a, b| Value::Double(java_min(a, b)))
+        }
+        ("java/lang/Math", "sqrt", "(D)D") => {
+            |t| unary(t, Value::double, |x| Value::Double(x.sqrt()))
+        }
+        ("java/lang/Math", "sin", "(D)D") => {
+            |t| unary(t, Value::double, |x| Value::Double(x.sin()))
+        }
+        ("java/lang/Math", "cos", "(D)D") => {
+            |t| unary(t, Value::double, |x| Value::Double(x.cos()))
+        }
+        ("java/lang/Math", "tan", "(D)D") => {
+            |t| unary(t, Value::double, |x| Value::Double(x.tan()))
+        }
+        ("java/lang/Math", "exp", "(D)D") => {
+            |t| unary(t, Value::double, |x| Value::Double(x.exp()))
+        }
+        ("java/lang/Math", "log", "(D)D") => |t| unary(t, Value::double, |x| Value::Double(x.ln())),
+        ("java/lang/Math", "log10", "(D)D") => {
+            |t| unary(t, Value::double, |x| Value::Double(x.log10()))
+        }
+        ("java/lang/Math", "floor", "(D)D") => {
+            |t| unary(t, Value::double, |x| Value::Double(x.floor()))
+        }
+        ("java/lang/Math", "ceil", "(D)D") => {
+            |t| unary(t, Value::double, |x| Value::Double(x.ceil()))
+        }
+        ("java/lang/Math", "rint", "(D)D") => {
+            |t| unary(t, Value::double, |x| Value::Double(x.round_ties_even()))
+        }
+        ("java/lang/Math", "pow", "(DD)D") => {
+            |t| binary(t, Value::double, |a, b| Value::Double(java_pow(a, b)))
+        }
+
+        ("java/lang/Integer", "numberOfLeadingZeros", "(I)I") => {
+            |t| unary(t, Value::int, |x| Value::Int(x.leading_zeros() as i32))
+        }
+        ("java/lang/Integer", "numberOfTrailingZeros", "(I)I") => {
+            |t| unary(t, Value::int, |x| Value::Int(x.trailing_zeros() as i32))
+        }
+        ("java/lang/Integer", "bitCount", "(I)I") => {
+            |t| unary(t, Value::int, |x| Value::Int(x.count_ones() as i32))
+        }
+        ("java/lang/Long", "numberOfLeadingZeros", "(J)I") => {
+            |t| unary(t, Value::long, |x| Value::Int(x.leading_zeros() as i32))
+        }
+        ("java/lang/Long", "numberOfTrailingZeros", "(J)I") => {
+            |t| unary(t, Value::long, |x| Value::Int(x.trailing_zeros() as i32))
+        }
+        ("java/lang/Long", "bitCount", "(J)I") => {
+            |t| unary(t, Value::long, |x| Value::Int(x.count_ones() as i32))
+        }
+
+        ("java/lang/String", "equals", "(Ljava/lang/Object;)Z") => string_equals,
+        ("java/lang/String", "hashCode", "()I") => string_hash_code,
+        ("java/lang/StringLatin1", "indexOf", "([BII)I") => latin1_index_of_char,
+        ("java/lang/StringLatin1", "indexOf", "([BI[BII)I") => latin1_index_of,
+
+        // Only primitive arrays, object arrays need store checks
+        (
+            "java/util/Arrays",
+            "fill",
+            "([ZZ)V" | "([BB)V" | "([CC)V" | "([SS)V" | "([II)V" | "([JJ)V" | "([FF)V" | "([DD)V",
+        ) => arrays_fill,
+        (
+            "java/util/Arrays",
+            "fill",
+            "([ZIIZ)V" | "([BIIB)V" | "([CIIC)V" | "([SIIS)V" | "([IIII)V" | "([JIIJ)V"
+            | "([FIIF)V" | "([DIID)V",
+        ) => arrays_fill_range,
+        (
+            "java/util/Arrays",
+            "copyOf",
+            "([ZI)[Z"
+            | "([BI)[B"
+            | "([CI)[C"
+            | "([SI)[S"
+            | "([II)[I"
+            | "([JI)[J"
+            | "([FI)[F"
+            | "([DI)[D"
+            | "([Ljava/lang/Object;I)[Ljava/lang/Object;",
+        ) => arrays_copy_of,
+
+        _ => return None,
+    };
+    Some(intrinsic)
+}
+
+fn unary<T>(thread: &mut Thread, unwrap: fn(Value) -> T, op: impl FnOnce(T) -> Value) {
+    let x = unwrap(thread.pop());
+    thread.operand_stack.push(op(x));
+}
+
+fn binary<T>(thread: &mut Thread, unwrap: fn(Value) -> T, op: impl FnOnce(T, T) -> Value) {
+    let b = unwrap(thread.pop());
+    let a = unwrap(thread.pop());
+    thread.operand_stack.push(op(a, b));
+}
+
+/// `Math.max`, which returns NaN if either value is NaN and considers -0.0 to be less than 0.0.
+/// Also used for floats since converting them to doubles and back is lossless.
+fn java_max(a: f64, b: f64) -> f64 {
+    if a.is_nan() || b.is_nan() {
+        f64::NAN
+    } else if a == b {
+        // Only differs for signed zeroes
+        if a.is_sign_negative() {
+            b
+        } else {
+            a
+        }
+    } else if a > b {
+        a
+    } else {
+        b
+    }
+}
+
+/// `Math.min`, see [`java_max`]
+fn java_min(a: f64, b: f64) -> f64 {
+    if a.is_nan() || b.is_nan() {
+        f64::NAN
+    } else if a == b {
+        if a.is_sign_negative() {
+            a
+        } else {
+            b
+        }
+    } else if a < b {
+        a
+    } else {
+        b
+    }
+}
+
+/// `Math.pow`, which differs from C's `pow` for NaN exponents and for a base of ±1 with an
+/// infinite exponent
+fn java_pow(a: f64, b: f64) -> f64 {
+    if b.is_nan() || (a.abs() == 1.0 && b.is_infinite()) {
+        f64::NAN
+    } else {
+        a.powf(b)
+    }
+}
+
+fn object_init(thread: &mut Thread) {
+    thread.pop();
+}
+
+/// Returns the `value` and `coder` fields of a string
+fn string_parts(str_obj: ObjectRef) -> (ArrayRef, i8) {
+    let str_class = heap().get_obj_class(str_obj);
+    let ma = method_area();
+    let value_field = ma
+        .resolve_field(str_class, Symbol::intern("value"), Symbol::intern("[B"))
+        .unwrap();
+    let coder_field = ma
+        .resolve_field(str_class, Symbol::intern("coder"), Symbol::intern("B"))
+        .unwrap();
+    drop(ma);
+
+    let heap = heap();
+    let value = heap.load_field(str_obj, value_field).array().unwrap();
+    let coder = heap.load_field(str_obj, coder_field).byte();
+    (value, coder)
+}
+
+fn null_pointer() -> ! {
+    JavaError::new("java/lang/NullPointerException", "").throw()
+}
+
+fn string_equals(thread: &mut Thread) {
+    let other = thread.pop().object();
+    let Some(this) = thread.pop().object() else {
+        null_pointer();
+    };
+
+    let equal = match other {
+        Some(other) if other == this => true,
+        Some(other) => {
+            let str_class = class_loader::resolve_class(
+                ClassLoader::Bootstrap,
+                Symbol::intern("java/lang/String"),
+            )
+            .unwrap();
+            // String is final, so there is no need for a subtype check
+            if heap().get_obj_class(other) == str_class {
+                let (this_value, this_coder) = string_parts(this);
+                let (other_value, other_coder) = string_parts(other);
+                let mut heap = heap();
+                let this_bytes = heap.array_contents::<i8>(this_value).to_vec();
+                let other_bytes = heap.array_contents::<i8>(other_value);
+                string_contents_equal((&this_bytes, this_coder), (other_bytes, other_coder))
+            } else {
+                false
+            }
+        }
+        None => false,
+    };
+    thread.operand_stack.push(Value::Int(equal as i32));
+}
+
+/// Strings with different coders are never equal, since Latin-1 is used whenever possible
+fn string_contents_equal(this: (&[i8], i8), other: (&[i8], i8)) -> bool {
+    this.1 == other.1 && this.0 == other.0
+}
+
+fn string_hash_code(thread: &mut Thread) {
+    let Some(this) = thread.pop().object() else {
+        null_pointer();
+    };
+    let (value, coder) = string_parts(this);
+    let hash = string_hash(heap().array_contents::<i8>(value), coder);
+    thread.operand_stack.push(Value::Int(hash));
+}
+
+/// `s[0]*31^(n-1) + s[1]*31^(n-2) + ... + s[n-1]` over the chars of the string, where UTF-16
+/// strings store each char in native byte order
+fn string_hash(bytes: &[i8], coder: i8) -> i32 {
+    if coder == 0 {
+        bytes.iter().fold(0i32, |h, &b| {
+            h.wrapping_mul(31).wrapping_add(b as u8 as i32)
+        })
+    } else {
+        bytes.chunks_exact(2).fold(0i32, |h, c| {
+            let c = u16::from_ne_bytes([c[0] as u8, c[1] as u8]);
+            h.wrapping_mul(31).wrapping_add(c as i32)
+        })
+    }
+}
+
+/// `StringLatin1.indexOf(byte[] value, int ch, int fromIndex)`
+fn latin1_index_of_char(thread: &mut Thread) {
+    let from_idx = thread.pop().int();
+    let ch = thread.pop().int();
+    let Some(value) = thread.pop().array() else {
+        null_pointer();
+    };
+    let idx = index_of_char(heap().array_contents::<i8>(value), ch, from_idx);
+    thread.operand_stack.push(Value::Int(idx));
+}
+
+fn index_of_char(bytes: &[i8], ch: i32, from_idx: i32) -> i32 {
+    let from_idx = from_idx.max(0) as usize;
+    // Chars above 0xff can't be in a Latin-1 string
+    if ch as u32 >> 8 != 0 || from_idx >= bytes.len() {
+        return -1;
+    }
+    bytes[from_idx..]
+        .iter()
+        .position(|&b| b as u8 as i32 == ch)
+        .map_or(-1, |idx| (from_idx + idx) as i32)
+}
+
+/// `StringLatin1.indexOf(byte[] value, int valueCount, byte[] str, int strCount, int fromIndex)`
+fn latin1_index_of(thread: &mut Thread) {
+    let from_idx = thread.pop().int();
+    let str_count = thread.pop().int();
+    let Some(str) = thread.pop().array() else {
+        null_pointer();
+    };
+    let value_count = thread.pop().int();
+    let Some(value) = thread.pop().array() else {
+        null_pointer();
+    };
+
+    let mut heap = heap();
+    let needle = heap.array_contents::<i8>(str).to_vec();
+    let haystack = heap.array_contents::<i8>(value);
+    let idx = index_of(haystack, value_count, &needle, str_count, from_idx)
+        .unwrap_or_else(|err| err.throw());
+    thread.operand_stack.push(Value::Int(idx));
+}
+
+fn index_of(
+    value: &[i8],
+    value_count: i32,
+    str: &[i8],
+    str_count: i32,
+    from_idx: i32,
+) -> JavaResult<i32> {
+    let haystack = prefix(value, value_count)?;
+    let needle = prefix(str, str_count)?;
+    let from_idx = from_idx.max(0) as usize;
+    if from_idx + needle.len() > haystack.len() {
+        return Ok(-1);
+    }
+    if needle.is_empty() {
+        return Ok(from_idx as i32);
+    }
+    Ok(haystack[from_idx..]
+        .windows(needle.len())
+        .position(|window| window == needle)
+        .map_or(-1, |idx| (from_idx + idx) as i32))
+}
+
+/// The first `count` elements of an array, as Java code indexing up to `count` would see them
+fn prefix(arr: &[i8], count: i32) -> JavaResult<&[i8]> {
+    usize::try_from(count)
+        .ok()
+        .and_then(|count| arr.get(..count))
+        .ok_or_else(|| {
+            JavaError::new(
+                "java/lang/ArrayIndexOutOfBoundsException",
+                format!("Index {} out of bounds for length {}", count, arr.len()),
+            )
+        })
+}
+
+fn fill(arr: ArrayRef, from: usize, to: usize, val: Value) {
+    let heap = heap();
+    let val = heap.arr_elem_kind(arr).store_ty(val);
+    for idx in from..to {
+        heap.store_arr_elem(arr, idx, val);
+    }
+}
+
+fn arrays_fill(thread: &mut Thread) {
+    let val = thread.pop();
+    let Some(arr) = thread.pop().array() else {
+        null_pointer();
+    };
+    let len = heap().arr_len(arr);
+    fill(arr, 0, len, val);
+}
+
+fn arrays_fill_range(thread: &mut Thread) {
+    let val = thread.pop();
+    let to = thread.pop().int();
+    let from = thread.pop().int();
+    let Some(arr) = thread.pop().array() else {
+        null_pointer();
+    };
+    let len = heap().arr_len(arr);
+    check_range(len, from, to).unwrap_or_else(|err| err.throw());
+    fill(arr, from as usize, to as usize, val);
+}
+
+/// `Arrays.rangeCheck`
+fn check_range(len: usize, from: i32, to: i32) -> JavaResult<()> {
+    if from > to {
+        return Err(JavaError::new(
+            "java/lang/IllegalArgumentException",
+            format!("fromIndex({}) > toIndex({})", from, to),
+        ));
+    }
+    if from < 0 {
+        return Err(JavaError::new(
+            "java/lang/ArrayIndexOutOfBoundsException",
+            format!("Array index out of range: {}", from),
+        ));
+    }
+    if to as usize > len {
+        return Err(JavaError::new(
+            "java/lang/ArrayIndexOutOfBoundsException",
+            format!("Array index out of range: {}", to),
+        ));
+    }
+    Ok(())
+}
+
+fn arrays_copy_of(thread: &mut Thread) {
+    let new_len = thread.pop().int();
+    let Some(arr) = thread.pop().array() else {
+        null_pointer();
+    };
+    let new_len = copy_len(new_len).unwrap_or_else(|err| err.throw());
+
+    // The copy has the same runtime type as the original, even for object arrays
+    let mut heap = heap();
+    let arr_class = heap.get_obj_class(arr.cast_to_object());
+    let copy = Heap::new_array_of_class(arr_class, heap.arr_elem_kind(arr), new_len);
+    let len = heap.arr_len(arr).min(new_len);
+    heap.array_copy(arr, 0, copy, 0, len);
+    thread.operand_stack.push(Value::Array(Some(copy)));
+}
+
+/// The length of the array created by `Arrays.copyOf`
+fn copy_len(new_len: i32) -> JavaResult<usize> {
+    usize::try_from(new_len)
+        .map_err(|_| JavaError::new("java/lang/NegativeArraySizeException", new_len.to_string()))
+}
+
+#[test]
+fn java_math_test() {
+    assert!(java_max(f64::NAN, 1.0).is_nan());
+    assert!(java_min(1.0, f64::NAN).is_nan());
+    assert!(java_max(-0.0, 0.0).is_sign_positive());
+    assert!(java_max(0.0, -0.0).is_sign_positive());
+    assert!(java_min(-0.0, 0.0).is_sign_negative());
+    assert!(java_min(0.0, -0.0).is_sign_negative());
+    assert_eq!(java_max(1.0, 2.0), 2.0);
+    assert_eq!(java_min(1.0, 2.0), 1.0);
+    assert!(java_pow(1.0, f64::NAN).is_nan());
+    assert!(java_pow(-1.0, f64::INFINITY).is_nan());
+    assert_eq!(java_pow(f64::NAN, 0.0), 1.0);
+    assert_eq!(java_pow(2.0, 10.0), 1024.0);
+}
+
+#[test]
+fn string_test() {
+    let hello: Vec<i8> = b"hello".iter().map(|&b| b as i8).collect();
+    // "hello".hashCode() and "".hashCode()
+    assert_eq!(string_hash(&hello, 0), 99162322);
+    assert_eq!(string_hash(&[], 0), 0);
+    // Bytes above 0x7f are unsigned chars, "\u00e9".hashCode() is 233
+    assert_eq!(string_hash(&[0xe9u8 as i8], 0), 233);
+    let utf16: Vec<i8> = "h\u{e9}"
+        .encode_utf16()
+        .flat_map(u16::to_ne_bytes)
+        .map(|b| b as i8)
+        .collect();
+    assert_eq!(string_hash(&utf16, 1), 104 * 31 + 233);
+
+    assert!(string_contents_equal((&hello, 0), (&hello.clone(), 0)));
+    assert!(!string_contents_equal((&hello, 0), (&hello[..4], 0)));
+    assert!(!string_contents_equal((&[], 0), (&[], 1)));
+
+    assert_eq!(index_of_char(&hello, 'l' as i32, 0), 2);
+    assert_eq!(index_of_char(&hello, 'l' as i32, 3), 3);
+    assert_eq!(index_of_char(&hello, 'l' as i32, -5), 2);
+    assert_eq!(index_of_char(&hello, 'l' as i32, 10), -1);
+    assert_eq!(index_of_char(&hello, 0x16c, 0), -1);
+    assert_eq!(index_of_char(&hello, -1, 0), -1);
+
+    let ll: Vec<i8> = b"ll".iter().map(|&b| b as i8).collect();
+    assert_eq!(index_of(&hello, 5, &ll, 2, 0), Ok(2));
+    assert_eq!(index_of(&hello, 5, &ll, 2, 3), Ok(-1));
+    // Only the first valueCount bytes are searched
+    assert_eq!(index_of(&hello, 3, &ll, 2, 0), Ok(-1));
+    assert_eq!(index_of(&hello, 5, &ll, 0, 4), Ok(4));
+    assert_eq!(
+        index_of(&hello, 6, &ll, 2, 0).unwrap_err().class_name,
+        "java/lang/ArrayIndexOutOfBoundsException"
+    );
+}
+
+#[test]
+fn arrays_test() {
+    assert_eq!(check_range(10, 0, 10), Ok(()));
+    assert_eq!(check_range(10, 5, 5), Ok(()));
+    let err = check_range(10, 6, 5).unwrap_err();
+    assert_eq!(err.class_name, "java/lang/IllegalArgumentException");
+    assert_eq!(err.message, "fromIndex(6) > toIndex(5)");
+    let err = check_range(10, -1, 5).unwrap_err();
+    assert_eq!(err.class_name, "java/lang/ArrayIndexOutOfBoundsException");
+    assert_eq!(err.message, "Array index out of range: -1");
+    let err = check_range(10, 0, 11).unwrap_err();
+    assert_eq!(err.message, "Array index out of range: 11");
+
+    assert_eq!(copy_len(0), Ok(0));
+    assert_eq!(copy_len(7), Ok(7));
+    let err = copy_len(-3).unwrap_err();
+    assert_eq!(err.class_name, "java/lang/NegativeArraySizeException");
+    assert_eq!(err.message, "-3");
+}
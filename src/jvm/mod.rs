@@ -1,14 +1,40 @@
 mod exec;
+pub mod inline_cache;
+pub mod intrinsics;
 mod natives;
+pub mod profile;
 
+use crate::class::{Class, InitState};
 use crate::class_file::attributes::CodeAttribute;
 use crate::class_file::descriptors::{BaseType, FieldType};
 use crate::class_file::methods;
-use crate::class_loader::{method_area, ClassId, MethodId};
-use crate::heap::{heap, ObjectRef};
+use crate::class_loader::{method_area, ClassId, FieldId, MethodArea, MethodId};
+use crate::error::{JavaError, JavaResult};
+use crate::heap::{heap, ArrayRef, ObjectRef};
+use crate::runtime_constant_pool::{Entry, RuntimeConstantPool};
+use crate::symbol::Symbol;
 use crate::value::Value;
-use std::mem;
-use std::sync::Arc;
+use profile::profile;
+use std::sync::{Arc, Condvar, PoisonError};
+use std::thread;
+use std::{iter, mem};
+
+/// Signalled whenever a class finishes initialization, successfully or not
+static CLASS_INIT: Condvar = Condvar::new();
+
+/// Marks a class as erroneous if its initialization unwinds with something other than a
+/// [`JavaError`], so that threads waiting for it don't block forever
+struct InitGuard(ClassId);
+
+impl Drop for InitGuard {
+    fn drop(&mut self) {
+        if thread::panicking() {
+            let mut ma = method_area();
+            ma.classes[self.0].init_state = InitState::Erroneous;
+            CLASS_INIT.notify_all();
+        }
+    }
+}
 
 struct StackFrame {
     method: MethodId,
@@ -20,6 +46,8 @@ struct StackFrame {
 pub struct Thread {
     method: MethodId,
     code: Arc<CodeAttribute>,
+    /// The constant pool of the current method's class
+    constant_pool: Arc<RuntimeConstantPool>,
     pc: usize,
     operand_stack: Vec<Value>,
     locals: Vec<Option<Value>>,
@@ -28,12 +56,15 @@ pub struct Thread {
 
 impl Thread {
     pub fn new(entry_method: MethodId) -> Thread {
-        let method = &method_area().methods[entry_method];
+        let ma = method_area();
+        let method = &ma.methods[entry_method];
         let code = method.code.clone().unwrap();
+        let constant_pool = ma.classes[method.defining_class].constant_pool.clone();
         let max_locals = code.max_locals as usize;
         Thread {
             method: entry_method,
             code,
+            constant_pool,
             pc: 0,
             operand_stack: Vec::new(),
             stack_frames: Vec::new(),
@@ -41,6 +72,29 @@ impl Thread {
         }
     }
 
+    /// Run a method to completion on a new stack, for when the VM calls into Java code. The class
+    /// of a static method is initialized first.
+    pub fn invoke(method: MethodId, args: &[Value]) -> Option<Value> {
+        let mut thread = Thread::new(method);
+        let ma = method_area();
+        let static_class = ma.methods[method]
+            .is_static()
+            .then_some(ma.methods[method].defining_class);
+        drop(ma);
+        if let Some(class) = static_class {
+            thread.ensure_initialized(class);
+        }
+        let mut local = 0;
+        for &arg in args {
+            thread.locals[local] = Some(arg);
+            local += match arg {
+                Value::Long(_) | Value::Double(_) => 2,
+                _ => 1,
+            };
+        }
+        thread.run()
+    }
+
     fn read_ins(&mut self) -> u8 {
         let data = self.code.code[self.pc];
         self.pc += 1;
@@ -68,24 +122,69 @@ impl Thread {
         method_area().methods[self.method].defining_class
     }
 
+    fn pop_frame(&mut self) {
+        let stack_frame = self.stack_frames.pop().unwrap();
+        self.method = stack_frame.method;
+        let ma = method_area();
+        let method = &ma.methods[self.method];
+        self.code = method.code.clone().unwrap();
+        self.constant_pool = ma.classes[method.defining_class].constant_pool.clone();
+        drop(ma);
+        self.pc = stack_frame.return_pc;
+        self.operand_stack = stack_frame.operand_stack;
+        self.locals = stack_frame.locals;
+    }
+
+    /// The references held by the operand stacks and local variables of every frame
+    pub fn roots(&self) -> Vec<ObjectRef> {
+        let frames = self
+            .stack_frames
+            .iter()
+            .map(|frame| (&frame.operand_stack, &frame.locals));
+        iter::once((&self.operand_stack, &self.locals))
+            .chain(frames)
+            .flat_map(|(stack, locals)| stack.iter().chain(locals.iter().flatten()))
+            .filter_map(|val| match *val {
+                Value::Object(obj) => obj,
+                Value::Array(arr) => arr.map(ArrayRef::cast_to_object),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Discard the frames of methods that were abruptly completed by a thrown error
+    fn unwind_to(&mut self, depth: usize) {
+        while self.stack_frames.len() > depth {
+            self.pop_frame();
+        }
+    }
+
     pub fn call_method(&mut self, method_id: MethodId) {
+        if profile::is_enabled() {
+            profile().record_invocation(method_id);
+        }
         let ma = method_area();
         let method = &ma.methods[method_id];
+        if let Some(intrinsic) = method.intrinsic {
+            drop(ma);
+            intrinsic(self);
+            return;
+        }
         let is_static = method.access_flags & methods::acc::STATIC != 0;
         println!(
             "Calling method: {}.{}",
             ma.classes[method.defining_class].name, method.name
         );
         if method.access_flags & methods::acc::NATIVE != 0 {
-            let class_name = ma.classes[method.defining_class].name.clone();
-            let method_name = method.name.clone();
+            let class_name = ma.classes[method.defining_class].name.to_string();
+            let method_name = method.name.to_string();
             drop(ma);
             natives::run_native(self, class_name, method_name);
             return;
         }
 
         let mut locals = vec![None; method.code.as_ref().unwrap().max_locals as usize];
-        let mut num_params = method.descriptor.0.len();
+        let mut num_params = method.parsed_descriptor.0.len();
         if !is_static {
             // objectref
             num_params += 1;
@@ -112,17 +211,13 @@ impl Thread {
         self.stack_frames.push(stack_frame);
         self.method = method_id;
         self.code = method.code.clone().unwrap();
+        self.constant_pool = ma.classes[method.defining_class].constant_pool.clone();
         self.pc = 0;
 
         drop(ma);
         let res = self.run();
 
-        let stack_frame = self.stack_frames.pop().unwrap();
-        self.method = stack_frame.method;
-        self.code = method_area().methods[self.method].code.clone().unwrap();
-        self.pc = stack_frame.return_pc;
-        self.operand_stack = stack_frame.operand_stack;
-        self.locals = stack_frame.locals;
+        self.pop_frame();
         if let Some(res) = res {
             self.operand_stack.push(res);
         }
@@ -133,30 +228,138 @@ impl Thread {
         );
     }
 
+    /// Initialize a class if it hasn't been already, following JVMS 5.5
     pub fn ensure_initialized(&mut self, class_id: ClassId) {
+        self.initialize(class_id).unwrap_or_else(|err| err.throw());
+    }
+
+    fn initialize(&mut self, class_id: ClassId) -> JavaResult<()> {
+        let current_thread = thread::current().id();
         let mut ma = method_area();
-        let class = &mut ma.classes[class_id];
-        if !class.initialized {
-            println!("Initializing class: {}", class.name);
-            class.initialized = true;
-            let class = &ma.classes[class_id];
-            if let Some(method) = class
-                .methods
-                .iter()
-                .cloned()
-                .find(|&mid| ma.methods[mid].name == "<clinit>")
-            {
-                drop(ma);
-                self.call_method(method);
+        loop {
+            match ma.classes[class_id].init_state {
+                InitState::Initialized => return Ok(()),
+                // A recursive request from the thread that is already initializing this class
+                InitState::BeingInitialized(thread) if thread == current_thread => return Ok(()),
+                InitState::BeingInitialized(_) => {
+                    ma = CLASS_INIT.wait(ma).unwrap_or_else(PoisonError::into_inner)
+                }
+                InitState::Erroneous => {
+                    return Err(JavaError::new(
+                        "java/lang/NoClassDefFoundError",
+                        format!("Could not initialize class {}", ma.classes[class_id].name),
+                    ))
+                }
+                InitState::Loaded => ma.link_class(class_id)?,
+                InitState::Linked => break,
             }
         }
+        ma.classes[class_id].init_state = InitState::BeingInitialized(current_thread);
+        drop(ma);
+        let _guard = InitGuard(class_id);
+        Self::initialize_constants(class_id);
+
+        let ma = method_area();
+        let class = &ma.classes[class_id];
+        println!("Initializing class: {}", class.name);
+        let mut supers = Vec::new();
+        if !class.is_interface() {
+            supers.extend(class.super_class);
+            for &interface in &class.interfaces {
+                Self::interfaces_to_initialize(&ma, interface, &mut supers);
+            }
+        }
+        let clinit = class
+            .methods
+            .iter()
+            .cloned()
+            .find(|&mid| ma.methods[mid].name == Symbol::CLINIT);
+        drop(ma);
+
+        let mut res = supers
+            .into_iter()
+            .try_for_each(|super_id| self.initialize(super_id));
+        if let (Ok(()), Some(clinit)) = (&res, clinit) {
+            let depth = self.stack_frames.len();
+            res = JavaError::catch(|| self.call_method(clinit)).map_err(|err| {
+                self.unwind_to(depth);
+                if err.is_error() {
+                    err
+                } else {
+                    JavaError::new("java/lang/ExceptionInInitializerError", err.to_string())
+                }
+            });
+        }
+
+        let mut ma = method_area();
+        ma.classes[class_id].init_state = match res {
+            Ok(()) => InitState::Initialized,
+            Err(_) => InitState::Erroneous,
+        };
+        CLASS_INIT.notify_all();
+        res
+    }
+
+    /// Assign static fields their ConstantValue attribute
+    fn initialize_constants(class_id: ClassId) {
+        let ma = method_area();
+        let class = &ma.classes[class_id];
+        let constants: Vec<(FieldId, u16)> = class
+            .fields
+            .iter()
+            .filter_map(|&id| {
+                let field = &ma.fields[id];
+                field
+                    .constant_value
+                    .filter(|_| field.is_static())
+                    .map(|idx| (id, idx))
+            })
+            .collect();
+        let constant_pool = class.constant_pool.clone();
+        drop(ma);
+
+        for (field, idx) in constants {
+            let val = match constant_pool.entry(idx) {
+                Entry::Integer(val) => Value::Int(*val),
+                Entry::Float(val) => Value::Float(*val),
+                Entry::Long(val) => Value::Long(*val),
+                Entry::Double(val) => Value::Double(*val),
+                Entry::String { .. } => Value::Object(Some(constant_pool.string(idx))),
+                _ => unreachable!("checked when loading"),
+            };
+            method_area().fields[field].store_static(val);
+        }
+    }
+
+    /// Superinterfaces that declare default methods are initialized before a class that
+    /// implements them, each after its own superinterfaces
+    fn interfaces_to_initialize(ma: &MethodArea, interface: ClassId, order: &mut Vec<ClassId>) {
+        let class = &ma.classes[interface];
+        for &super_interface in &class.interfaces {
+            Self::interfaces_to_initialize(ma, super_interface, order);
+        }
+        let has_defaults = class.methods.iter().any(|&mid| {
+            let method = &ma.methods[mid];
+            !method.is_abstract() && !method.is_static()
+        });
+        if has_defaults && !order.contains(&interface) {
+            order.push(interface);
+        }
     }
 
     fn br_if(&mut self, cur_pc: usize, cond: bool) {
         let target = cur_pc as isize + self.read_u16() as i16 as isize;
         if cond {
-            self.pc = target as usize;
+            self.jump(cur_pc, target as usize);
+        }
+    }
+
+    /// Transfer control to `target` from the branch instruction at `cur_pc`
+    fn jump(&mut self, cur_pc: usize, target: usize) {
+        if target <= cur_pc && profile::is_enabled() {
+            profile().record_back_edge(self.method, cur_pc);
         }
+        self.pc = target;
     }
 
     fn pop(&mut self) -> Value {
@@ -179,11 +382,87 @@ impl Thread {
         println!("{:?}", ValueDebugger(val));
     }
 
-    /// For method selection in invokeinterface and invokevirtual instructions
-    fn select_method(&mut self, method_id: MethodId) -> MethodId {
+    /// Resolve the method referenced by an invoke instruction, which must be static exactly when
+    /// the instruction is invokestatic
+    fn invoked_method(&self, idx: u16, is_static: bool) -> MethodId {
+        let method_id = self
+            .constant_pool
+            .method(idx)
+            .unwrap_or_else(|err| err.throw());
+        let ma = method_area();
+        let method = &ma.methods[method_id];
+        if method.is_static() != is_static {
+            let err = JavaError::new(
+                "java/lang/IncompatibleClassChangeError",
+                format!(
+                    "Expected {} method {}.{}{}",
+                    if is_static { "static" } else { "non-static" },
+                    ma.classes[method.defining_class].name,
+                    method.name,
+                    method.descriptor
+                ),
+            );
+            drop(ma);
+            err.throw();
+        }
+        method_id
+    }
+
+    /// Resolve the field referenced by a field access instruction, which must be static exactly
+    /// when the instruction is getstatic or putstatic
+    fn accessed_field(&self, idx: u16, is_static: bool) -> FieldId {
+        let field_id = self
+            .constant_pool
+            .field(idx)
+            .unwrap_or_else(|err| err.throw());
+        let ma = method_area();
+        let field = &ma.fields[field_id];
+        if field.is_static() != is_static {
+            let err = JavaError::new(
+                "java/lang/IncompatibleClassChangeError",
+                format!(
+                    "Expected {} field {}.{}",
+                    if is_static { "static" } else { "non-static" },
+                    ma.classes[field.defining_class].name,
+                    field.name
+                ),
+            );
+            drop(ma);
+            err.throw();
+        }
+        field_id
+    }
+
+    /// Method selection for invokespecial. Methods of a superclass are looked up starting from the
+    /// direct superclass of the current class, as if `ACC_SUPER` was set, which the JVM assumes
+    /// for every class since Java SE 8.
+    fn select_special_method(&self, idx: u16, resolved: MethodId) -> MethodId {
+        let referenced = self
+            .constant_pool
+            .member_class(idx)
+            .unwrap_or_else(|err| err.throw());
+        let current = self.class_id();
         let ma = method_area();
+        let current_super = ma.classes[current].super_class;
+        let is_superclass = iter::successors(current_super, |&class| ma.classes[class].super_class)
+            .any(|class| class == referenced);
+        let class = if ma.methods[resolved].name != Symbol::INIT
+            && !ma.classes[referenced].is_interface()
+            && is_superclass
+        {
+            current_super.unwrap()
+        } else {
+            referenced
+        };
+        Class::select_special_method(&ma, class, resolved).unwrap_or_else(|err| err.throw())
+    }
+
+    /// For method selection in invokeinterface and invokevirtual instructions. `call_pc` is the
+    /// location of the invoke instruction, used to find the call site's inline cache.
+    fn select_method(&mut self, call_pc: usize, method_id: MethodId) -> MethodId {
+        let mut ma = method_area();
         let method = &ma.methods[method_id];
-        let num_params = method.descriptor.0.len();
+        let num_params = method.parsed_descriptor.0.len();
 
         let stack_obj_idx = self.operand_stack.len() - num_params - 1;
         let obj = match self.operand_stack[stack_obj_idx].object() {
@@ -191,23 +470,23 @@ impl Thread {
             None => panic!("NullPointerException"),
         };
         let obj_class = heap().get_obj_class(obj);
+        if profile::is_enabled() {
+            profile().record_receiver(self.method, call_pc, obj_class);
+        }
 
-        let mut cur_class = obj_class;
-        loop {
-            let c = &ma.classes[cur_class];
-            let m = c.methods.iter().find(|&m| {
-                let m = &ma.methods[*m];
-                m.name == method.name && m.descriptor == method.descriptor
-            });
-            if let Some(&m) = m {
-                break m;
-            }
-            match c.super_class {
-                Some(s) => cur_class = s,
-                // Could not find an overriding method
-                None => break method_id,
-            }
+        let inline_cache = ma.methods[self.method].inline_caches.get(&call_pc);
+        if let Some(selected) = inline_cache.and_then(|ic| ic.lookup(obj_class)) {
+            return selected;
         }
+
+        let selected =
+            Class::select_method(&ma, obj_class, method_id).unwrap_or_else(|err| err.throw());
+        ma.methods[self.method]
+            .inline_caches
+            .entry(call_pc)
+            .or_default()
+            .update(obj_class, selected);
+        selected
     }
 
     fn print_stacktrace(&self) {
@@ -229,7 +508,10 @@ impl std::fmt::Debug for ValueDebugger {
         match self.0 {
             Value::Object(Some(obj)) => {
                 let class_id = heap().get_obj_class(obj);
-                let name = method_area().classes[class_id].name.replace('/', ".");
+                let name = method_area().classes[class_id]
+                    .name
+                    .as_str()
+                    .replace('/', ".");
                 let mut dbg = f.debug_struct(&name);
 
                 let mut cur_class = class_id;
@@ -239,7 +521,7 @@ impl std::fmt::Debug for ValueDebugger {
                         if method_area().fields[field].is_static() {
                             continue;
                         }
-                        let name = method_area().fields[field].name.clone();
+                        let name = method_area().fields[field].name.to_string();
                         let val = heap().load_field(obj, field).clone();
                         if matches!(val, Value::Object(Some(_))) {
                             // this is to avoid infinite recursion
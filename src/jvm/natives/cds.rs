@@ -1,6 +1,7 @@
 //! Class Data Sharing
 //! https://docs.oracle.com/en/java/javase/18/vm/class-data-sharing.html
 
+use crate::cds;
 use crate::jvm::Thread;
 use crate::value::Value;
 
@@ -11,18 +12,24 @@ pub fn is_dumping_class_list(thread: &mut Thread) {
 
 pub fn is_dumping_archive(thread: &mut Thread) {
     // boolean type
-    thread.operand_stack.push(Value::Int(0));
+    thread
+        .operand_stack
+        .push(Value::Int(cds::is_dumping() as i32));
 }
 
 pub fn is_sharing_enabled(thread: &mut Thread) {
     // boolean type
-    thread.operand_stack.push(Value::Int(0));
+    thread
+        .operand_stack
+        .push(Value::Int(cds::is_sharing_enabled() as i32));
 }
 
 pub fn get_random_seed_for_dumping(thread: &mut Thread) {
     thread.operand_stack.push(Value::Long(0x694201337));
 }
 
-pub fn intialize_from_archive(_thread: &mut Thread) {
-    // TODO
+pub fn intialize_from_archive(thread: &mut Thread) {
+    // Only class metadata is archived, so there are never archived static field values to
+    // initialize the class with
+    let _class = thread.pop();
 }
@@ -0,0 +1,67 @@
+use crate::class::Class;
+use crate::class_loader::{self, method_area, ClassLoader};
+use crate::error::JavaError;
+use crate::heap::heap;
+use crate::jvm::Thread;
+use crate::symbol::Symbol;
+use crate::value::Value;
+
+pub fn define_class1(thread: &mut Thread) {
+    let _source = thread.pop();
+    let _protection_domain = thread.pop();
+    let len = thread.pop().int();
+    let off = thread.pop().int();
+    let Some(bytes) = thread.pop().array() else {
+        panic!("NullPointerException");
+    };
+    let name = thread.pop().object();
+    let loader = ClassLoader::from_obj(thread.pop().object());
+
+    let data: Vec<u8> = {
+        let mut heap = heap();
+        let bytes = heap.array_contents::<i8>(bytes);
+        if off < 0 || len < 0 || (off + len) as usize > bytes.len() {
+            panic!("ArrayIndexOutOfBoundsException");
+        }
+        bytes[off as usize..(off + len) as usize]
+            .iter()
+            .map(|&b| b as u8)
+            .collect()
+    };
+    let class = class_loader::define_class(loader, &data).unwrap_or_else(|err| err.throw());
+
+    if let Some(name) = name {
+        let name = heap().read_string(name).replace('.', "/");
+        let defined_name = method_area().classes[class].name;
+        if defined_name != name.as_str() {
+            let message = format!("{} (wrong name: {})", defined_name, name);
+            JavaError::new("java/lang/NoClassDefFoundError", message).throw();
+        }
+    }
+    thread
+        .operand_stack
+        .push(Value::Object(Some(Class::obj(class))));
+}
+
+pub fn find_loaded_class0(thread: &mut Thread) {
+    let Some(name) = thread.pop().object() else {
+        panic!("NullPointerException");
+    };
+    let loader = ClassLoader::from_obj(thread.pop().object());
+    let name = Symbol::intern(&heap().read_string(name).replace('.', "/"));
+    let class = method_area().class_map.get(&(loader, name)).copied();
+    thread
+        .operand_stack
+        .push(Value::Object(class.map(Class::obj)));
+}
+
+pub fn find_bootstrap_class(thread: &mut Thread) {
+    let Some(name) = thread.pop().object() else {
+        panic!("NullPointerException");
+    };
+    let name = Symbol::intern(&heap().read_string(name).replace('.', "/"));
+    let class = class_loader::resolve_class(ClassLoader::Bootstrap, name).ok();
+    thread
+        .operand_stack
+        .push(Value::Object(class.map(Class::obj)));
+}
@@ -1,9 +1,12 @@
 use crate::class::FieldBacking;
 use crate::class_loader::method_area;
-use crate::heap::{arr_layout, heap, Object, ObjectRef};
+use crate::error::JavaError;
+use crate::heap::{arr_layout, compress_ref, decompress_ref, heap, Object, ObjectRef, ValueKind};
 use crate::jvm::Thread;
+use crate::symbol::Symbol;
 use crate::value::Value;
-use std::sync::atomic::{fence, AtomicI32, AtomicI64, AtomicPtr, Ordering};
+use crate::CONFIG;
+use std::sync::atomic::{fence, AtomicI32, AtomicI64, AtomicPtr, AtomicU32, Ordering};
 
 fn arr_class_layout(thread: &mut Thread) -> (usize, usize) {
     let Some(arr_class_obj) = thread.pop().object() else {
@@ -16,7 +19,7 @@ fn arr_class_layout(thread: &mut Thread) -> (usize, usize) {
         .elem_ty
         .as_ref()
         .expect("class was not array class");
-    let (_, base, stride) = arr_layout(elem_ty, 128);
+    let (_, base, stride) = arr_layout(ValueKind::of(elem_ty), 128);
     (base, stride)
 }
 
@@ -41,7 +44,14 @@ pub fn object_field_offset(thread: &mut Thread) {
     let name = heap().read_string(name_str);
     let ma = method_area();
     let class_id = ma.class_objs[&class_obj];
-    let field_id = ma.resolve_field(class_id, &name);
+    // Only fields declared by the class itself are considered, like in HotSpot
+    let name = Symbol::intern(&name);
+    let field_id = ma.classes[class_id]
+        .fields
+        .iter()
+        .copied()
+        .find(|&field| ma.fields[field].name == name)
+        .unwrap_or_else(|| JavaError::new("java/lang/InternalError", name.to_string()).throw());
     let FieldBacking::Instance(offset) = ma.fields[field_id].backing else {
         panic!("tried to get offset of static field");
     };
@@ -125,15 +135,24 @@ pub fn compare_and_set_reference(thread: &mut Thread) {
         panic!("NullPointerException");
     };
 
-    let atomic = unsafe {
-        let ptr = obj_ref
-            .inner_ptr()
-            .cast::<*mut Object>()
-            .byte_offset(offset);
-        AtomicPtr::from_ptr(ptr)
+    let res = unsafe {
+        let ptr = obj_ref.inner_ptr().byte_offset(offset);
+        if CONFIG.compressed_refs {
+            AtomicU32::from_ptr(ptr.cast::<u32>())
+                .compare_exchange(
+                    compress_ref(expected),
+                    compress_ref(x),
+                    Ordering::SeqCst,
+                    Ordering::SeqCst,
+                )
+                .is_ok()
+        } else {
+            AtomicPtr::from_ptr(ptr.cast::<*mut Object>())
+                .compare_exchange(expected, x, Ordering::SeqCst, Ordering::SeqCst)
+                .is_ok()
+        }
     };
-    let res = atomic.compare_exchange(expected, x, Ordering::SeqCst, Ordering::SeqCst);
-    thread.operand_stack.push(Value::Int(res.is_ok() as i32));
+    thread.operand_stack.push(Value::Int(res as i32));
 }
 
 pub fn get_reference_volatile(thread: &mut Thread) {
@@ -143,11 +162,13 @@ pub fn get_reference_volatile(thread: &mut Thread) {
     };
 
     let val = unsafe {
-        let ptr = obj_ref
-            .inner_ptr()
-            .cast::<*mut Object>()
-            .byte_offset(offset);
-        ObjectRef::from_ptr(ptr.read_volatile())
+        let ptr = obj_ref.inner_ptr().byte_offset(offset);
+        let obj = if CONFIG.compressed_refs {
+            decompress_ref(ptr.cast::<u32>().read_volatile())
+        } else {
+            ptr.cast::<*mut Object>().read_volatile()
+        };
+        ObjectRef::from_ptr(obj)
     };
 
     thread.operand_stack.push(Value::Object(val))
@@ -162,10 +183,11 @@ pub fn put_reference_volatile(thread: &mut Thread) {
 
     unsafe {
         let val = object_ptr(x);
-        let ptr = obj_ref
-            .inner_ptr()
-            .cast::<*mut Object>()
-            .byte_offset(offset);
-        ptr.write_volatile(val);
+        let ptr = obj_ref.inner_ptr().byte_offset(offset);
+        if CONFIG.compressed_refs {
+            ptr.cast::<u32>().write_volatile(compress_ref(val));
+        } else {
+            ptr.cast::<*mut Object>().write_volatile(val);
+        }
     }
 }
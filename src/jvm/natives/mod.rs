@@ -1,5 +1,6 @@
 mod cds;
 mod class;
+mod class_loader;
 mod file_descriptor;
 mod finalizer;
 mod float;
@@ -19,6 +20,9 @@ pub fn run_native(thread: &mut Thread, class: String, method: String) {
     match (class.as_str(), method.as_str()) {
         ("java/lang/System", "registerNatives") => println!("stub: native System.registerNatives"),
         ("java/lang/Class", "registerNatives") => println!("stub: native Class.registerNatives"),
+        ("java/lang/ClassLoader", "registerNatives") => {
+            println!("stub: native ClassLoader.registerNatives")
+        }
         ("jdk/internal/misc/Unsafe", "registerNatives") => {
             println!("stub: native Unsafe.registerNatives")
         }
@@ -37,11 +41,13 @@ pub fn run_native(thread: &mut Thread, class: String, method: String) {
         }
 
         ("java/lang/System", "arraycopy") => system::arraycopy(thread),
+        ("java/lang/System", "identityHashCode") => system::identity_hash_code(thread),
         ("java/lang/System", "setIn0") => system::set_in(thread),
         ("java/lang/System", "setOut0") => system::set_out(thread),
         ("java/lang/System", "setErr0") => system::set_err(thread),
         ("java/lang/Runtime", "availableProcessors") => runtime::available_processors(thread),
         ("java/lang/Runtime", "maxMemory") => runtime::max_memory(thread),
+        ("java/lang/Runtime", "gc") => runtime::gc(thread),
         ("java/lang/Object", "getClass") => object::get_class(thread),
         ("java/lang/Object", "hashCode") => object::hash_code(thread),
         ("java/lang/Object", "clone") => object::clone(thread),
@@ -49,6 +55,13 @@ pub fn run_native(thread: &mut Thread, class: String, method: String) {
         ("java/lang/Class", "getPrimitiveClass") => class::get_primitive_class(thread),
         ("java/lang/Class", "isPrimitive") => class::is_primitive(thread),
         ("java/lang/Class", "initClassName") => class::init_class_name(thread),
+        ("java/lang/Class", "isArray") => class::is_array(thread),
+        ("java/lang/Class", "forName0") => class::for_name(thread),
+        ("java/lang/ClassLoader", "defineClass1") => class_loader::define_class1(thread),
+        ("java/lang/ClassLoader", "findLoadedClass0") => class_loader::find_loaded_class0(thread),
+        ("java/lang/ClassLoader", "findBootstrapClass") => {
+            class_loader::find_bootstrap_class(thread)
+        }
         ("java/lang/StringUTF16", "isBigEndian") => string::is_big_endian(thread),
         ("java/lang/Float", "intBitsToFloat") => float::int_bits_to_float(thread),
         ("java/lang/Float", "floatToRawIntBits") => float::float_to_int_bits(thread),
@@ -1,6 +1,9 @@
 use crate::class::Class;
+use crate::class_loader::{self, method_area, ClassLoader};
+use crate::error::JavaError;
 use crate::heap::heap;
 use crate::jvm::Thread;
+use crate::symbol::Symbol;
 use crate::value::Value;
 
 pub fn get_class(thread: &mut Thread) {
@@ -14,19 +17,27 @@ pub fn get_class(thread: &mut Thread) {
 }
 
 pub fn hash_code(thread: &mut Thread) {
-    let Some(_obj) = thread.pop().object() else {
+    let Some(obj) = thread.pop().object() else {
         panic!("NullPointerException");
     };
-    // lmaoxd
-    // FIXME: According to the spec, this is technically valid,
-    // but will kill the performance of hashtables.
-    thread.operand_stack.push(Value::Int(69));
+    let hash = heap().identity_hash(obj);
+    thread.operand_stack.push(Value::Int(hash));
 }
 
 pub fn clone(thread: &mut Thread) {
     let Some(obj) = thread.pop().object() else {
         panic!("NullPointerException");
     };
+    let class = heap().get_obj_class(obj);
+    let cloneable = class_loader::resolve_class(
+        ClassLoader::Bootstrap,
+        Symbol::intern("java/lang/Cloneable"),
+    )
+    .unwrap();
+    if !Class::instance_of(class, cloneable) {
+        let name = method_area().classes[class].name.as_str().replace('/', ".");
+        JavaError::new("java/lang/CloneNotSupportedException", name).throw();
+    }
     let new_obj = heap().clone_object(obj);
     thread.operand_stack.push(Value::Object(Some(new_obj)));
 }
@@ -1,3 +1,4 @@
+use crate::class_loader::method_area;
 use crate::jvm::Thread;
 use crate::value::Value;
 
@@ -9,3 +10,11 @@ pub fn available_processors(thread: &mut Thread) {
 pub fn max_memory(thread: &mut Thread) {
     thread.operand_stack.push(Value::Long(i64::MAX));
 }
+
+/// There is no garbage collector, but this is when the classes of unreachable loaders are
+/// unloaded. Java threads can't be started yet, so this thread's stack is the only one.
+pub fn gc(thread: &mut Thread) {
+    let _runtime = thread.pop();
+    let roots = thread.roots();
+    method_area().unload_unreachable_loaders(roots);
+}
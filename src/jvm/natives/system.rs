@@ -2,6 +2,7 @@ use crate::class::FieldBacking;
 use crate::class_loader::method_area;
 use crate::heap::heap;
 use crate::jvm::Thread;
+use crate::symbol::Symbol;
 use crate::value::Value;
 
 pub fn arraycopy(thread: &mut Thread) {
@@ -18,11 +19,21 @@ pub fn arraycopy(thread: &mut Thread) {
     heap().array_copy(src, src_pos, dest, dest_pos, length);
 }
 
-fn set_static(thread: &mut Thread, name: &str) {
+pub fn identity_hash_code(thread: &mut Thread) {
+    let hash = match thread.pop().object() {
+        Some(obj) => heap().identity_hash(obj),
+        None => 0,
+    };
+    thread.operand_stack.push(Value::Int(hash));
+}
+
+fn set_static(thread: &mut Thread, name: &str, descriptor: &str) {
     let val = thread.pop().object();
     let class_id = thread.class_id();
     let mut ma = method_area();
-    let field_id = ma.resolve_field(class_id, name);
+    let field_id = ma
+        .resolve_field(class_id, Symbol::intern(name), Symbol::intern(descriptor))
+        .unwrap();
     let field = match &mut ma.fields[field_id].backing {
         FieldBacking::StaticValue(val) => val,
         _ => panic!("System.{} is not static?", name),
@@ -31,13 +42,13 @@ fn set_static(thread: &mut Thread, name: &str) {
 }
 
 pub fn set_in(thread: &mut Thread) {
-    set_static(thread, "in");
+    set_static(thread, "in", "Ljava/io/InputStream;");
 }
 
 pub fn set_out(thread: &mut Thread) {
-    set_static(thread, "out");
+    set_static(thread, "out", "Ljava/io/PrintStream;");
 }
 
 pub fn set_err(thread: &mut Thread) {
-    set_static(thread, "err");
+    set_static(thread, "err", "Ljava/io/PrintStream;");
 }
@@ -1,6 +1,6 @@
 use crate::class_file::descriptors::{FieldType, ObjectType};
 use crate::class_loader::method_area;
-use crate::heap::{heap, ArrayRef};
+use crate::heap::{heap, ArrayRef, Heap};
 use crate::jvm::Thread;
 use crate::value::Value;
 
@@ -51,8 +51,8 @@ pub fn vm_properties(thread: &mut Thread) {
         .map(|prop| heap().create_string(&mut method_area(), &prop))
         .collect();
 
-    let mut heap = heap();
-    let arr = heap.new_array(
+    let heap = heap();
+    let arr = Heap::new_array(
         &mut method_area(),
         FieldType::ObjectType(ObjectType {
             class_name: "java/lang/String".to_string(),
@@ -67,7 +67,7 @@ pub fn vm_properties(thread: &mut Thread) {
 }
 
 pub fn platform_properties(thread: &mut Thread) {
-    let arr = heap().new_array(
+    let arr = Heap::new_array(
         &mut method_area(),
         FieldType::ObjectType(ObjectType {
             class_name: "java/lang/String".to_string(),
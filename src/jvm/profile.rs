@@ -0,0 +1,136 @@
+//! Interpreter profiling
+//!
+//! Counts method invocations, taken back-edges, and receiver types at virtual call sites. Call
+//! sites and branches are keyed by the method and the bytecode offset of the instruction.
+
+use crate::class_loader::{ClassId, MethodArea, MethodId};
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{LazyLock, Mutex, MutexGuard, PoisonError};
+
+static PROFILE: LazyLock<Mutex<Profile>> = LazyLock::new(Default::default);
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Retrieve the profile by locking the mutex
+pub fn profile() -> MutexGuard<'static, Profile> {
+    PROFILE.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Start recording, as requested with `-Xprof`. The interpreter doesn't touch the profile
+/// otherwise.
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// A bytecode location, given as the method and the offset of the instruction within its code
+pub type BytecodeLocation = (MethodId, usize);
+
+#[derive(Default, Debug)]
+pub struct Profile {
+    invocations: HashMap<MethodId, u64>,
+    /// Keyed by the location of the branch instruction
+    back_edges: HashMap<BytecodeLocation, u64>,
+    /// Receiver classes seen at invokevirtual and invokeinterface call sites
+    receiver_types: HashMap<BytecodeLocation, HashMap<ClassId, u64>>,
+}
+
+impl Profile {
+    pub fn record_invocation(&mut self, method: MethodId) {
+        *self.invocations.entry(method).or_default() += 1;
+    }
+
+    pub fn record_back_edge(&mut self, method: MethodId, pc: usize) {
+        *self.back_edges.entry((method, pc)).or_default() += 1;
+    }
+
+    pub fn record_receiver(&mut self, method: MethodId, pc: usize, receiver: ClassId) {
+        *self
+            .receiver_types
+            .entry((method, pc))
+            .or_default()
+            .entry(receiver)
+            .or_default() += 1;
+    }
+
+    /// Drop everything recorded about unloaded methods and receiver classes, whose ids will be
+    /// reused
+    pub fn forget(&mut self, methods: &HashSet<MethodId>, classes: &HashSet<ClassId>) {
+        self.invocations
+            .retain(|method, _| !methods.contains(method));
+        self.back_edges
+            .retain(|(method, _), _| !methods.contains(method));
+        self.receiver_types
+            .retain(|(method, _), _| !methods.contains(method));
+        for types in self.receiver_types.values_mut() {
+            types.retain(|class, _| !classes.contains(class));
+        }
+    }
+
+    // Not used by the interpreter itself yet, these are for consumers of the profile
+    #[allow(dead_code)]
+    pub fn invocation_count(&self, method: MethodId) -> u64 {
+        self.invocations.get(&method).copied().unwrap_or(0)
+    }
+
+    #[allow(dead_code)]
+    pub fn back_edge_count(&self, method: MethodId, pc: usize) -> u64 {
+        self.back_edges.get(&(method, pc)).copied().unwrap_or(0)
+    }
+
+    /// The receiver classes seen at a call site, most frequent first
+    pub fn receiver_types(&self, method: MethodId, pc: usize) -> Vec<(ClassId, u64)> {
+        let mut types: Vec<_> = self
+            .receiver_types
+            .get(&(method, pc))
+            .map(|types| types.iter().map(|(&c, &n)| (c, n)).collect())
+            .unwrap_or_default();
+        types.sort_by_key(|&(_, count)| Reverse(count));
+        types
+    }
+
+    /// The `n` most invoked methods, most invoked first
+    pub fn hottest_methods(&self, n: usize) -> Vec<(MethodId, u64)> {
+        let mut methods: Vec<_> = self.invocations.iter().map(|(&m, &c)| (m, c)).collect();
+        methods.sort_by_key(|&(_, count)| Reverse(count));
+        methods.truncate(n);
+        methods
+    }
+
+    /// The `n` most taken back-edges, most taken first
+    pub fn hottest_loops(&self, n: usize) -> Vec<(BytecodeLocation, u64)> {
+        let mut loops: Vec<_> = self.back_edges.iter().map(|(&l, &c)| (l, c)).collect();
+        loops.sort_by_key(|&(_, count)| Reverse(count));
+        loops.truncate(n);
+        loops
+    }
+
+    pub fn print_report(&self, ma: &MethodArea, n: usize) {
+        let method_name = |method: MethodId| {
+            let method = &ma.methods[method];
+            format!("{}.{}", ma.classes[method.defining_class].name, method.name)
+        };
+
+        println!("hottest methods:");
+        for (method, count) in self.hottest_methods(n) {
+            println!("  {:>10}  {}", count, method_name(method));
+        }
+
+        println!("hottest loops:");
+        for ((method, pc), count) in self.hottest_loops(n) {
+            println!("  {:>10}  {} @ {}", count, method_name(method), pc);
+        }
+
+        println!("polymorphic call sites:");
+        for &(method, pc) in self.receiver_types.keys() {
+            let types = self.receiver_types(method, pc);
+            if types.len() > 1 {
+                println!("  {:>10}  {} @ {}", types.len(), method_name(method), pc);
+            }
+        }
+    }
+}
@@ -1,51 +1,257 @@
 #![feature(lazy_cell, alloc_layout_extra)]
 
-use crate::class_file::descriptors::MethodDescriptor;
-use crate::class_loader::method_area;
+use crate::class_loader::{method_area, preload_classes, resolve_class, ClassLoader};
+use crate::class_path::{class_path_mut, set_class_path, CompositeSource, JarSource};
+use crate::error::JavaError;
+use crate::jvm::profile::{self, profile};
 use crate::jvm::Thread;
+use crate::symbol::Symbol;
+use std::fs;
+use std::panic;
+use std::path::PathBuf;
 
+mod cds;
 mod class;
 mod class_file;
 mod class_loader;
+mod class_path;
+mod error;
 mod heap;
 mod jvm;
+mod runtime_constant_pool;
+mod symbol;
 mod value;
 
 struct Config {
-    classpath: &'static [&'static str],
-    main_class: &'static str,
+    /// Searched after the class path, for the standard library. This can be an extracted
+    /// `java.base` module or a JDK's `lib/modules` image.
+    boot_class_path: &'static str,
+    /// Used when neither `-cp` nor `CLASSPATH` is given
+    default_class_path: &'static str,
+    default_main_class: &'static str,
+    /// Where CDS archives are dumped to and mapped from without `-XX:SharedArchiveFile`
+    default_archive_file: &'static str,
+    /// Replace selected JDK methods with Rust implementations
+    use_intrinsics: bool,
+    /// Store references in objects and arrays as 32-bit offsets into a single reserved heap region
+    compressed_refs: bool,
 }
 
 static CONFIG: Config = Config {
-    classpath: &["./test", "./modules/java.base"],
-    main_class: "Test",
+    boot_class_path: "./modules/java.base",
+    default_class_path: "./test",
+    default_main_class: "Test",
+    default_archive_file: "./leprd.jsa",
+    use_intrinsics: true,
+    compressed_refs: true,
 };
 
 fn initialize_class(thread: &mut Thread, name: &str) {
-    let class = method_area().resolve_class(name);
+    let class = resolve_class(ClassLoader::Bootstrap, Symbol::intern(name))
+        .unwrap_or_else(|err| err.throw());
     thread.ensure_initialized(class);
 }
 
+fn usage() -> ! {
+    eprintln!("Usage: leprd [-cp <class path>] [-Xbootclasspath:<path>] [main class]");
+    eprintln!("       leprd -jar <jar file>");
+    eprintln!("Options:");
+    eprintln!("    -Xshare:{{auto|on|off|dump}}       use or create a class data sharing archive");
+    eprintln!("    -XX:SharedArchiveFile=<file>     the archive to use");
+    eprintln!("    -XX:SharedClassListFile=<file>   classes to archive, one per line");
+    eprintln!("    -Xprof                           print the hottest methods and loops on exit");
+    eprintln!("    -verbose:class                   print where each class is loaded from");
+    std::process::exit(1);
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum ShareMode {
+    Off,
+    /// Map the archive if it exists and matches the class path
+    Auto,
+    /// Fail if the archive can't be mapped
+    On,
+    Dump,
+}
+
+struct Args {
+    main_class: String,
+    share_mode: ShareMode,
+    archive_file: PathBuf,
+    class_list_file: Option<PathBuf>,
+}
+
+/// Set up the class path from the command line or `CLASSPATH`, and parse the other options
+fn parse_args() -> Args {
+    let mut share_mode = ShareMode::Auto;
+    let mut archive_file = PathBuf::from(CONFIG.default_archive_file);
+    let mut class_list_file = None;
+    let mut user_class_path = None;
+    let mut boot_class_path = CONFIG.boot_class_path.to_string();
+    let mut main_class = None;
+    let mut jar = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-cp" | "-classpath" | "--class-path" => {
+                user_class_path = Some(args.next().unwrap_or_else(|| usage()));
+            }
+            _ if arg.starts_with("-Xbootclasspath:") => {
+                boot_class_path = arg["-Xbootclasspath:".len()..].to_string();
+            }
+            "-Xshare:off" => share_mode = ShareMode::Off,
+            "-Xshare:auto" => share_mode = ShareMode::Auto,
+            "-Xshare:on" => share_mode = ShareMode::On,
+            "-Xshare:dump" => share_mode = ShareMode::Dump,
+            _ if arg.starts_with("-XX:SharedArchiveFile=") => {
+                archive_file = PathBuf::from(&arg["-XX:SharedArchiveFile=".len()..]);
+            }
+            _ if arg.starts_with("-XX:SharedClassListFile=") => {
+                class_list_file = Some(PathBuf::from(&arg["-XX:SharedClassListFile=".len()..]));
+            }
+            "-Xprof" => profile::enable(),
+            "-verbose:class" => class_path::enable_verbose(),
+            "-jar" => jar = Some(args.next().unwrap_or_else(|| usage())),
+            _ if arg.starts_with('-') || main_class.is_some() || jar.is_some() => usage(),
+            _ => main_class = Some(arg.replace('.', "/")),
+        }
+    }
+
+    // With -jar, the JAR is the whole class path and names the main class in its manifest
+    if let Some(jar) = jar {
+        let jar_main_class = JarSource::open(&jar)
+            .unwrap_or_else(|err| {
+                eprintln!("Error: could not open {}: {}", jar, err);
+                std::process::exit(1);
+            })
+            .manifest()
+            .main_class
+            .clone()
+            .unwrap_or_else(|| {
+                eprintln!("no main manifest attribute, in {}", jar);
+                std::process::exit(1);
+            });
+        user_class_path = Some(jar);
+        main_class = Some(jar_main_class.replace('.', "/"));
+    }
+
+    let user_class_path = user_class_path
+        .or_else(|| std::env::var("CLASSPATH").ok())
+        .unwrap_or_else(|| CONFIG.default_class_path.to_string());
+    set_class_path(CompositeSource::parse(&user_class_path));
+    class_path_mut().push(CompositeSource::parse(&boot_class_path));
+
+    Args {
+        main_class: main_class.unwrap_or_else(|| CONFIG.default_main_class.to_string()),
+        share_mode,
+        archive_file,
+        class_list_file,
+    }
+}
+
+/// Dump a CDS archive and exit, or map one before any classes are loaded
+fn setup_sharing(args: &Args) {
+    match args.share_mode {
+        ShareMode::Off => {}
+        ShareMode::Dump => {
+            let class_list: Vec<String> = match &args.class_list_file {
+                Some(path) => fs::read_to_string(path)
+                    .unwrap_or_else(|err| {
+                        eprintln!("Error: could not read {}: {}", path.display(), err);
+                        std::process::exit(1);
+                    })
+                    .lines()
+                    .map(str::trim)
+                    .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                    .map(|line| line.replace('.', "/"))
+                    .collect(),
+                None => cds::DEFAULT_CLASS_LIST
+                    .iter()
+                    .map(|name| name.to_string())
+                    .collect(),
+            };
+            match cds::dump(&args.archive_file, &class_list) {
+                Ok(count) => {
+                    println!(
+                        "Dumped {} classes to {}",
+                        count,
+                        args.archive_file.display()
+                    );
+                    std::process::exit(0);
+                }
+                Err(err) => {
+                    eprintln!(
+                        "Error: could not dump {}: {}",
+                        args.archive_file.display(),
+                        err
+                    );
+                    std::process::exit(1);
+                }
+            }
+        }
+        ShareMode::Auto | ShareMode::On => {
+            if let Err(err) = cds::map(&args.archive_file) {
+                if args.share_mode == ShareMode::On {
+                    eprintln!(
+                        "An error has occurred while processing the shared archive file {}: {}",
+                        args.archive_file.display(),
+                        err
+                    );
+                    std::process::exit(1);
+                }
+            }
+        }
+    }
+}
+
 fn main() {
-    let mut ma = method_area();
-    let system_class = ma.resolve_class("java/lang/System");
-    let init_phase_1 =
-        ma.resolve_method(system_class, "initPhase1", &MethodDescriptor::read("()V"));
-    drop(ma);
+    JavaError::install_panic_hook();
+    let args = parse_args();
+    setup_sharing(&args);
+    let main_class = args.main_class;
+    let result = panic::catch_unwind(|| JavaError::catch(|| run(&main_class)));
+    // The profile is reported however the program ends, since a crash is when it's most wanted
+    if profile::is_enabled() {
+        // The method area is locked first, like the interpreter does when recording receivers
+        let ma = method_area();
+        profile().print_report(&ma, 10);
+    }
+    match result {
+        Ok(Ok(())) => {}
+        Ok(Err(err)) => {
+            eprintln!("Exception in thread \"main\" {}", err);
+            std::process::exit(1);
+        }
+        Err(payload) => panic::resume_unwind(payload),
+    }
+}
+
+fn run(main_class: &str) {
+    preload_classes().unwrap_or_else(|err| err.throw());
+    let system_class =
+        resolve_class(ClassLoader::Bootstrap, Symbol::intern("java/lang/System")).unwrap();
+    let init_phase_1 = method_area()
+        .resolve_method(
+            system_class,
+            Symbol::intern("initPhase1"),
+            Symbol::intern("()V"),
+        )
+        .unwrap();
     let mut thread = Thread::new(init_phase_1);
     initialize_class(&mut thread, "java/lang/System");
     initialize_class(&mut thread, "java/lang/ref/Finalizer");
     println!("running thread");
     thread.run();
 
-    initialize_class(&mut thread, &CONFIG.main_class);
-    let mut ma = method_area();
-    let class = ma.resolve_class(CONFIG.main_class);
-    let method = ma.resolve_method(
-        class,
-        "main",
-        &MethodDescriptor::read("([Ljava/lang/String;)V"),
-    );
-    drop(ma);
+    initialize_class(&mut thread, main_class);
+    let class = resolve_class(ClassLoader::Bootstrap, Symbol::intern(main_class))
+        .unwrap_or_else(|err| err.throw());
+    let method = method_area()
+        .resolve_method(
+            class,
+            Symbol::intern("main"),
+            Symbol::intern("([Ljava/lang/String;)V"),
+        )
+        .unwrap_or_else(|err| err.throw());
     thread.call_method(method);
 }
@@ -0,0 +1,613 @@
+//! The run-time constant pool (JVMS 5.1)
+//!
+//! Every entry of a class's constant pool is stored at its constant pool index along with its
+//! resolution state. Resolution happens at most once per entry. If it fails, the error is
+//! cached and thrown again on every later use of the entry (JVMS 5.4.3).
+
+use crate::cds::{ArchiveReader, ArchiveWriter};
+use crate::class::Class;
+use crate::class_file::constant_pool::{CPInfo, ConstantPool};
+use crate::class_file::descriptors::{
+    FieldDescriptor, FieldType, MethodDescriptor, ReturnDescriptor,
+};
+use crate::class_loader::{self, method_area, ClassId, ClassLoader, FieldId, MethodArea, MethodId};
+use crate::error::{JavaError, JavaResult};
+use crate::heap::{heap, Heap, ObjectRef, ValueKind};
+use crate::jvm::Thread;
+use crate::symbol::Symbol;
+use crate::value::Value;
+use std::alloc::Layout;
+use std::sync::OnceLock;
+use std::{io, iter};
+
+#[derive(Debug)]
+pub struct MemberRef<T> {
+    pub class_index: u16,
+    pub name: Symbol,
+    pub descriptor: Symbol,
+    resolved: OnceLock<JavaResult<T>>,
+}
+
+impl<T> MemberRef<T> {
+    fn symbols(&self) -> impl Iterator<Item = Symbol> {
+        [self.name, self.descriptor].into_iter()
+    }
+
+    fn write_archive(&self, w: &mut ArchiveWriter) {
+        w.u16(self.class_index);
+        w.symbol(self.name);
+        w.symbol(self.descriptor);
+    }
+
+    fn read_archive(r: &mut ArchiveReader) -> io::Result<MemberRef<T>> {
+        Ok(MemberRef {
+            class_index: r.u16()?,
+            name: r.symbol()?,
+            descriptor: r.symbol()?,
+            resolved: OnceLock::new(),
+        })
+    }
+
+    fn new(cp: &ConstantPool, class_index: u16, name_and_type_index: u16) -> MemberRef<T> {
+        let (name, descriptor) = cp.nat_symbols(name_and_type_index);
+        MemberRef {
+            class_index,
+            name,
+            descriptor,
+            resolved: OnceLock::new(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Entry {
+    /// Index 0, the second slot of longs and doubles, and Module and Package entries which are
+    /// only used by module-info classes
+    Unusable,
+    Utf8(Symbol),
+    Integer(i32),
+    Float(f32),
+    Long(i64),
+    Double(f64),
+    Class {
+        name: Symbol,
+        resolved: OnceLock<JavaResult<ClassId>>,
+        /// Cached by `new` once the class is initialized, so that allocation doesn't lock the
+        /// method area
+        instance_layout: OnceLock<Layout>,
+    },
+    String {
+        string: Symbol,
+        resolved: OnceLock<ObjectRef>,
+    },
+    Field(MemberRef<FieldId>),
+    Method(MemberRef<MethodId>),
+    InterfaceMethod(MemberRef<MethodId>),
+    NameAndType {
+        name: Symbol,
+        descriptor: Symbol,
+    },
+    MethodHandle {
+        reference_kind: u8,
+        reference_index: u16,
+        resolved: OnceLock<JavaResult<ObjectRef>>,
+    },
+    MethodType {
+        descriptor: Symbol,
+        resolved: OnceLock<JavaResult<ObjectRef>>,
+    },
+    /// Bootstrap methods aren't supported, so dynamically-computed constants and call sites are
+    /// kept only in symbolic form
+    Dynamic {
+        bootstrap_method_attr_index: u16,
+        name: Symbol,
+        descriptor: Symbol,
+    },
+    InvokeDynamic {
+        bootstrap_method_attr_index: u16,
+        name: Symbol,
+        descriptor: Symbol,
+    },
+}
+
+/// Returns the cached resolution result, or resolves and caches it. If two threads race to
+/// resolve the same entry, both use whichever result was stored first.
+fn resolve_with<T: Clone>(
+    cell: &OnceLock<JavaResult<T>>,
+    resolve: impl FnOnce() -> JavaResult<T>,
+) -> JavaResult<T> {
+    if let Some(res) = cell.get() {
+        return res.clone();
+    }
+    let _ = cell.set(resolve());
+    cell.get().unwrap().clone()
+}
+
+#[derive(Debug, Default)]
+pub struct RuntimeConstantPool {
+    /// Indexed by constant pool index
+    entries: Vec<Entry>,
+    /// The defining loader of the class, which initiates loading of referenced classes
+    loader: ClassLoader,
+}
+
+impl RuntimeConstantPool {
+    pub fn new(cp: &ConstantPool, loader: ClassLoader) -> RuntimeConstantPool {
+        let mut entries = vec![Entry::Unusable];
+        entries.extend(cp.table.iter().map(|info| match *info {
+            CPInfo::Utf8 { .. } | CPInfo::Unusable => Entry::Unusable,
+            CPInfo::Integer { val } => Entry::Integer(val),
+            CPInfo::Float { val } => Entry::Float(val),
+            CPInfo::Long { val } => Entry::Long(val),
+            CPInfo::Double { val } => Entry::Double(val),
+            CPInfo::Class { name_index } => Entry::Class {
+                name: cp.symbol(name_index),
+                resolved: OnceLock::new(),
+                instance_layout: OnceLock::new(),
+            },
+            CPInfo::String { string_index } => Entry::String {
+                string: cp.symbol(string_index),
+                resolved: OnceLock::new(),
+            },
+            CPInfo::Fieldref {
+                class_index,
+                name_and_type_index,
+            } => Entry::Field(MemberRef::new(cp, class_index, name_and_type_index)),
+            CPInfo::Methodref {
+                class_index,
+                name_and_type_index,
+            } => Entry::Method(MemberRef::new(cp, class_index, name_and_type_index)),
+            CPInfo::InterfaceMethodref {
+                class_index,
+                name_and_type_index,
+            } => Entry::InterfaceMethod(MemberRef::new(cp, class_index, name_and_type_index)),
+            CPInfo::NameAndType {
+                name_index,
+                descriptor_index,
+            } => Entry::NameAndType {
+                name: cp.symbol(name_index),
+                descriptor: cp.symbol(descriptor_index),
+            },
+            CPInfo::MethodHandle {
+                reference_kind,
+                reference_index,
+            } => Entry::MethodHandle {
+                reference_kind,
+                reference_index,
+                resolved: OnceLock::new(),
+            },
+            CPInfo::MethodType { descriptor_index } => Entry::MethodType {
+                descriptor: cp.symbol(descriptor_index),
+                resolved: OnceLock::new(),
+            },
+            CPInfo::Dynamic {
+                bootstrap_method_attr_index,
+                name_and_type_index,
+            } => {
+                let (name, descriptor) = cp.nat_symbols(name_and_type_index);
+                Entry::Dynamic {
+                    bootstrap_method_attr_index,
+                    name,
+                    descriptor,
+                }
+            }
+            CPInfo::InvokeDynamic {
+                bootstrap_method_attr_index,
+                name_and_type_index,
+            } => {
+                let (name, descriptor) = cp.nat_symbols(name_and_type_index);
+                Entry::InvokeDynamic {
+                    bootstrap_method_attr_index,
+                    name,
+                    descriptor,
+                }
+            }
+            CPInfo::Module { .. } | CPInfo::Package { .. } => Entry::Unusable,
+        }));
+        // Utf8 entries are filled in afterwards since they are already interned
+        for (idx, symbol) in cp.symbols.iter().enumerate() {
+            if let Some(symbol) = symbol {
+                entries[idx + 1] = Entry::Utf8(*symbol);
+            }
+        }
+
+        RuntimeConstantPool { entries, loader }
+    }
+
+    /// Write the entries for a CDS archive. Resolution results aren't archived.
+    pub fn write_archive(&self, w: &mut ArchiveWriter) {
+        w.u32(self.entries.len() as u32);
+        for entry in &self.entries {
+            match entry {
+                Entry::Unusable => w.u8(0),
+                Entry::Utf8(symbol) => {
+                    w.u8(1);
+                    w.symbol(*symbol);
+                }
+                Entry::Integer(val) => {
+                    w.u8(3);
+                    w.u32(*val as u32);
+                }
+                Entry::Float(val) => {
+                    w.u8(4);
+                    w.u32(val.to_bits());
+                }
+                Entry::Long(val) => {
+                    w.u8(5);
+                    w.u64(*val as u64);
+                }
+                Entry::Double(val) => {
+                    w.u8(6);
+                    w.u64(val.to_bits());
+                }
+                Entry::Class { name, .. } => {
+                    w.u8(7);
+                    w.symbol(*name);
+                }
+                Entry::String { string, .. } => {
+                    w.u8(8);
+                    w.symbol(*string);
+                }
+                Entry::Field(field_ref) => {
+                    w.u8(9);
+                    field_ref.write_archive(w);
+                }
+                Entry::Method(method_ref) => {
+                    w.u8(10);
+                    method_ref.write_archive(w);
+                }
+                Entry::InterfaceMethod(method_ref) => {
+                    w.u8(11);
+                    method_ref.write_archive(w);
+                }
+                Entry::NameAndType { name, descriptor } => {
+                    w.u8(12);
+                    w.symbol(*name);
+                    w.symbol(*descriptor);
+                }
+                Entry::MethodHandle {
+                    reference_kind,
+                    reference_index,
+                    ..
+                } => {
+                    w.u8(15);
+                    w.u8(*reference_kind);
+                    w.u16(*reference_index);
+                }
+                Entry::MethodType { descriptor, .. } => {
+                    w.u8(16);
+                    w.symbol(*descriptor);
+                }
+                Entry::Dynamic {
+                    bootstrap_method_attr_index,
+                    name,
+                    descriptor,
+                } => {
+                    w.u8(17);
+                    w.u16(*bootstrap_method_attr_index);
+                    w.symbol(*name);
+                    w.symbol(*descriptor);
+                }
+                Entry::InvokeDynamic {
+                    bootstrap_method_attr_index,
+                    name,
+                    descriptor,
+                } => {
+                    w.u8(18);
+                    w.u16(*bootstrap_method_attr_index);
+                    w.symbol(*name);
+                    w.symbol(*descriptor);
+                }
+            }
+        }
+    }
+
+    /// Read entries written by [`RuntimeConstantPool::write_archive`]. Each entry is tagged with
+    /// the constant pool tag it came from.
+    pub fn read_archive(r: &mut ArchiveReader, loader: ClassLoader) -> io::Result<Self> {
+        let entries = r.vec(|r| {
+            Ok(match r.u8()? {
+                0 => Entry::Unusable,
+                1 => Entry::Utf8(r.symbol()?),
+                3 => Entry::Integer(r.u32()? as i32),
+                4 => Entry::Float(f32::from_bits(r.u32()?)),
+                5 => Entry::Long(r.u64()? as i64),
+                6 => Entry::Double(f64::from_bits(r.u64()?)),
+                7 => Entry::Class {
+                    name: r.symbol()?,
+                    resolved: OnceLock::new(),
+                    instance_layout: OnceLock::new(),
+                },
+                8 => Entry::String {
+                    string: r.symbol()?,
+                    resolved: OnceLock::new(),
+                },
+                9 => Entry::Field(MemberRef::read_archive(r)?),
+                10 => Entry::Method(MemberRef::read_archive(r)?),
+                11 => Entry::InterfaceMethod(MemberRef::read_archive(r)?),
+                12 => Entry::NameAndType {
+                    name: r.symbol()?,
+                    descriptor: r.symbol()?,
+                },
+                15 => Entry::MethodHandle {
+                    reference_kind: r.u8()?,
+                    reference_index: r.u16()?,
+                    resolved: OnceLock::new(),
+                },
+                16 => Entry::MethodType {
+                    descriptor: r.symbol()?,
+                    resolved: OnceLock::new(),
+                },
+                17 => Entry::Dynamic {
+                    bootstrap_method_attr_index: r.u16()?,
+                    name: r.symbol()?,
+                    descriptor: r.symbol()?,
+                },
+                18 => Entry::InvokeDynamic {
+                    bootstrap_method_attr_index: r.u16()?,
+                    name: r.symbol()?,
+                    descriptor: r.symbol()?,
+                },
+                _ => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "invalid constant pool entry in archive",
+                    ))
+                }
+            })
+        })?;
+        Ok(RuntimeConstantPool { entries, loader })
+    }
+
+    pub fn entry(&self, idx: u16) -> &Entry {
+        &self.entries[idx as usize]
+    }
+
+    /// The objects that resolved entries refer to, which the constant pool keeps alive
+    pub fn resolved_objects(&self) -> impl Iterator<Item = ObjectRef> + '_ {
+        self.entries.iter().filter_map(|entry| match entry {
+            Entry::String { resolved, .. } => resolved.get().copied(),
+            Entry::MethodHandle { resolved, .. } | Entry::MethodType { resolved, .. } => {
+                resolved.get().and_then(|res| res.as_ref().ok()).copied()
+            }
+            _ => None,
+        })
+    }
+
+    /// The symbols the entries refer to, including the exception class names of failed
+    /// resolutions
+    pub fn symbols(&self) -> impl Iterator<Item = Symbol> + '_ {
+        fn error<T>(resolved: &OnceLock<JavaResult<T>>) -> Option<Symbol> {
+            resolved.get()?.as_ref().err().map(|err| err.class_name)
+        }
+        self.entries.iter().flat_map(|entry| match entry {
+            Entry::Utf8(symbol) | Entry::String { string: symbol, .. } => vec![*symbol],
+            Entry::Class { name, resolved, .. } => {
+                iter::once(*name).chain(error(resolved)).collect()
+            }
+            Entry::Field(member) => member.symbols().chain(error(&member.resolved)).collect(),
+            Entry::Method(member) | Entry::InterfaceMethod(member) => {
+                member.symbols().chain(error(&member.resolved)).collect()
+            }
+            Entry::NameAndType { name, descriptor }
+            | Entry::Dynamic {
+                name, descriptor, ..
+            }
+            | Entry::InvokeDynamic {
+                name, descriptor, ..
+            } => vec![*name, *descriptor],
+            Entry::MethodHandle { resolved, .. } => error(resolved).into_iter().collect(),
+            Entry::MethodType {
+                descriptor,
+                resolved,
+            } => iter::once(*descriptor).chain(error(resolved)).collect(),
+            _ => Vec::new(),
+        })
+    }
+
+    /// Resolve a Class entry (JVMS 5.4.3.1)
+    pub fn class(&self, idx: u16) -> JavaResult<ClassId> {
+        match self.entry(idx) {
+            Entry::Class { name, resolved, .. } => {
+                resolve_with(resolved, || class_loader::resolve_class(self.loader, *name))
+            }
+            _ => panic!("ClassFormatError"),
+        }
+    }
+
+    /// The instance layout of the class of a Class entry, which must already be initialized
+    pub fn instance_layout(&self, idx: u16) -> Layout {
+        match self.entry(idx) {
+            Entry::Class {
+                resolved,
+                instance_layout,
+                ..
+            } => *instance_layout.get_or_init(|| {
+                let class = *resolved.get().unwrap().as_ref().unwrap();
+                method_area().classes[class].instance_layout()
+            }),
+            _ => panic!("ClassFormatError"),
+        }
+    }
+
+    /// Resolve a Fieldref entry (JVMS 5.4.3.2)
+    pub fn field(&self, idx: u16) -> JavaResult<FieldId> {
+        match self.entry(idx) {
+            Entry::Field(field_ref) => resolve_with(&field_ref.resolved, || {
+                let class = self.class(field_ref.class_index)?;
+                let mut ma = method_area();
+                let field = ma.resolve_field(class, field_ref.name, field_ref.descriptor)?;
+                let declaring_loader = ma.classes[ma.fields[field].defining_class].defining_loader;
+                ma.add_descriptor_constraints(field_ref.descriptor, self.loader, declaring_loader)?;
+                Ok(field)
+            }),
+            _ => panic!("ClassFormatError"),
+        }
+    }
+
+    /// Resolve a Methodref or InterfaceMethodref entry (JVMS 5.4.3.3, 5.4.3.4)
+    pub fn method(&self, idx: u16) -> JavaResult<MethodId> {
+        match self.entry(idx) {
+            Entry::Method(method_ref) => resolve_with(&method_ref.resolved, || {
+                let class = self.class(method_ref.class_index)?;
+                let mut ma = method_area();
+                let method = ma.resolve_method(class, method_ref.name, method_ref.descriptor)?;
+                self.constrain_method(&mut ma, method)
+            }),
+            Entry::InterfaceMethod(method_ref) => resolve_with(&method_ref.resolved, || {
+                let class = self.class(method_ref.class_index)?;
+                let mut ma = method_area();
+                let method =
+                    ma.resolve_interface_method(class, method_ref.name, method_ref.descriptor)?;
+                self.constrain_method(&mut ma, method)
+            }),
+            _ => panic!("ClassFormatError"),
+        }
+    }
+
+    /// A method declared by a class from another loader must agree with this class on the types
+    /// in its descriptor
+    fn constrain_method(&self, ma: &mut MethodArea, method: MethodId) -> JavaResult<MethodId> {
+        let method_ref = &ma.methods[method];
+        let descriptor = method_ref.descriptor;
+        let declaring_loader = ma.classes[method_ref.defining_class].defining_loader;
+        ma.add_descriptor_constraints(descriptor, self.loader, declaring_loader)?;
+        Ok(method)
+    }
+
+    /// Resolve the class referenced by a Fieldref, Methodref, or InterfaceMethodref entry
+    pub fn member_class(&self, idx: u16) -> JavaResult<ClassId> {
+        match self.entry(idx) {
+            Entry::Field(field_ref) => self.class(field_ref.class_index),
+            Entry::Method(method_ref) | Entry::InterfaceMethod(method_ref) => {
+                self.class(method_ref.class_index)
+            }
+            _ => panic!("ClassFormatError"),
+        }
+    }
+
+    /// Resolve a MethodType entry to a `java.lang.invoke.MethodType` (JVMS 5.4.3.5)
+    pub fn method_type(&self, idx: u16) -> JavaResult<ObjectRef> {
+        match self.entry(idx) {
+            Entry::MethodType {
+                descriptor,
+                resolved,
+            } => resolve_with(resolved, || self.method_type_obj(*descriptor)),
+            _ => panic!("ClassFormatError"),
+        }
+    }
+
+    /// Resolve a MethodHandle entry to a `java.lang.invoke.MethodHandle` (JVMS 5.4.3.5). The
+    /// referenced field or method is resolved first, then the handle is created for `caller`.
+    pub fn method_handle(&self, idx: u16, caller: ClassId) -> JavaResult<ObjectRef> {
+        let Entry::MethodHandle {
+            reference_kind,
+            reference_index,
+            resolved,
+        } = self.entry(idx)
+        else {
+            panic!("ClassFormatError");
+        };
+        resolve_with(resolved, || {
+            let (class, name, type_obj) = match self.entry(*reference_index) {
+                Entry::Field(field_ref) => {
+                    self.field(*reference_index)?;
+                    let field_ty = FieldDescriptor::read(&field_ref.descriptor.as_str()).0;
+                    let type_class = self.type_class(&field_ty)?;
+                    let class = self.class(field_ref.class_index)?;
+                    (class, field_ref.name, Class::obj(type_class))
+                }
+                Entry::Method(method_ref) | Entry::InterfaceMethod(method_ref) => {
+                    self.method(*reference_index)?;
+                    let class = self.class(method_ref.class_index)?;
+                    let method_type = self.method_type_obj(method_ref.descriptor)?;
+                    (class, method_ref.name, method_type)
+                }
+                _ => panic!("ClassFormatError"),
+            };
+            let name_obj = heap().create_string(&mut method_area(), &name.as_str());
+            link_method_handle(
+                "linkMethodHandleConstant",
+                "(Ljava/lang/Class;ILjava/lang/Class;Ljava/lang/String;Ljava/lang/Object;)\
+                 Ljava/lang/invoke/MethodHandle;",
+                &[
+                    Value::Object(Some(Class::obj(caller))),
+                    Value::Int(*reference_kind as i32),
+                    Value::Object(Some(Class::obj(class))),
+                    Value::Object(Some(name_obj)),
+                    Value::Object(Some(type_obj)),
+                ],
+            )
+        })
+    }
+
+    /// Create a `MethodType` for a method descriptor, resolving the classes in it
+    fn method_type_obj(&self, descriptor: Symbol) -> JavaResult<ObjectRef> {
+        let MethodDescriptor(params, ret) = MethodDescriptor::read(&descriptor.as_str());
+        let rtype = match ret {
+            ReturnDescriptor::Void => Class::primitive("void"),
+            ReturnDescriptor::FieldType(ty) => self.type_class(&ty)?,
+        };
+        let ptypes = params
+            .iter()
+            .map(|param| self.type_class(&param.0))
+            .collect::<JavaResult<Vec<_>>>()?;
+
+        let class_class =
+            class_loader::resolve_class(ClassLoader::Bootstrap, Symbol::intern("java/lang/Class"))?;
+        let mut ma = method_area();
+        let arr_class = ma.array_class_of(class_class);
+        let ptypes_arr = Heap::new_array_of_class(arr_class, ValueKind::Object, ptypes.len());
+        drop(ma);
+        for (idx, ptype) in ptypes.into_iter().enumerate() {
+            let ptype_obj = Class::obj(ptype);
+            heap().store_arr_elem(ptypes_arr, idx, Value::Object(Some(ptype_obj)));
+        }
+        link_method_handle(
+            "findMethodHandleType",
+            "(Ljava/lang/Class;[Ljava/lang/Class;)Ljava/lang/invoke/MethodType;",
+            &[
+                Value::Object(Some(Class::obj(rtype))),
+                Value::Array(Some(ptypes_arr)),
+            ],
+        )
+    }
+
+    /// The class for a type in a descriptor, loaded by this class's loader
+    fn type_class(&self, ty: &FieldType) -> JavaResult<ClassId> {
+        match ty {
+            FieldType::BaseType(base_type) => Ok(Class::primitive(&base_type.to_string())),
+            FieldType::ObjectType(obj_type) => {
+                class_loader::resolve_class(self.loader, Symbol::intern(&obj_type.class_name))
+            }
+            FieldType::ArrayType(_) => {
+                class_loader::resolve_class(self.loader, Symbol::intern(&ty.descriptor()))
+            }
+        }
+    }
+
+    /// Resolve a String entry to a `java.lang.String` instance, which is created only once
+    pub fn string(&self, idx: u16) -> ObjectRef {
+        match self.entry(idx) {
+            Entry::String { string, resolved } => *resolved.get_or_init(|| {
+                let mut ma = method_area();
+                heap().create_string(&mut ma, &string.as_str())
+            }),
+            _ => panic!("ClassFormatError"),
+        }
+    }
+}
+
+/// Call a static method of `java.lang.invoke.MethodHandleNatives`, which creates method handles
+/// and method types on behalf of the VM
+fn link_method_handle(name: &str, descriptor: &str, args: &[Value]) -> JavaResult<ObjectRef> {
+    let natives = class_loader::resolve_class(
+        ClassLoader::Bootstrap,
+        Symbol::intern("java/lang/invoke/MethodHandleNatives"),
+    )?;
+    let method =
+        method_area().resolve_method(natives, Symbol::intern(name), Symbol::intern(descriptor))?;
+    JavaError::catch(|| Thread::invoke(method, args))?
+        .and_then(Value::object)
+        .ok_or_else(|| JavaError::new("java/lang/InternalError", format!("{} returned null", name)))
+}
@@ -0,0 +1,179 @@
+//! Interned strings for class, method, and field names and descriptors
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, LazyLock, PoisonError, RwLock};
+
+static SYMBOL_TABLE: LazyLock<RwLock<SymbolTable>> = LazyLock::new(|| {
+    let mut table = SymbolTable::default();
+    for str in WELL_KNOWN {
+        table.insert(str);
+    }
+    RwLock::new(table)
+});
+
+/// Interned when the table is created, in this order, so that hot paths can compare against
+/// these without touching the table
+const WELL_KNOWN: [&str; 4] = [
+    "<init>",
+    "<clinit>",
+    "java/lang/invoke/MethodHandle",
+    "java/lang/invoke/VarHandle",
+];
+
+/// Set in the handles of symbols that start with `[`, so array class names can be recognized
+/// without looking up the string
+const ARRAY_FLAG: u32 = 1 << 31;
+
+#[derive(Default)]
+struct SymbolTable {
+    /// Indexed by symbol, with `None` in the slots of reclaimed symbols
+    strings: Vec<Option<Arc<str>>>,
+    symbols: HashMap<Arc<str>, Symbol>,
+    /// Slots of reclaimed symbols, reused before the table grows
+    free: Vec<u32>,
+}
+
+impl SymbolTable {
+    fn insert(&mut self, str: &str) -> Symbol {
+        let index = self.free.pop().unwrap_or(self.strings.len() as u32);
+        let symbol = Symbol(if str.starts_with('[') {
+            index | ARRAY_FLAG
+        } else {
+            index
+        });
+        let str: Arc<str> = Arc::from(str);
+        match self.strings.get_mut(index as usize) {
+            Some(slot) => *slot = Some(str.clone()),
+            None => self.strings.push(Some(str.clone())),
+        }
+        self.symbols.insert(str, symbol);
+        symbol
+    }
+}
+
+/// A handle to an interned string. Two symbols are equal if and only if their strings are equal.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Symbol(u32);
+
+impl Symbol {
+    pub const INIT: Symbol = Symbol(0);
+    pub const CLINIT: Symbol = Symbol(1);
+    pub const METHOD_HANDLE: Symbol = Symbol(2);
+    pub const VAR_HANDLE: Symbol = Symbol(3);
+
+    pub fn intern(str: &str) -> Symbol {
+        let table = SYMBOL_TABLE.read().unwrap_or_else(PoisonError::into_inner);
+        if let Some(&symbol) = table.symbols.get(str) {
+            return symbol;
+        }
+        drop(table);
+
+        let mut table = SYMBOL_TABLE.write().unwrap_or_else(PoisonError::into_inner);
+        // Another thread may have interned it in the meantime
+        match table.symbols.get(str) {
+            Some(&symbol) => symbol,
+            None => table.insert(str),
+        }
+    }
+
+    pub fn as_str(self) -> Arc<str> {
+        let table = SYMBOL_TABLE.read().unwrap_or_else(PoisonError::into_inner);
+        table.strings[self.index()]
+            .clone()
+            .expect("symbol was reclaimed")
+    }
+
+    /// The symbol for a string if it's interned, without interning it
+    #[cfg(test)]
+    pub fn lookup(str: &str) -> Option<Symbol> {
+        let table = SYMBOL_TABLE.read().unwrap_or_else(PoisonError::into_inner);
+        table.symbols.get(str).copied()
+    }
+
+    /// Free the strings of symbols that are no longer used, such as those of unloaded classes, so
+    /// their slots can be reused. Nothing may hold on to them afterwards. Well-known symbols are
+    /// never reclaimed.
+    pub fn reclaim(unused: impl IntoIterator<Item = Symbol>) {
+        let mut table = SYMBOL_TABLE.write().unwrap_or_else(PoisonError::into_inner);
+        for symbol in unused {
+            if symbol.index() < WELL_KNOWN.len() {
+                continue;
+            }
+            if let Some(str) = table.strings[symbol.index()].take() {
+                table.symbols.remove(&str);
+                table.free.push(symbol.index() as u32);
+            }
+        }
+    }
+
+    fn index(self) -> usize {
+        (self.0 & !ARRAY_FLAG) as usize
+    }
+
+    /// Whether this is the name of an array class, such as `[I`
+    pub fn is_array(self) -> bool {
+        self.0 & ARRAY_FLAG != 0
+    }
+
+    /// The package of a class with this binary name, e.g. `java/lang` for `java/lang/Object`
+    pub fn package(self) -> Symbol {
+        let name = self.as_str();
+        Symbol::intern(name.rsplit_once('/').map_or("", |(package, _)| package))
+    }
+}
+
+impl PartialEq<str> for Symbol {
+    fn eq(&self, other: &str) -> bool {
+        &*self.as_str() == other
+    }
+}
+
+impl PartialEq<&str> for Symbol {
+    fn eq(&self, other: &&str) -> bool {
+        self == *other
+    }
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.as_str())
+    }
+}
+
+impl fmt::Debug for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", &*self.as_str())
+    }
+}
+
+#[test]
+fn symbol_test() {
+    let a = Symbol::intern("java/lang/Object");
+    let b = Symbol::intern("java/lang/String");
+    assert_ne!(a, b);
+    assert_eq!(a, Symbol::intern("java/lang/Object"));
+    assert_eq!(a, "java/lang/Object");
+    assert_eq!(b.to_string(), "java/lang/String");
+    assert_eq!(Symbol::intern("<clinit>"), Symbol::CLINIT);
+    assert_eq!(
+        Symbol::intern("java/lang/invoke/VarHandle"),
+        Symbol::VAR_HANDLE
+    );
+    assert!(Symbol::intern("[Ljava/lang/Object;").is_array());
+    assert!(!a.is_array());
+    assert_eq!(a.package(), "java/lang");
+}
+
+#[test]
+fn reclaim_test() {
+    let symbol = Symbol::intern("test/Reclaimed");
+    Symbol::reclaim([symbol, Symbol::INIT]);
+    assert_eq!(Symbol::lookup("test/Reclaimed"), None);
+    assert_eq!(Symbol::intern("<init>"), Symbol::INIT);
+
+    let array = Symbol::intern("[Ltest/Reclaimed;");
+    assert!(array.is_array());
+    assert_eq!(array, "[Ltest/Reclaimed;");
+    assert_eq!(Symbol::intern("test/Reclaimed"), "test/Reclaimed");
+}
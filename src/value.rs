@@ -1,10 +1,12 @@
 use crate::class_file::descriptors::{BaseType, FieldType};
+use crate::error::{JavaError, JavaResult};
 use crate::heap::{ArrayRef, ObjectRef};
+use std::cmp::Ordering;
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Value {
     Byte(i8),
-    Char(u8),
+    Char(u16),
     Double(f64),
     Float(f32),
     Int(i32),
@@ -15,15 +17,15 @@ pub enum Value {
     Array(Option<ArrayRef>),
 }
 
-macro_rules! impl_val_op_unary {
+macro_rules! impl_val_op_binary {
     ($trait:ty => fn $fn_name:ident: $($variant:ident),+) => {
         impl $trait for Value {
             type Output = Value;
 
-            fn $fn_name(self) -> Value {
-                match self {
+            fn $fn_name(self, rhs: Value) -> Value {
+                match (self, rhs) {
                     $(
-                        Value::$variant(val) => Value::$variant(val.$fn_name()),
+                        (Value::$variant(lhs), Value::$variant(rhs)) => Value::$variant(lhs.$fn_name(rhs)),
                     )+
                     _ => unreachable!(),
                 }
@@ -32,16 +34,19 @@ macro_rules! impl_val_op_unary {
     };
 }
 
-macro_rules! impl_val_op_binary {
-    ($trait:ty => fn $fn_name:ident: $($variant:ident),+) => {
+/// Integer arithmetic wraps on overflow, and floating point arithmetic is IEEE 754 with round to
+/// nearest, which is what Rust's float operators do (JVMS 2.8)
+macro_rules! impl_val_op_arithmetic {
+    ($trait:ty => fn $fn_name:ident, $wrapping_fn:ident) => {
         impl $trait for Value {
             type Output = Value;
 
             fn $fn_name(self, rhs: Value) -> Value {
                 match (self, rhs) {
-                    $(
-                        (Value::$variant(lhs), Value::$variant(rhs)) => Value::$variant(lhs.$fn_name(rhs)),
-                    )+
+                    (Value::Int(lhs), Value::Int(rhs)) => Value::Int(lhs.$wrapping_fn(rhs)),
+                    (Value::Long(lhs), Value::Long(rhs)) => Value::Long(lhs.$wrapping_fn(rhs)),
+                    (Value::Float(lhs), Value::Float(rhs)) => Value::Float(lhs.$fn_name(rhs)),
+                    (Value::Double(lhs), Value::Double(rhs)) => Value::Double(lhs.$fn_name(rhs)),
                     _ => unreachable!(),
                 }
             }
@@ -49,28 +54,29 @@ macro_rules! impl_val_op_binary {
     };
 }
 
-// :(
-impl std::ops::Mul for Value {
+impl std::ops::Neg for Value {
     type Output = Value;
 
-    fn mul(self, rhs: Value) -> Value {
-        match (self, rhs) {
-            (Value::Int(lhs), Value::Int(rhs)) => Value::Int(lhs.overflowing_mul(rhs).0),
-            (Value::Long(lhs), Value::Long(rhs)) => Value::Long(lhs.overflowing_mul(rhs).0),
-            (Value::Float(lhs), Value::Float(rhs)) => Value::Float(lhs * rhs),
-            (Value::Double(lhs), Value::Double(rhs)) => Value::Double(lhs * rhs),
+    fn neg(self) -> Value {
+        match self {
+            Value::Int(val) => Value::Int(val.wrapping_neg()),
+            Value::Long(val) => Value::Long(val.wrapping_neg()),
+            Value::Float(val) => Value::Float(-val),
+            Value::Double(val) => Value::Double(-val),
             _ => unreachable!(),
         }
     }
 }
 
+// Only the low 5 bits of the shift distance are used for ints, and the low 6 bits for longs, which
+// is exactly what the wrapping shifts do
 impl std::ops::Shr for Value {
     type Output = Value;
 
     fn shr(self, rhs: Value) -> Value {
         match (self, rhs) {
-            (Value::Int(lhs), Value::Int(rhs)) => Value::Int(lhs >> rhs),
-            (Value::Long(lhs), Value::Int(rhs)) => Value::Long(lhs >> rhs),
+            (Value::Int(lhs), Value::Int(rhs)) => Value::Int(lhs.wrapping_shr(rhs as u32)),
+            (Value::Long(lhs), Value::Int(rhs)) => Value::Long(lhs.wrapping_shr(rhs as u32)),
             _ => unreachable!(),
         }
     }
@@ -81,29 +87,102 @@ impl std::ops::Shl for Value {
 
     fn shl(self, rhs: Value) -> Value {
         match (self, rhs) {
-            (Value::Int(lhs), Value::Int(rhs)) => Value::Int(lhs << rhs),
-            (Value::Long(lhs), Value::Int(rhs)) => Value::Long(lhs << rhs),
+            (Value::Int(lhs), Value::Int(rhs)) => Value::Int(lhs.wrapping_shl(rhs as u32)),
+            (Value::Long(lhs), Value::Int(rhs)) => Value::Long(lhs.wrapping_shl(rhs as u32)),
             _ => unreachable!(),
         }
     }
 }
 
-impl_val_op_binary!(std::ops::Add => fn add: Int, Long, Float, Double);
-impl_val_op_binary!(std::ops::Sub => fn sub: Int, Long, Float, Double);
-impl_val_op_binary!(std::ops::Div => fn div: Int, Long, Float, Double);
-impl_val_op_binary!(std::ops::Rem => fn rem: Int, Long, Float, Double);
+impl_val_op_arithmetic!(std::ops::Add => fn add, wrapping_add);
+impl_val_op_arithmetic!(std::ops::Sub => fn sub, wrapping_sub);
+impl_val_op_arithmetic!(std::ops::Mul => fn mul, wrapping_mul);
 impl_val_op_binary!(std::ops::BitAnd => fn bitand: Int, Long);
 impl_val_op_binary!(std::ops::BitOr => fn bitor: Int, Long);
 impl_val_op_binary!(std::ops::BitXor => fn bitxor: Int, Long);
-impl_val_op_unary!(std::ops::Neg => fn neg: Int, Long, Float, Double);
+
+fn division_by_zero() -> JavaError {
+    JavaError::new("java/lang/ArithmeticException", "/ by zero")
+}
 
 impl Value {
     pub fn ushr(self, rhs: Value) -> Value {
         match (self, rhs) {
             (Value::Int(lhs), Value::Int(rhs)) => {
-                Value::Int((lhs as u32).overflowing_shr(rhs as u32).0 as i32)
+                Value::Int((lhs as u32).wrapping_shr(rhs as u32) as i32)
+            }
+            (Value::Long(lhs), Value::Int(rhs)) => {
+                Value::Long((lhs as u64).wrapping_shr(rhs as u32) as i64)
             }
-            (Value::Long(lhs), Value::Int(rhs)) => Value::Long((lhs as u64 >> rhs) as i64),
+            _ => unreachable!(),
+        }
+    }
+
+    /// Integer division rounds towards zero. The only overflow, `MIN / -1`, results in `MIN`.
+    pub fn div(self, rhs: Value) -> JavaResult<Value> {
+        Ok(match (self, rhs) {
+            (Value::Int(_), Value::Int(0)) | (Value::Long(_), Value::Long(0)) => {
+                return Err(division_by_zero())
+            }
+            (Value::Int(lhs), Value::Int(rhs)) => Value::Int(lhs.wrapping_div(rhs)),
+            (Value::Long(lhs), Value::Long(rhs)) => Value::Long(lhs.wrapping_div(rhs)),
+            (Value::Float(lhs), Value::Float(rhs)) => Value::Float(lhs / rhs),
+            (Value::Double(lhs), Value::Double(rhs)) => Value::Double(lhs / rhs),
+            _ => unreachable!(),
+        })
+    }
+
+    /// The result has the sign of the dividend, for floats too. This is not the IEEE 754 remainder.
+    pub fn rem(self, rhs: Value) -> JavaResult<Value> {
+        Ok(match (self, rhs) {
+            (Value::Int(_), Value::Int(0)) | (Value::Long(_), Value::Long(0)) => {
+                return Err(division_by_zero())
+            }
+            (Value::Int(lhs), Value::Int(rhs)) => Value::Int(lhs.wrapping_rem(rhs)),
+            (Value::Long(lhs), Value::Long(rhs)) => Value::Long(lhs.wrapping_rem(rhs)),
+            (Value::Float(lhs), Value::Float(rhs)) => Value::Float(lhs % rhs),
+            (Value::Double(lhs), Value::Double(rhs)) => Value::Double(lhs % rhs),
+            _ => unreachable!(),
+        })
+    }
+
+    /// For lcmp, fcmp<op>, and dcmp<op>. Returns 1, 0, or -1, or `nan_result` if either value is
+    /// NaN. Positive and negative zero are equal.
+    pub fn compare(self, rhs: Value, nan_result: i32) -> i32 {
+        let ordering = match (self, rhs) {
+            (Value::Long(lhs), Value::Long(rhs)) => Some(lhs.cmp(&rhs)),
+            (Value::Float(lhs), Value::Float(rhs)) => lhs.partial_cmp(&rhs),
+            (Value::Double(lhs), Value::Double(rhs)) => lhs.partial_cmp(&rhs),
+            _ => unreachable!(),
+        };
+        match ordering {
+            Some(Ordering::Greater) => 1,
+            Some(Ordering::Equal) => 0,
+            Some(Ordering::Less) => -1,
+            None => nan_result,
+        }
+    }
+
+    /// The conversion instructions, i2l through i2s. Converting floating point values to integers
+    /// rounds towards zero, saturates, and turns NaN into 0, which is what `as` does. Other
+    /// conversions to floating point round to nearest.
+    pub fn convert(self, to: BaseType) -> Value {
+        match (self, to) {
+            (Value::Int(val), BaseType::J) => Value::Long(val as i64),
+            (Value::Int(val), BaseType::F) => Value::Float(val as f32),
+            (Value::Int(val), BaseType::D) => Value::Double(val as f64),
+            (Value::Int(val), BaseType::B) => Value::Int(val as i8 as i32),
+            (Value::Int(val), BaseType::C) => Value::Int(val as u16 as i32),
+            (Value::Int(val), BaseType::S) => Value::Int(val as i16 as i32),
+            (Value::Long(val), BaseType::I) => Value::Int(val as i32),
+            (Value::Long(val), BaseType::F) => Value::Float(val as f32),
+            (Value::Long(val), BaseType::D) => Value::Double(val as f64),
+            (Value::Float(val), BaseType::I) => Value::Int(val as i32),
+            (Value::Float(val), BaseType::J) => Value::Long(val as i64),
+            (Value::Float(val), BaseType::D) => Value::Double(val as f64),
+            (Value::Double(val), BaseType::I) => Value::Int(val as i32),
+            (Value::Double(val), BaseType::J) => Value::Long(val as i64),
+            (Value::Double(val), BaseType::F) => Value::Float(val as f32),
             _ => unreachable!(),
         }
     }
@@ -140,7 +219,7 @@ impl Value {
                 FieldType::BaseType(BaseType::B) => Value::Byte(int as i8),
                 FieldType::BaseType(BaseType::S) => Value::Short(int as i16),
                 FieldType::BaseType(BaseType::Z) => Value::Boolean((int & 0b1) == 1),
-                FieldType::BaseType(BaseType::C) => Value::Char(int as u8),
+                FieldType::BaseType(BaseType::C) => Value::Char(int as u16),
                 _ => unimplemented!(),
             }
         } else {
@@ -165,13 +244,11 @@ macro_rules! impl_matches_field_type {
 }
 
 impl_matches_field_type!(FieldType::BaseType(BaseType::B), i8);
-impl_matches_field_type!(FieldType::BaseType(BaseType::C), u8);
+impl_matches_field_type!(FieldType::BaseType(BaseType::C), u16);
 impl_matches_field_type!(FieldType::BaseType(BaseType::D), f64);
 impl_matches_field_type!(FieldType::BaseType(BaseType::F), f32);
 impl_matches_field_type!(FieldType::BaseType(BaseType::I), i32);
 impl_matches_field_type!(FieldType::BaseType(BaseType::J), i64);
-impl_matches_field_type!(FieldType::ObjectType(_), Option<ObjectRef>);
-impl_matches_field_type!(FieldType::ArrayType(_), Option<ArrayRef>);
 
 macro_rules! unwrap_val {
     ($value_type:ident, $expr:expr) => {
@@ -187,7 +264,7 @@ impl Value {
         unwrap_val!(Byte, self)
     }
 
-    pub fn char(self) -> u8 {
+    pub fn char(self) -> u16 {
         unwrap_val!(Char, self)
     }
 
@@ -230,3 +307,198 @@ impl Value {
         matches!(self, Value::Long(_) | Value::Double(_))
     }
 }
+
+#[test]
+fn int_arithmetic_test() {
+    use Value::{Int, Long};
+    assert_eq!(Int(i32::MAX) + Int(1), Int(i32::MIN));
+    assert_eq!(Int(i32::MIN) - Int(1), Int(i32::MAX));
+    assert_eq!(Int(i32::MAX) * Int(2), Int(-2));
+    assert_eq!(Int(i32::MIN) * Int(-1), Int(i32::MIN));
+    assert_eq!(-Int(i32::MIN), Int(i32::MIN));
+    assert_eq!(Long(i64::MAX) + Long(1), Long(i64::MIN));
+    assert_eq!(Long(i64::MIN) - Long(1), Long(i64::MAX));
+    assert_eq!(Long(i64::MIN) * Long(-1), Long(i64::MIN));
+    assert_eq!(-Long(i64::MIN), Long(i64::MIN));
+    assert_eq!(Int(-8) & Int(7), Int(0));
+    assert_eq!(Long(-1) ^ Long(1), Long(-2));
+}
+
+#[test]
+fn division_test() {
+    use Value::{Int, Long};
+    let div = |a: Value, b: Value| a.div(b).unwrap();
+    let rem = |a: Value, b: Value| a.rem(b).unwrap();
+
+    // Rounds towards zero, and the remainder takes the sign of the dividend
+    assert_eq!(div(Int(7), Int(2)), Int(3));
+    assert_eq!(div(Int(-7), Int(2)), Int(-3));
+    assert_eq!(div(Int(7), Int(-2)), Int(-3));
+    assert_eq!(rem(Int(7), Int(2)), Int(1));
+    assert_eq!(rem(Int(-7), Int(2)), Int(-1));
+    assert_eq!(rem(Int(7), Int(-2)), Int(1));
+    assert_eq!(div(Long(-7), Long(2)), Long(-3));
+    assert_eq!(rem(Long(-7), Long(2)), Long(-1));
+
+    assert_eq!(div(Int(i32::MIN), Int(-1)), Int(i32::MIN));
+    assert_eq!(rem(Int(i32::MIN), Int(-1)), Int(0));
+    assert_eq!(div(Long(i64::MIN), Long(-1)), Long(i64::MIN));
+    assert_eq!(rem(Long(i64::MIN), Long(-1)), Long(0));
+
+    for (a, b) in [(Int(1), Int(0)), (Int(0), Int(0)), (Long(1), Long(0))] {
+        let err = a.div(b).unwrap_err();
+        assert_eq!(err.class_name, "java/lang/ArithmeticException");
+        assert_eq!(err.message, "/ by zero");
+        assert!(a.rem(b).is_err());
+    }
+}
+
+#[test]
+fn float_division_test() {
+    use Value::{Double, Float};
+    let div = |a: Value, b: Value| a.div(b).unwrap();
+    let rem = |a: Value, b: Value| a.rem(b).unwrap();
+
+    assert_eq!(div(Float(1.0), Float(0.0)), Float(f32::INFINITY));
+    assert_eq!(div(Float(1.0), Float(-0.0)), Float(f32::NEG_INFINITY));
+    assert!(div(Float(0.0), Float(0.0)).float().is_nan());
+    assert_eq!(div(Double(-1.0), Double(0.0)), Double(f64::NEG_INFINITY));
+    assert!(div(Double(0.0), Double(0.0)).double().is_nan());
+
+    assert_eq!(rem(Float(5.5), Float(2.0)), Float(1.5));
+    assert_eq!(rem(Float(-5.5), Float(2.0)), Float(-1.5));
+    assert_eq!(rem(Double(5.5), Double(-2.0)), Double(1.5));
+    assert!(rem(Double(1.0), Double(0.0)).double().is_nan());
+    assert!(rem(Double(f64::INFINITY), Double(1.0)).double().is_nan());
+    assert_eq!(rem(Double(1.0), Double(f64::INFINITY)), Double(1.0));
+    assert!(rem(Float(-0.0), Float(1.0)).float().is_sign_negative());
+
+    assert!((-Double(0.0)).double().is_sign_negative());
+    assert!((-Float(f32::NAN)).float().is_nan());
+}
+
+#[test]
+fn shift_test() {
+    use Value::{Int, Long};
+    // Only the low 5 or 6 bits of the distance are used
+    assert_eq!(Int(1) << Int(32), Int(1));
+    assert_eq!(Int(1) << Int(33), Int(2));
+    assert_eq!(Int(1) << Int(-1), Int(i32::MIN));
+    assert_eq!(Long(1) << Int(64), Long(1));
+    assert_eq!(Long(1) << Int(63), Long(i64::MIN));
+    assert_eq!(Long(1) << Int(-1), Long(i64::MIN));
+
+    assert_eq!(Int(-16) >> Int(2), Int(-4));
+    assert_eq!(Int(-16) >> Int(34), Int(-4));
+    assert_eq!(Int(-1) >> Int(31), Int(-1));
+    assert_eq!(Long(-16) >> Int(66), Long(-4));
+
+    assert_eq!(Int(-1).ushr(Int(28)), Int(0xf));
+    assert_eq!(Int(-1).ushr(Int(32)), Int(-1));
+    assert_eq!(Int(-1).ushr(Int(60)), Int(0xf));
+    assert_eq!(Long(-1).ushr(Int(60)), Long(0xf));
+    assert_eq!(Long(-1).ushr(Int(64)), Long(-1));
+    assert_eq!(Long(-1).ushr(Int(124)), Long(0xf));
+}
+
+#[test]
+fn conversion_test() {
+    use Value::{Double, Float, Int, Long};
+
+    assert_eq!(Int(-1).convert(BaseType::J), Long(-1));
+    assert_eq!(Long(0x1_2345_6789).convert(BaseType::I), Int(0x2345_6789));
+    assert_eq!(Long(-1).convert(BaseType::I), Int(-1));
+    assert_eq!(Int(0x1ff).convert(BaseType::B), Int(-1));
+    assert_eq!(Int(0x7f).convert(BaseType::B), Int(0x7f));
+    assert_eq!(Int(-1).convert(BaseType::C), Int(0xffff));
+    assert_eq!(Int(0x1_0041).convert(BaseType::C), Int(0x41));
+    assert_eq!(Int(0x8000).convert(BaseType::S), Int(-0x8000));
+    assert_eq!(Int(0x1_7fff).convert(BaseType::S), Int(0x7fff));
+
+    // Rounds to nearest
+    assert_eq!(Int(16_777_217).convert(BaseType::F), Float(16_777_216.0));
+    assert_eq!(
+        Long(i64::MAX).convert(BaseType::D),
+        Double(9.223372036854776e18)
+    );
+    assert_eq!(Double(0.1).convert(BaseType::F), Float(0.1));
+    assert_eq!(Double(1e300).convert(BaseType::F), Float(f32::INFINITY));
+    assert!(Double(f64::NAN).convert(BaseType::F).float().is_nan());
+
+    // Rounds towards zero, saturates, and NaN becomes zero
+    for (val, to_int, to_long) in [
+        (2.9, 2, 2),
+        (-2.9, -2, -2),
+        (f64::NAN, 0, 0),
+        (f64::INFINITY, i32::MAX, i64::MAX),
+        (f64::NEG_INFINITY, i32::MIN, i64::MIN),
+        (1e10, i32::MAX, 10_000_000_000),
+        (-1e10, i32::MIN, -10_000_000_000),
+        (1e20, i32::MAX, i64::MAX),
+        (-1e20, i32::MIN, i64::MIN),
+    ] {
+        assert_eq!(Double(val).convert(BaseType::I), Int(to_int));
+        assert_eq!(Double(val).convert(BaseType::J), Long(to_long));
+        assert_eq!(Float(val as f32).convert(BaseType::I), Int(to_int));
+        assert_eq!(Float(val as f32).convert(BaseType::J), Long(to_long));
+    }
+    assert_eq!(
+        Float(f32::MAX).convert(BaseType::D),
+        Double(f32::MAX as f64)
+    );
+}
+
+#[test]
+fn comparison_test() {
+    use Value::{Double, Float, Long};
+
+    assert_eq!(Long(i64::MIN).compare(Long(i64::MAX), 0), -1);
+    assert_eq!(Long(5).compare(Long(5), 0), 0);
+    assert_eq!(Long(i64::MAX).compare(Long(i64::MIN), 0), 1);
+
+    assert_eq!(Float(1.0).compare(Float(2.0), 1), -1);
+    assert_eq!(Float(0.0).compare(Float(-0.0), 1), 0);
+    assert_eq!(Double(f64::INFINITY).compare(Double(f64::MAX), -1), 1);
+    assert_eq!(Double(f64::NEG_INFINITY).compare(Double(f64::MIN), -1), -1);
+
+    // fcmpl and dcmpl push -1 for NaN, fcmpg and dcmpg push 1
+    for nan_result in [-1, 1] {
+        assert_eq!(Float(f32::NAN).compare(Float(1.0), nan_result), nan_result);
+        assert_eq!(Float(1.0).compare(Float(f32::NAN), nan_result), nan_result);
+        assert_eq!(
+            Float(f32::NAN).compare(Float(f32::NAN), nan_result),
+            nan_result
+        );
+        assert_eq!(
+            Double(f64::NAN).compare(Double(0.0), nan_result),
+            nan_result
+        );
+        assert_eq!(
+            Double(0.0).compare(Double(f64::NAN), nan_result),
+            nan_result
+        );
+    }
+}
+
+#[test]
+fn store_ty_test() {
+    use Value::Int;
+    assert_eq!(
+        Int(0x1ff).store_ty(&FieldType::BaseType(BaseType::B)),
+        Value::Byte(-1)
+    );
+    assert_eq!(
+        Int(-1).store_ty(&FieldType::BaseType(BaseType::C)),
+        Value::Char(0xffff)
+    );
+    assert_eq!(
+        Int(0x1_8000).store_ty(&FieldType::BaseType(BaseType::S)),
+        Value::Short(i16::MIN)
+    );
+    assert_eq!(
+        Int(2).store_ty(&FieldType::BaseType(BaseType::Z)),
+        Value::Boolean(false)
+    );
+    assert_eq!(Value::Char(0xffff).extend_32(), Int(0xffff));
+    assert_eq!(Value::Short(-1).extend_32(), Int(-1));
+}